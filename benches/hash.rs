@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use ff::PrimeField;
 use generic_array::typenum;
-use neptune::poseidon::{HashMode, PoseidonConstants};
+use neptune::poseidon::{self, HashMode, PoseidonConstants};
 use neptune::*;
 use paired::bls12_381::{Bls12, Fr};
 use rand::rngs::OsRng;
@@ -128,11 +128,145 @@ where
     group.finish();
 }
 
+/// Compare hashing a layer with a fresh `Poseidon` for every hash and with one instance reset between hashes.
+fn bench_reuse<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let layer: Vec<Scalar> = (0..1024 * A::to_usize())
+        .map(|i| scalar_from_u64::<Fr>(i as u64))
+        .collect();
+    let constants = PoseidonConstants::<Bls12, A>::new();
+
+    let mut group = c.benchmark_group(format!("reuse-{}", A::to_usize()));
+
+    group.bench_with_input(
+        BenchmarkId::new("new", "1024 hashes"),
+        &layer,
+        |b, layer| {
+            b.iter(|| {
+                layer
+                    .chunks(A::to_usize())
+                    .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+                    .collect::<Vec<_>>()
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("reset", "1024 hashes"),
+        &layer,
+        |b, layer| {
+            let mut h = Poseidon::<Bls12, A>::new(&constants);
+            b.iter(|| {
+                layer
+                    .chunks(A::to_usize())
+                    .map(|preimage| {
+                        h.reset();
+                        for x in preimage {
+                            h.input(*x).unwrap();
+                        }
+                        h.hash()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        },
+    );
+
+    group.finish();
+}
+
+/// Compare hashing the chunks of a layer by copying each into a hasher with `set_preimage`, with reading each straight
+/// from the layer with `hash_slice`.
+fn bench_layer<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let layer: Vec<Scalar> = (0..1024 * A::to_usize())
+        .map(|i| scalar_from_u64::<Fr>(i as u64))
+        .collect();
+    let constants = PoseidonConstants::<Bls12, A>::new();
+
+    let mut group = c.benchmark_group(format!("layer-{}", A::to_usize()));
+
+    group.bench_with_input(
+        BenchmarkId::new("set_preimage", "1024 hashes"),
+        &layer,
+        |b, layer| {
+            let mut h = Poseidon::<Bls12, A>::new(&constants);
+            b.iter(|| {
+                layer
+                    .chunks(A::to_usize())
+                    .map(|preimage| {
+                        h.set_preimage(preimage);
+                        h.hash()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("hash_slice", "1024 hashes"),
+        &layer,
+        |b, layer| {
+            let h = Poseidon::<Bls12, A>::new(&constants);
+            b.iter(|| {
+                layer
+                    .chunks(A::to_usize())
+                    .map(|preimage| h.hash_slice(preimage))
+                    .collect::<Vec<_>>()
+            })
+        },
+    );
+
+    group.finish();
+}
+
+/// Compare the fixed-size `hash2` helper, which shares lazily built constants, with constructing a `Poseidon` from
+/// caller-held constants for each hash.
+fn bench_hash2(c: &mut Criterion) {
+    let pairs: Vec<(Scalar, Scalar)> = (0..1000)
+        .map(|i| (scalar_from_u64::<Fr>(i), scalar_from_u64::<Fr>(i + 1)))
+        .collect();
+    let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+
+    let mut group = c.benchmark_group("hash2");
+
+    group.bench_with_input(
+        BenchmarkId::new("hash2", "1000 pairs"),
+        &pairs,
+        |b, pairs| {
+            b.iter(|| {
+                pairs
+                    .iter()
+                    .map(|(l, r)| poseidon::hash2(*l, *r))
+                    .collect::<Vec<_>>()
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("Poseidon::new_with_preimage", "1000 pairs"),
+        &pairs,
+        |b, pairs| {
+            b.iter(|| {
+                pairs
+                    .iter()
+                    .map(|(l, r)| Poseidon::new_with_preimage(&[*l, *r], &constants).hash())
+                    .collect::<Vec<_>>()
+            })
+        },
+    );
+
+    group.finish();
+}
+
 criterion_group! {
     name = hash;
 
     config = Criterion::default();
 
-    targets = bench_hash::<typenum::U2>, bench_hash::<typenum::U4>, bench_hash::<typenum::U8>, bench_hash::<typenum::U11>
+    targets = bench_hash::<typenum::U2>, bench_hash::<typenum::U4>, bench_hash::<typenum::U8>, bench_hash::<typenum::U11>, bench_reuse::<typenum::U8>, bench_layer::<typenum::U8>, bench_hash2
 }
 criterion_main!(hash);
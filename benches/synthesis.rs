@@ -2,10 +2,12 @@ use crate::poseidon::{Arity, PoseidonConstants};
 use bellperson::gadgets::num::AllocatedNum;
 use bellperson::util_cs::bench_cs::BenchCS;
 use bellperson::{Circuit, ConstraintSystem, SynthesisError};
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
 use ff::Field;
 use generic_array::typenum;
-use neptune::circuit::poseidon_hash;
+use neptune::circuit::{poseidon_hash, poseidon_hash_batch, poseidon_hash_witness};
+use neptune::test_cs::TestConstraintSystem;
+use neptune::witness_cs::WitnessCS;
 use neptune::*;
 use paired::bls12_381::{Bls12, Fr};
 use rand::thread_rng;
@@ -17,15 +19,14 @@ struct BenchCircuit<A: Arity<Fr>> {
 }
 
 impl<A: Arity<Fr>> Circuit<Bls12> for BenchCircuit<A> {
-    fn synthesize<CS: ConstraintSystem<Bls12>>(
-        self,
-        mut cs: &mut CS,
-    ) -> Result<(), SynthesisError> {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
         let mut rng = thread_rng();
         let arity = A::to_usize();
         let constants = PoseidonConstants::<Bls12, A>::new();
 
-        for _ in 0..self.n {
+        for n in 0..self.n {
+            // Each hash gets its own namespace, so paths stay unique in constraint systems which record them.
+            let mut cs = cs.namespace(|| n.to_string());
             let mut i = 0;
             let mut fr_data = vec![Fr::random(&mut rng); arity];
             let data: Vec<AllocatedNum<Bls12>> = (0..arity)
@@ -37,7 +38,7 @@ impl<A: Arity<Fr>> Circuit<Bls12> for BenchCircuit<A> {
                     AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || Ok(fr)).unwrap()
                 })
                 .collect::<Vec<_>>();
-            let _ = poseidon_hash(&mut cs, data, &constants).expect("poseidon hashing failed");
+            let _ = poseidon_hash(&mut cs, &data, &constants).expect("poseidon hashing failed");
         }
         Ok(())
     }
@@ -73,11 +74,176 @@ where
     }
 }
 
+/// Compare hashing a layer of preimages one `poseidon_hash` call at a time with `poseidon_hash_batch`, in a
+/// constraint system which records namespaces.
+fn bench_batch_synthesis<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let mut group = c.benchmark_group(format!("batch-synthesis-{}", A::to_usize()));
+    let constants = PoseidonConstants::<Bls12, A>::new();
+    let num_hashes = 100;
+
+    let setup = || {
+        let mut rng = thread_rng();
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimages = (0..num_hashes)
+            .map(|i| {
+                (0..A::to_usize())
+                    .map(|j| {
+                        let fr = Fr::random(&mut rng);
+                        AllocatedNum::alloc(cs.namespace(|| format!("data {} {}", i, j)), || Ok(fr))
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        (cs, preimages)
+    };
+
+    group.bench_function(format!("loop, count: {}", num_hashes), |b| {
+        b.iter_batched(
+            setup,
+            |(mut cs, preimages)| {
+                for (i, preimage) in preimages.iter().enumerate() {
+                    poseidon_hash(
+                        cs.namespace(|| format!("poseidon hash {}", i)),
+                        preimage,
+                        &constants,
+                    )
+                    .expect("poseidon hashing failed");
+                }
+                cs
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function(format!("batch, count: {}", num_hashes), |b| {
+        b.iter_batched(
+            setup,
+            |(mut cs, preimages)| {
+                poseidon_hash_batch(cs.namespace(|| "batch"), &preimages, &constants)
+                    .expect("poseidon hashing failed");
+                cs
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Compare synthesizing many hashes into a constraint system which ignores namespaces with one which records every
+/// path, to show what naming costs.
+fn bench_namespace_overhead<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let mut group = c.benchmark_group(format!("namespace-overhead-{}", A::to_usize()));
+    let num_hashes = 100;
+
+    group.bench_function(format!("no-op, count: {}", num_hashes), |b| {
+        b.iter(|| {
+            let mut cs = BenchCS::<Bls12>::new();
+            BenchCircuit::<A> {
+                n: num_hashes,
+                _a: PhantomData::<A>,
+            }
+            .synthesize(&mut cs)
+        })
+    });
+
+    group.bench_function(format!("recording, count: {}", num_hashes), |b| {
+        b.iter(|| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            BenchCircuit::<A> {
+                n: num_hashes,
+                _a: PhantomData::<A>,
+            }
+            .synthesize(&mut cs)
+        })
+    });
+}
+
+/// Synthesize many hashes into a constraint system which records every path, to track what recording costs on large
+/// circuits.
+fn bench_test_cs_synthesis<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let mut group = c.benchmark_group(format!("test-cs-synthesis-{}", A::to_usize()));
+    let num_hashes = 500;
+
+    group.bench_function(format!("count: {}", num_hashes), |b| {
+        b.iter(|| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            BenchCircuit::<A> {
+                n: num_hashes,
+                _a: PhantomData::<A>,
+            }
+            .synthesize(&mut cs)
+        })
+    });
+}
+
+/// Compare proving-time synthesis of many hashes with the full gadget and with the witness-only fast path, both into a
+/// constraint system which only records the assignment.
+fn bench_witness_synthesis<A>(c: &mut Criterion)
+where
+    A: Arity<Fr>,
+{
+    let mut group = c.benchmark_group(format!("witness-synthesis-{}", A::to_usize()));
+    let constants = PoseidonConstants::<Bls12, A>::new();
+    let num_hashes = 1000;
+
+    let setup = || {
+        let mut rng = thread_rng();
+        let mut cs = WitnessCS::<Bls12>::new();
+        let preimages = (0..num_hashes)
+            .map(|_| {
+                (0..A::to_usize())
+                    .map(|_| {
+                        let fr = Fr::random(&mut rng);
+                        AllocatedNum::alloc(&mut cs, || Ok(fr)).unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        (cs, preimages)
+    };
+
+    group.bench_function(format!("gadget, count: {}", num_hashes), |b| {
+        b.iter_batched(
+            setup,
+            |(mut cs, preimages)| {
+                for preimage in preimages.iter() {
+                    poseidon_hash(&mut cs, preimage, &constants).expect("poseidon hashing failed");
+                }
+                cs
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function(format!("witness only, count: {}", num_hashes), |b| {
+        b.iter_batched(
+            setup,
+            |(mut cs, preimages)| {
+                for preimage in preimages.iter() {
+                    poseidon_hash_witness(&mut cs, preimage, &constants)
+                        .expect("poseidon hashing failed");
+                }
+                cs
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
 criterion_group! {
     name = synthesis;
 
     config = Criterion::default().sample_size(10);
 
-    targets = bench_synthesis::<typenum::U8>
+    targets = bench_synthesis::<typenum::U8>, bench_batch_synthesis::<typenum::U8>, bench_namespace_overhead::<typenum::U8>, bench_test_cs_synthesis::<typenum::U8>, bench_witness_synthesis::<typenum::U8>
 }
 criterion_main!(synthesis);
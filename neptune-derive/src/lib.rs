@@ -0,0 +1,163 @@
+//! `#[derive(ToFieldElements)]`, re-exported by `neptune` with its `derive` feature.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Field, Index, Lit, Meta, NestedMeta,
+    Path, Type,
+};
+
+/// Primitive types with no `ToFieldElements` encoding, rejected with a hint rather than an unsatisfied trait bound.
+const UNSUPPORTED: &[&str] = &[
+    "u8", "u16", "u32", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+    "char", "str", "String",
+];
+
+/// Encode the fields of a struct in declaration order. Fields may be marked `#[neptune(skip)]`, to leave them out, or
+/// `#[neptune(with = "path")]`, to append their encoding by calling `path(&field, out)`.
+#[proc_macro_derive(ToFieldElements, attributes(neptune))]
+pub fn derive_to_field_elements(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+enum Encoding {
+    Default,
+    Skip,
+    With(Path),
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "ToFieldElements can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut encode = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(#index)
+            }
+        };
+
+        match field_encoding(field)? {
+            Encoding::Skip => {}
+            Encoding::With(path) => {
+                encode.push(quote_spanned!(field.span()=> #path(&self.#member, out);));
+            }
+            Encoding::Default => {
+                check_supported(&field.ty)?;
+                encode.push(quote_spanned! {field.span()=>
+                    ::neptune::field_elements::ToFieldElements::to_field_elements(&self.#member, out);
+                });
+            }
+        }
+    }
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::neptune::field_elements::ToFieldElements));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let name = &input.ident;
+
+    Ok(quote! {
+        impl #impl_generics ::neptune::field_elements::ToFieldElements for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn to_field_elements(&self, out: &mut ::std::vec::Vec<::neptune::Scalar>) {
+                #(#encode)*
+            }
+        }
+    })
+}
+
+fn field_encoding(field: &Field) -> Result<Encoding, Error> {
+    let mut encoding = Encoding::Default;
+
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("neptune"))
+    {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(Error::new_spanned(
+                    meta,
+                    "expected `#[neptune(skip)]` or `#[neptune(with = \"path\")]`",
+                ))
+            }
+        };
+
+        for nested in list.nested.iter() {
+            let next = match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => Encoding::Skip,
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("with") => {
+                    match &pair.lit {
+                        Lit::Str(path) => Encoding::With(path.parse()?),
+                        lit => {
+                            return Err(Error::new_spanned(
+                                lit,
+                                "expected the path of a function, as a string",
+                            ))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Error::new_spanned(
+                        nested,
+                        "unknown neptune attribute, expected `skip` or `with = \"path\"`",
+                    ))
+                }
+            };
+
+            if let Encoding::Default = encoding {
+                encoding = next;
+            } else {
+                return Err(Error::new_spanned(
+                    nested,
+                    "a field takes at most one of `skip` and `with`",
+                ));
+            }
+        }
+    }
+
+    Ok(encoding)
+}
+
+fn check_supported(ty: &Type) -> Result<(), Error> {
+    let unsupported = match ty {
+        Type::Ptr(_) | Type::BareFn(_) => true,
+        Type::Path(path) if path.qself.is_none() => {
+            UNSUPPORTED.iter().any(|name| path.path.is_ident(name))
+        }
+        _ => false,
+    };
+
+    if unsupported {
+        Err(Error::new_spanned(
+            ty,
+            "field type has no field element encoding; encode it with `#[neptune(with = \"path\")]` or leave it out \
+             with `#[neptune(skip)]`",
+        ))
+    } else {
+        Ok(())
+    }
+}
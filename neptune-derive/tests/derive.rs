@@ -0,0 +1,51 @@
+use neptune::field_elements::{hash_object, to_field_elements, ToFieldElements};
+use neptune::{scalar_from_u64, Scalar};
+
+#[derive(ToFieldElements)]
+struct Account {
+    id: u64,
+    active: bool,
+    key: [u8; 32],
+    balances: Vec<u64>,
+    #[neptune(skip)]
+    _cache: Option<Scalar>,
+    #[neptune(with = "encode_u32")]
+    nonce: u32,
+}
+
+fn encode_u32(value: &u32, out: &mut Vec<Scalar>) {
+    out.push(scalar_from_u64(u64::from(*value)));
+}
+
+#[derive(ToFieldElements)]
+struct Pair<T>(T, Scalar);
+
+#[test]
+fn test_derived_encoding() {
+    let mut key = [0u8; 32];
+    key[31] = 0xff;
+    let account = Account {
+        id: 17,
+        active: true,
+        key,
+        balances: vec![5, 6, 7],
+        _cache: Some(scalar_from_u64(99)),
+        nonce: 3,
+    };
+
+    let manual = (17u64, true, key, vec![5u64, 6, 7], 3u64);
+    assert_eq!(to_field_elements(&manual), to_field_elements(&account));
+    assert_eq!(hash_object(&manual), hash_object(&account));
+
+    let pair = Pair(account, scalar_from_u64(1));
+    let mut expected = to_field_elements(&manual);
+    expected.push(scalar_from_u64(1));
+    assert_eq!(expected, to_field_elements(&pair));
+}
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/pass/*.rs");
+    t.compile_fail("tests/ui/fail/*.rs");
+}
@@ -0,0 +1,9 @@
+use neptune::field_elements::ToFieldElements;
+
+#[derive(ToFieldElements)]
+enum Record {
+    A(u64),
+    B(bool),
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use neptune::field_elements::ToFieldElements;
+use neptune::Scalar;
+
+fn encode(_: &u64, _: &mut Vec<Scalar>) {}
+
+#[derive(ToFieldElements)]
+struct Record {
+    #[neptune(skip, with = "encode")]
+    id: u64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use neptune::field_elements::ToFieldElements;
+
+#[derive(ToFieldElements)]
+struct Record {
+    #[neptune(rename = "identifier")]
+    id: u64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use neptune::field_elements::ToFieldElements;
+
+#[derive(ToFieldElements)]
+struct Record {
+    id: u64,
+    count: u32,
+}
+
+fn main() {}
@@ -0,0 +1,30 @@
+use neptune::field_elements::ToFieldElements;
+use neptune::Scalar;
+
+fn encode_len(value: &String, out: &mut Vec<Scalar>) {
+    (value.len() as u64).to_field_elements(out);
+}
+
+#[derive(ToFieldElements)]
+struct Note {
+    value: u64,
+    #[neptune(skip)]
+    memo: String,
+    #[neptune(with = "encode_len")]
+    owner: String,
+}
+
+#[derive(ToFieldElements)]
+struct Empty;
+
+fn main() {
+    let mut out = Vec::new();
+    Note {
+        value: 1,
+        memo: String::new(),
+        owner: "abc".to_string(),
+    }
+    .to_field_elements(&mut out);
+    Empty.to_field_elements(&mut out);
+    assert_eq!(2, out.len());
+}
@@ -0,0 +1,124 @@
+#!/usr/bin/env python3
+"""Compute the digests of `vectors::KNOWN_VECTORS` independently of the crate.
+
+A plain model of the Poseidon permutation, written separately from the crate, with the parameters neptune uses:
+
+- the BLS12-381 scalar field, and the x^5 S-box;
+- round constants from the Grain LFSR seeded with field 1 (prime), S-box 1 and a 255-bit field size, as
+  create_rcs_grain.sage generates them;
+- the Cauchy MDS matrix with entries 1 / (i + j + width), indexing rows and columns from zero;
+- eight full rounds, and partial rounds as calc_round_numbers.py computes them for 128-bit security over a 256-bit
+  field, increased by 25% (rounded up) for the strengthened parameters;
+- the arity tag 2^arity - 1 in the first element of the state, and the digest taken from the second.
+
+Run it with `python3 parameters/known_vectors.py`; it prints the body of `KNOWN_VECTORS` in the crate's order.
+"""
+
+from math import ceil, inf, log
+
+P = 0x73EDA753299D7D483339D80809A1D80553BDA402FFFE5BFEFFFFFFFF00000001
+ALPHA = 5
+FIELD_BITS = 255
+
+
+def grain_constants(width, full_rounds, partial_rounds):
+    """The round constants create_rcs_grain.sage generates, in the order the rounds consume them."""
+
+    def bits(n, value):
+        return [(value >> i) & 1 for i in reversed(range(n))]
+
+    state = (
+        bits(2, 1)
+        + bits(4, 1)
+        + bits(12, FIELD_BITS)
+        + bits(12, width)
+        + bits(10, full_rounds)
+        + bits(10, partial_rounds)
+        + bits(30, (1 << 30) - 1)
+    )
+
+    def step():
+        bit = state[62] ^ state[51] ^ state[38] ^ state[23] ^ state[13] ^ state[0]
+        state.pop(0)
+        state.append(bit)
+        return bit
+
+    for _ in range(160):
+        step()
+
+    def next_bit():
+        # Self-shrinking: output the second bit of each pair whose first bit is set.
+        while not step():
+            step()
+        return step()
+
+    constants = []
+    while len(constants) < width * (full_rounds + partial_rounds):
+        value = 0
+        for _ in range(FIELD_BITS):
+            value = (value << 1) | next_bit()
+        if value < P:
+            constants.append(value)
+    return constants
+
+
+def is_secure(field_bits, width, security_bits, full_rounds, partial_rounds):
+    """The statistical, interpolation and Groebner basis bounds of calc_round_numbers.py."""
+    n, t, m, rp = field_bits, width, security_bits, partial_rounds
+    c = round(log(2) / log(ALPHA), 2)
+    lowest = min(m, n)
+    statistical = 6 if m <= (n - log(ALPHA - 1, 2)) * (t + 1) else 10
+    interpolation = c * lowest + log(t, 2) - rp
+    groebner_1 = c / 2 * lowest - rp
+    groebner_2 = (c / 3 * lowest - 1 - rp) / (t - 1)
+    return full_rounds >= max(ceil(b) for b in (statistical, interpolation, groebner_1, groebner_2))
+
+
+def round_numbers(width, strengthened):
+    best = (inf, 0, 0)
+    for partial_rounds in range(1, 500):
+        for full_rounds in range(4, 100, 2):
+            if not is_secure(256, width, 128, full_rounds, partial_rounds):
+                continue
+            full_rounds, partial_rounds_margin = full_rounds + 2, ceil(partial_rounds * 1.075)
+            cost = width * full_rounds + partial_rounds_margin
+            if (cost, full_rounds) < best[:2]:
+                best = (cost, full_rounds, partial_rounds_margin)
+    _, full_rounds, partial_rounds = best
+    if strengthened:
+        partial_rounds = ceil(partial_rounds * 1.25)
+    return full_rounds, partial_rounds
+
+
+def poseidon(preimage, strengthened):
+    arity = len(preimage)
+    width = arity + 1
+    full_rounds, partial_rounds = round_numbers(width, strengthened)
+    constants = grain_constants(width, full_rounds, partial_rounds)
+    mds = [[pow(i + j + width, P - 2, P) for j in range(width)] for i in range(width)]
+
+    state = [(1 << arity) - 1] + list(preimage)
+    for r in range(full_rounds + partial_rounds):
+        state = [(x + k) % P for x, k in zip(state, constants[r * width : (r + 1) * width])]
+        partial = full_rounds // 2 <= r < full_rounds // 2 + partial_rounds
+        state = [pow(x, ALPHA, P) if i == 0 or not partial else x for i, x in enumerate(state)]
+        state = [sum(state[i] * mds[i][j] for i in range(width)) % P for j in range(width)]
+    return state[1]
+
+
+def main():
+    for arity in [2, 4, 8, 11, 16, 24, 36]:
+        for strengthened in [False, True] if arity in (2, 4, 8) else [False]:
+            for name, preimage in [("Incrementing", list(range(arity))), ("Zero", [0] * arity)]:
+                strength = "Strengthened" if strengthened else "Standard"
+                digest = poseidon(preimage, strengthened)
+                print("    vector(")
+                print("        %d," % arity)
+                print("        Strength::%s," % strength)
+                print("        Preimage::%s," % name)
+                print('        "0x%064x",' % digest)
+                print("    ),")
+
+
+if __name__ == "__main__":
+    main()
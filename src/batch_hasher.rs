@@ -79,8 +79,18 @@ where
     A: Arity<Fr>,
 {
     fn hash(&mut self, preimages: &[GenericArray<Fr, A>]) -> Result<Vec<Fr>, Error> {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_batch(preimages.len());
+
         match self {
-            Batcher::GPU(batcher) => batcher.hash(preimages),
+            Batcher::GPU(batcher) => {
+                let digests = batcher.hash(preimages)?;
+                // The CPU batcher hashes with `Poseidon`, which counts its own hashes; a GPU batch must be counted here.
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_hashes(preimages.len(), preimages.len() * A::to_usize());
+
+                Ok(digests)
+            }
             Batcher::CPU(batcher) => batcher.hash(preimages),
         }
     }
@@ -1,13 +1,23 @@
+use crate::hash_type::HashType;
 use crate::matrix::Matrix;
 use crate::mds::SparseMatrix;
-use crate::poseidon::{Arity, PoseidonConstants};
+use crate::poseidon::{
+    Arity, PoseidonConstants, RoundTrace, POSEIDON_CONSTANTS_2, POSEIDON_CONSTANTS_4,
+    POSEIDON_CONSTANTS_8,
+};
+use crate::transcript;
+use crate::{Alpha, Error};
 
-use bellperson::gadgets::boolean::Boolean;
+use bellperson::gadgets::boolean::{AllocatedBit, Boolean};
 use bellperson::gadgets::num;
 use bellperson::gadgets::num::AllocatedNum;
-use bellperson::{ConstraintSystem, LinearCombination, SynthesisError};
-use ff::Field;
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
 use ff::ScalarEngine as Engine;
+use ff::{Field, PrimeField};
+use generic_array::typenum::U2;
+use generic_array::GenericArray;
+use paired::bls12_381::Bls12;
+use std::io;
 use std::marker::PhantomData;
 
 /// Similar to `num::Num`, we use `Elt` to accumulate both values and linear combinations, then eventually
@@ -57,7 +67,7 @@ impl<E: Engine> Elt<E> {
 
                 if enforce {
                     cs.enforce(
-                        || format!("enforce num allocation preserves lc"),
+                        || "enforce num allocation preserves lc",
                         |_| num.lc(E::Fr::one()),
                         |lc| lc + CS::one(),
                         |lc| lc + v.get_variable(),
@@ -68,6 +78,18 @@ impl<E: Engine> Elt<E> {
         }
     }
 
+    /// Whether the value is known when the circuit is built: a Num whose linear combination only involves the ONE
+    /// variable (or nothing at all).
+    fn is_constant<CS: ConstraintSystem<E>>(&self) -> bool {
+        match self {
+            Self::Allocated(_) => false,
+            Self::Num(num) => num
+                .lc(E::Fr::one())
+                .iter()
+                .all(|(var, _)| var.get_unchecked() == CS::one().get_unchecked()),
+        }
+    }
+
     fn val(&self) -> Option<E::Fr> {
         match self {
             Self::Allocated(v) => v.get_value(),
@@ -82,12 +104,21 @@ impl<E: Engine> Elt<E> {
         }
     }
 
-    /// Add two Nums and return a Num tracking the calculation. It is forbidden to invoke on an Allocated because the intended computation
-    /// does not include that path.
+    /// Add two Elts and return a Num tracking the calculation. Allocated operands are deferred into their linear
+    /// combination, so addition never allocates or adds a constraint.
     fn add<CS: ConstraintSystem<E>>(self, other: Elt<E>) -> Result<Elt<E>, SynthesisError> {
         match (self, other) {
             (Elt::Num(a), Elt::Num(b)) => Ok(Elt::Num(a.add(&b))),
-            _ => panic!("only two numbers may be added"),
+            (Elt::Allocated(a), b) => Elt::Num(a.into()).add::<CS>(b),
+            (a, Elt::Allocated(b)) => a.add::<CS>(Elt::Num(b.into())),
+        }
+    }
+
+    /// Convert into a Num, deferring an Allocated into its linear combination.
+    fn into_num(self) -> num::Num<E> {
+        match self {
+            Self::Allocated(a) => a.into(),
+            Self::Num(num) => num,
         }
     }
 
@@ -121,11 +152,24 @@ where
     E: Engine,
     A: Arity<E::Fr>,
 {
-    /// Create a new Poseidon hasher for `preimage`.
-    fn new(elements: Vec<Elt<E>>, constants: &'a PoseidonConstants<E, A>) -> Self {
+    /// Create a new Poseidon hasher for the state `elements`. Returns an error describing the mismatch, rather than
+    /// panicking partway through a round, if `elements` or `constants` do not have the shapes the rounds index into.
+    fn try_new(
+        elements: Vec<Elt<E>>,
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, Error> {
         let width = constants.width();
 
-        PoseidonCircuit {
+        check_constants(constants)?;
+        if elements.len() != width {
+            return Err(Error::Other(format!(
+                "expected {} state elements, got {}",
+                width,
+                elements.len()
+            )));
+        }
+
+        Ok(PoseidonCircuit {
             constants_offset: 0,
             width,
             elements,
@@ -133,37 +177,82 @@ where
             current_round: 0,
             constants,
             _w: PhantomData::<A>,
-        }
+        })
     }
 
     fn hash<CS: ConstraintSystem<E>>(
         &mut self,
         mut cs: CS,
     ) -> Result<AllocatedNum<E>, SynthesisError> {
-        self.full_round(cs.namespace(|| "first round"), true, false)?;
+        let result = Elt::Num(self.hash_to_num(&mut cs)?);
 
-        for i in 1..self.constants.full_rounds / 2 {
-            self.full_round(
-                cs.namespace(|| format!("initial full round {}", i)),
-                false,
-                false,
-            )?;
-        }
+        // The terminal MDS product leaves every lane as an unallocated linear combination, so only the output lane
+        // costs an allocation (and its single enforcing constraint). The other lanes are simply dropped.
+        result.ensure_allocated(&mut cs.namespace(|| "hash result"), true)
+    }
+
+    fn hash_to_num<CS: ConstraintSystem<E>>(
+        &mut self,
+        cs: CS,
+    ) -> Result<num::Num<E>, SynthesisError> {
+        self.permute(cs)?;
+
+        Ok(self.elements[1].clone().into_num())
+    }
 
-        for i in 0..self.constants.partial_rounds {
-            self.partial_round(cs.namespace(|| format!("partial round {}", i)))?;
+    /// Apply the permutation to all elements, leaving each lane as an unallocated linear combination.
+    fn permute<CS: ConstraintSystem<E>>(&mut self, cs: CS) -> Result<(), SynthesisError> {
+        if self.elements.iter().all(|elt| elt.is_constant::<CS>()) {
+            return self.permute_constant::<CS>();
         }
 
-        for i in 0..(self.constants.full_rounds / 2) - 1 {
-            self.full_round(
-                cs.namespace(|| format!("final full round {}", i)),
-                false,
-                false,
-            )?;
+        self.permute_traced(cs, None)
+    }
+
+    /// Apply the permutation as `permute` does, but never constant-folding, and appending the state after each round
+    /// to `trace` if given. Missing values are recorded as `None`.
+    fn permute_traced<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        mut trace: Option<&mut RoundTrace<E::Fr>>,
+    ) -> Result<(), SynthesisError> {
+        let half_full_rounds = self.constants.full_rounds / 2;
+        let partial_rounds = self.constants.partial_rounds;
+        let rounds = self.constants.full_rounds + partial_rounds;
+        for round in 0..rounds {
+            // Names are only built by constraint systems which record them.
+            let round_cs = cs.namespace(|| round_name(round, half_full_rounds, partial_rounds));
+            if round < half_full_rounds || round >= half_full_rounds + partial_rounds {
+                self.full_round(round_cs, round == 0, round == rounds - 1)?;
+            } else {
+                self.partial_round(round_cs)?;
+            }
+
+            if let Some(trace) = trace.as_mut() {
+                trace.push(self.elements.iter().map(Elt::val).collect());
+            }
         }
-        self.full_round(cs.namespace(|| "terminal full round"), false, true)?;
 
-        self.elements[1].ensure_allocated(&mut cs.namespace(|| "hash result"), true)
+        Ok(())
+    }
+
+    /// When every element is known as the circuit is built, the permutation is computed out of circuit and the
+    /// result is constant, costing no constraints.
+    fn permute_constant<CS: ConstraintSystem<E>>(&mut self) -> Result<(), SynthesisError> {
+        let values = self
+            .elements
+            .iter()
+            .map(|elt| elt.val().ok_or(SynthesisError::AssignmentMissing))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut state = GenericArray::clone_from_slice(&values);
+
+        crate::poseidon::poseidon_permute(self.constants, &mut state);
+        self.elements = state
+            .iter()
+            .map(|value| Elt::num_from_fr::<CS>(*value))
+            .collect();
+
+        Ok(())
     }
 
     fn full_round<CS: ConstraintSystem<E>>(
@@ -192,7 +281,7 @@ where
         };
         constants_offset += post_round_keys.len();
 
-        // Apply the quintic S-Box to all elements
+        // Apply the S-Box to all elements
         for i in 0..self.elements.len() {
             let pre_round_key = if first_round {
                 let rk = pre_round_keys[i];
@@ -209,26 +298,30 @@ where
             };
 
             if first_round {
-                if i == 0 {
-                    // The very first s-box for the constant arity tag can also be computed statically, as a constant.
-                    self.elements[i] = constant_quintic_s_box_pre_add_tag::<CS, E>(
+                if self.elements[i].is_constant::<CS>() {
+                    // The first s-box for a constant element (always the domain tag, and any lane a sponge has left
+                    // untouched) can also be computed statically, as a constant.
+                    self.elements[i] = constant_alpha_s_box_pre_add_tag::<CS, E>(
                         &self.elements[i],
                         pre_round_key,
                         post_round_key,
+                        self.constants.alpha,
                     );
                 } else {
-                    self.elements[i] = quintic_s_box_pre_add(
-                        cs.namespace(|| format!("quintic s-box {}", i)),
+                    self.elements[i] = alpha_s_box_pre_add(
+                        cs.namespace(|| format!("s-box {}", i)),
                         &self.elements[i],
                         pre_round_key,
                         post_round_key,
+                        self.constants.alpha,
                     )?;
                 }
             } else {
-                self.elements[i] = quintic_s_box(
-                    cs.namespace(|| format!("quintic s-box {}", i)),
+                self.elements[i] = alpha_s_box(
+                    cs.namespace(|| format!("s-box {}", i)),
                     &self.elements[i],
                     post_round_key,
+                    self.constants.alpha,
                 )?;
             }
         }
@@ -242,11 +335,12 @@ where
     fn partial_round<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
         let round_key = self.constants.compressed_round_constants[self.constants_offset];
         self.constants_offset += 1;
-        // Apply the quintic S-Box to the first element.
-        self.elements[0] = quintic_s_box(
-            cs.namespace(|| "solitary quintic s-box"),
+        // Apply the S-Box to the first element.
+        self.elements[0] = alpha_s_box(
+            cs.namespace(|| "solitary s-box"),
             &self.elements[0],
             Some(round_key),
+            self.constants.alpha,
         )?;
 
         // Multiply the elements by the constant MDS matrix
@@ -329,17 +423,45 @@ where
 
         Ok(())
     }
-
-    fn debug(&self) {
-        let element_frs: Vec<_> = self.elements.iter().map(|n| n.val()).collect::<Vec<_>>();
-        dbg!(element_frs, self.constants_offset);
-    }
 }
 
 /// Create circuit for Poseidon hash.
+///
+/// The gadget is generic over the engine and arity: pass the preimage and the matching constants, which are shared
+/// (never allocated) across any number of hashes. Returns `SynthesisError::Unsatisfiable` if the preimage length
+/// is not the arity.
+///
+/// ```
+/// use bellperson::gadgets::num::AllocatedNum;
+/// use bellperson::{Circuit, ConstraintSystem, SynthesisError};
+/// use generic_array::typenum::U2;
+/// use neptune::circuit::poseidon_hash;
+/// use neptune::poseidon::PoseidonConstants;
+/// use paired::bls12_381::{Bls12, Fr};
+///
+/// struct DigestCircuit<'a> {
+///     preimage: [Option<Fr>; 2],
+///     constants: &'a PoseidonConstants<Bls12, U2>,
+/// }
+///
+/// impl<'a> Circuit<Bls12> for DigestCircuit<'a> {
+///     fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+///         let mut preimage = Vec::new();
+///         for (i, x) in self.preimage.iter().enumerate() {
+///             preimage.push(AllocatedNum::alloc(
+///                 cs.namespace(|| format!("preimage {}", i)),
+///                 || x.ok_or(SynthesisError::AssignmentMissing),
+///             )?);
+///         }
+///
+///         let digest = poseidon_hash(cs.namespace(|| "poseidon"), &preimage, self.constants)?;
+///         digest.inputize(cs.namespace(|| "digest"))
+///     }
+/// }
+/// ```
 pub fn poseidon_hash<CS, E, A>(
     cs: CS,
-    preimage: Vec<AllocatedNum<E>>,
+    preimage: &[AllocatedNum<E>],
     constants: &PoseidonConstants<E, A>,
 ) -> Result<AllocatedNum<E>, SynthesisError>
 where
@@ -347,268 +469,3566 @@ where
     E: Engine,
     A: Arity<E::Fr>,
 {
-    let tag_element = Elt::num_from_fr::<CS>(constants.arity_tag);
-    let mut elements = Vec::with_capacity(A::to_usize());
-    elements.push(tag_element);
-    elements.extend(preimage.into_iter().map(Elt::Allocated));
-
-    let mut p = PoseidonCircuit::new(elements, constants);
+    let mut p = PoseidonCircuit::try_new(
+        preimage_elements::<CS, E, A>(preimage, constants)?,
+        constants,
+    )
+    .map_err(invalid_constants)?;
 
     p.hash(cs)
 }
 
-/// Compute l^5 and enforce constraint. If round_key is supplied, add it to result.
-fn quintic_s_box<CS: ConstraintSystem<E>, E: Engine>(
+/// Allocate `preimage` and create circuit for its Poseidon hash, returning the allocated preimage and the digest.
+///
+/// Each element of `preimage` is a value (`None` during parameter generation) and whether it is public. Elements are
+/// allocated in order, public ones with `alloc_input`, so the public inputs this adds are exactly the public preimage
+/// elements, in preimage order. The domain tag is a constant and never becomes an input. The digest is not made public:
+/// call `inputize` on it to append it after the preimage inputs.
+pub fn poseidon_hash_alloc<CS, E, A>(
     mut cs: CS,
-    e: &Elt<E>,
-    post_round_key: Option<E::Fr>,
-) -> Result<Elt<E>, SynthesisError> {
-    let l = e.ensure_allocated(&mut cs.namespace(|| "S-box input"), true)?;
+    preimage: &[(Option<E::Fr>, bool)],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<(Vec<AllocatedNum<E>>, AllocatedNum<E>), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if preimage.len() != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
 
-    // If round_key was supplied, add it after all exponentiation.
-    let l2 = l.square(cs.namespace(|| "l^2"))?;
-    let l4 = l2.square(cs.namespace(|| "l^4"))?;
-    let l5 = mul_sum(
-        cs.namespace(|| "(l4 * l) + rk)"),
-        &l4,
-        &l,
-        None,
-        post_round_key,
-        true,
-    );
+    let allocated = preimage
+        .iter()
+        .enumerate()
+        .map(|(i, (value, is_public))| {
+            let assignment = || value.ok_or(SynthesisError::AssignmentMissing);
+            let cs = cs.namespace(|| format!("preimage {}", i));
+            if *is_public {
+                AllocatedNum::alloc_input(cs, assignment)
+            } else {
+                AllocatedNum::alloc(cs, assignment)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(Elt::Allocated(l5?))
+    let digest = poseidon_hash(cs.namespace(|| "hash"), &allocated, constants)?;
+
+    Ok((allocated, digest))
 }
 
-/// Compute l^5 and enforce constraint. If round_key is supplied, add it to l first.
-fn quintic_s_box_pre_add<CS: ConstraintSystem<E>, E: Engine>(
-    mut cs: CS,
-    e: &Elt<E>,
-    pre_round_key: Option<E::Fr>,
-    post_round_key: Option<E::Fr>,
-) -> Result<Elt<E>, SynthesisError> {
-    if let (Some(pre_round_key), Some(post_round_key)) = (pre_round_key, post_round_key) {
-        let l = e.ensure_allocated(&mut cs.namespace(|| "S-box input"), true)?;
+/// Create circuit hashing two elements with the default arity 2 constants, matching `poseidon::hash2`.
+pub fn poseidon_hash2<CS: ConstraintSystem<Bls12>>(
+    cs: CS,
+    a: &AllocatedNum<Bls12>,
+    b: &AllocatedNum<Bls12>,
+) -> Result<AllocatedNum<Bls12>, SynthesisError> {
+    poseidon_hash(cs, &[a.clone(), b.clone()], &*POSEIDON_CONSTANTS_2)
+}
 
-        // If round_key was supplied, add it to l before squaring.
-        let l2 = square_sum(cs.namespace(|| "(l+rk)^2"), pre_round_key, &l, true)?;
-        let l4 = l2.square(cs.namespace(|| "l^4"))?;
-        let l5 = mul_sum(
-            cs.namespace(|| "l4 * (l + rk)"),
-            &l4,
-            &l,
-            Some(pre_round_key),
-            Some(post_round_key),
-            true,
-        );
+/// Create circuit hashing four elements with the default arity 4 constants, matching `poseidon::hash4`.
+pub fn poseidon_hash4<CS: ConstraintSystem<Bls12>>(
+    cs: CS,
+    preimage: &[AllocatedNum<Bls12>; 4],
+) -> Result<AllocatedNum<Bls12>, SynthesisError> {
+    poseidon_hash(cs, preimage, &*POSEIDON_CONSTANTS_4)
+}
 
-        Ok(Elt::Allocated(l5?))
-    } else {
-        panic!("pre_round_key and post_round_key must both be provided.");
-    }
+/// Create circuit hashing eight elements with the default arity 8 constants, matching `poseidon::hash8`.
+pub fn poseidon_hash8<CS: ConstraintSystem<Bls12>>(
+    cs: CS,
+    preimage: &[AllocatedNum<Bls12>; 8],
+) -> Result<AllocatedNum<Bls12>, SynthesisError> {
+    poseidon_hash(cs, preimage, &*POSEIDON_CONSTANTS_8)
 }
 
-/// Compute l^5 and enforce constraint. If round_key is supplied, add it to l first.
-fn constant_quintic_s_box_pre_add_tag<CS: ConstraintSystem<E>, E: Engine>(
-    tag: &Elt<E>,
-    pre_round_key: Option<E::Fr>,
-    post_round_key: Option<E::Fr>,
-) -> Elt<E> {
-    let mut tag = tag.val().expect("missing tag val");
-    pre_round_key.expect("pre_round_key must be provided");
-    post_round_key.expect("post_round_key must be provided");
+/// Create circuit for Poseidon hash of a preimage of at most `arity` elements, matching `poseidon::hash_variable`.
+///
+/// The preimage is zero-padded to the arity and the capacity element holds the `HashType::ConstantLength` tag for its
+/// length. The padding is constant, so its first-round s-boxes cost no constraints. Returns
+/// `SynthesisError::Unsatisfiable` if the preimage is longer than the arity.
+pub fn poseidon_hash_variable<CS, E, A>(
+    cs: CS,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if preimage.len() > A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
 
-    crate::quintic_s_box::<E>(&mut tag, pre_round_key.as_ref(), post_round_key.as_ref());
+    let tag = HashType::ConstantLength(preimage.len()).domain_tag::<E::Fr, A>();
+    let mut elements = Vec::with_capacity(constants.width());
+    elements.push(Elt::num_from_fr::<CS>(tag));
+    elements.extend(preimage.iter().cloned().map(Elt::Allocated));
+    elements.resize(constants.width(), Elt::Num(num::Num::zero()));
 
-    Elt::num_from_fr::<CS>(tag)
+    let mut p = PoseidonCircuit::try_new(elements, constants).map_err(invalid_constants)?;
+
+    p.hash(cs)
 }
 
-/// Calculates square of sum and enforces that constraint.
-pub fn square_sum<CS: ConstraintSystem<E>, E: Engine>(
+/// Create circuit for Poseidon hash of a preimage of any length by chaining permutations, matching
+/// `poseidon::hash_multi`, which documents the chaining.
+///
+/// Intermediate digests are left unallocated until the next block's first s-box, and the padding is constant, so each
+/// block costs no more than `poseidon_hash`.
+pub fn poseidon_hash_multi<CS, E, A>(
     mut cs: CS,
-    to_add: E::Fr,
-    num: &AllocatedNum<E>,
-    enforce: bool,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
 ) -> Result<AllocatedNum<E>, SynthesisError>
 where
     CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
 {
-    let res = AllocatedNum::alloc(cs.namespace(|| "squared sum"), || {
-        let mut tmp = num
-            .get_value()
-            .ok_or_else(|| SynthesisError::AssignmentMissing)?;
-        tmp.add_assign(&to_add);
-        tmp.square();
+    let arity = A::to_usize();
+    let tag = HashType::Chained(preimage.len()).domain_tag::<E::Fr, A>();
+    let (first, rest) = preimage.split_at(std::cmp::min(arity, preimage.len()));
 
-        Ok(tmp)
-    })?;
+    let block_circuit = |elements: Vec<Elt<E>>| {
+        let mut state = Vec::with_capacity(constants.width());
+        state.push(Elt::num_from_fr::<CS>(tag));
+        state.extend(elements);
+        state.resize(constants.width(), Elt::Num(num::Num::zero()));
 
-    if enforce {
-        cs.enforce(
-            || "squared sum constraint",
-            |lc| lc + num.get_variable() + (to_add, CS::one()),
-            |lc| lc + num.get_variable() + (to_add, CS::one()),
-            |lc| lc + res.get_variable(),
-        );
+        PoseidonCircuit::try_new(state, constants).map_err(invalid_constants)
+    };
+
+    let mut p = block_circuit(first.iter().cloned().map(Elt::Allocated).collect())?;
+    for (i, chunk) in rest.chunks(arity - 1).enumerate() {
+        let digest = p.hash_to_num(cs.namespace(|| format!("block {}", i)))?;
+
+        let mut elements = Vec::with_capacity(arity);
+        elements.push(Elt::Num(digest));
+        elements.extend(chunk.iter().cloned().map(Elt::Allocated));
+        p = block_circuit(elements)?;
     }
-    Ok(res)
+
+    p.hash(cs.namespace(|| "final block"))
 }
 
-/// Calculates (a * (pre_add + b)) + post_add — and enforces that constraint.
-pub fn mul_sum<CS: ConstraintSystem<E>, E: Engine>(
-    mut cs: CS,
-    a: &AllocatedNum<E>,
-    b: &AllocatedNum<E>,
-    pre_add: Option<E::Fr>,
-    post_add: Option<E::Fr>,
-    enforce: bool,
+/// Create circuit for the keyed pseudorandom function `poseidon::prf`: hash the witness `key` followed by `inputs`
+/// under the `HashType::Prf` domain tag. Returns `SynthesisError::Unsatisfiable` unless there are exactly
+/// `arity - 1` inputs.
+pub fn poseidon_prf<CS, E, A>(
+    cs: CS,
+    key: &AllocatedNum<E>,
+    inputs: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
 ) -> Result<AllocatedNum<E>, SynthesisError>
 where
     CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
 {
-    let res = AllocatedNum::alloc(cs.namespace(|| "mul_sum"), || {
-        let mut tmp = b
-            .get_value()
-            .ok_or_else(|| SynthesisError::AssignmentMissing)?;
-        if let Some(x) = pre_add {
-            tmp.add_assign(&x);
-        }
-        tmp.mul_assign(
-            &a.get_value()
-                .ok_or_else(|| SynthesisError::AssignmentMissing)?,
-        );
-        if let Some(x) = post_add {
-            tmp.add_assign(&x);
-        }
+    if inputs.len() + 1 != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
 
-        Ok(tmp)
-    })?;
+    let mut elements = Vec::with_capacity(constants.width());
+    elements.push(Elt::num_from_fr::<CS>(
+        HashType::Prf.domain_tag::<E::Fr, A>(),
+    ));
+    elements.push(Elt::Allocated(key.clone()));
+    elements.extend(inputs.iter().cloned().map(Elt::Allocated));
 
-    if enforce {
-        if let Some(x) = post_add {
-            let mut neg = E::Fr::zero();
-            neg.sub_assign(&x);
+    let mut p = PoseidonCircuit::try_new(elements, constants).map_err(invalid_constants)?;
 
-            if let Some(pre) = pre_add {
-                cs.enforce(
-                    || "mul sum constraint pre-post-add",
-                    |lc| lc + b.get_variable() + (pre, CS::one()),
-                    |lc| lc + a.get_variable(),
-                    |lc| lc + res.get_variable() + (neg, CS::one()),
-                );
-            } else {
-                cs.enforce(
-                    || "mul sum constraint post-add",
-                    |lc| lc + b.get_variable(),
-                    |lc| lc + a.get_variable(),
-                    |lc| lc + res.get_variable() + (neg, CS::one()),
-                );
-            }
-        } else {
-            if let Some(pre) = pre_add {
-                cs.enforce(
-                    || "mul sum constraint pre-add",
-                    |lc| lc + b.get_variable() + (pre, CS::one()),
-                    |lc| lc + a.get_variable(),
-                    |lc| lc + res.get_variable(),
-                );
-            } else {
-                cs.enforce(
-                    || "mul sum constraint",
-                    |lc| lc + b.get_variable(),
-                    |lc| lc + a.get_variable(),
-                    |lc| lc + res.get_variable(),
-                );
-            }
-        }
-    }
-    Ok(res)
+    p.hash(cs)
 }
 
-/// Calculates a * (b + to_add) — and enforces that constraint.
-pub fn mul_pre_sum<CS: ConstraintSystem<E>, E: Engine>(
+/// Create circuit for the salted hash `poseidon::hash_with_salt`: the witness `salt` is hashed alone under the
+/// `HashType::Salt` domain tag, and its digest becomes the capacity element for hashing `preimage`. Returns
+/// `SynthesisError::Unsatisfiable` unless `preimage` holds exactly `arity` elements.
+pub fn poseidon_hash_with_salt<CS, E, A>(
     mut cs: CS,
-    a: &AllocatedNum<E>,
-    b: &AllocatedNum<E>,
-    to_add: E::Fr,
-    enforce: bool,
+    salt: &AllocatedNum<E>,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
 ) -> Result<AllocatedNum<E>, SynthesisError>
 where
     CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
 {
-    let res = AllocatedNum::alloc(cs.namespace(|| "mul_sum"), || {
-        let mut tmp = b
-            .get_value()
-            .ok_or_else(|| SynthesisError::AssignmentMissing)?;
-        tmp.add_assign(&to_add);
-        tmp.mul_assign(
-            &a.get_value()
-                .ok_or_else(|| SynthesisError::AssignmentMissing)?,
-        );
-
-        Ok(tmp)
-    })?;
-
-    if enforce {
-        cs.enforce(
-            || "mul sum constraint",
-            |lc| lc + b.get_variable() + (to_add, CS::one()),
-            |lc| lc + a.get_variable(),
-            |lc| lc + res.get_variable(),
-        );
+    if preimage.len() != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
     }
-    Ok(res)
-}
 
-fn scalar_product_with_add<E: Engine, CS: ConstraintSystem<E>>(
-    elts: &[Elt<E>],
-    scalars: &[E::Fr],
-    to_add: E::Fr,
-) -> Result<Elt<E>, SynthesisError> {
-    let tmp = scalar_product::<E, CS>(elts, scalars)?;
-    let tmp2 = tmp.add::<CS>(Elt::<E>::num_from_fr::<CS>(to_add))?;
+    let mut salt_elements = Vec::with_capacity(constants.width());
+    salt_elements.push(Elt::num_from_fr::<CS>(
+        HashType::Salt.domain_tag::<E::Fr, A>(),
+    ));
+    salt_elements.push(Elt::Allocated(salt.clone()));
+    salt_elements.resize(constants.width(), Elt::num_from_fr::<CS>(E::Fr::zero()));
+    let capacity = PoseidonCircuit::try_new(salt_elements, constants)
+        .map_err(invalid_constants)?
+        .hash(cs.namespace(|| "salt"))?;
 
-    Ok(tmp2)
+    let mut elements = Vec::with_capacity(constants.width());
+    elements.push(Elt::Allocated(capacity));
+    elements.extend(preimage.iter().cloned().map(Elt::Allocated));
+    let mut p = PoseidonCircuit::try_new(elements, constants).map_err(invalid_constants)?;
+
+    p.hash(cs.namespace(|| "salted hash"))
 }
 
-fn scalar_product<E: Engine, CS: ConstraintSystem<E>>(
-    elts: &[Elt<E>],
-    scalars: &[E::Fr],
-) -> Result<Elt<E>, SynthesisError> {
-    elts.iter()
-        .zip(scalars)
-        .try_fold(Elt::Num(num::Num::zero()), |acc, (elt, &scalar)| {
-            acc.add::<CS>(elt.clone().scale::<CS>(scalar)?)
+/// Create circuit for the Poseidon hashes of many preimages, returning the digests in order.
+///
+/// The digests are those `poseidon_hash` computes for each preimage, sharing `constants`. Every preimage is checked
+/// before anything is synthesized, so a batch containing a preimage of the wrong length adds nothing to `cs`. Each
+/// hash is namespaced by its index alone, which keeps paths short in constraint systems that record them.
+pub fn poseidon_hash_batch<CS, E, A>(
+    mut cs: CS,
+    preimages: &[Vec<AllocatedNum<E>>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<Vec<AllocatedNum<E>>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let all_elements = preimages
+        .iter()
+        .map(|preimage| preimage_elements::<CS, E, A>(preimage, constants))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    all_elements
+        .into_iter()
+        .enumerate()
+        .map(|(i, elements)| {
+            PoseidonCircuit::try_new(elements, constants)
+                .map_err(invalid_constants)?
+                .hash(cs.namespace(|| i.to_string()))
         })
+        .collect()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Allocate exactly the variables `poseidon_hash` would, in the same order and with the same values, but add no
+/// constraints, returning the digest.
+///
+/// This is a fast path for proving-time synthesis into a constraint system such as `witness_cs::WitnessCS`, which
+/// only records the assignment. The values are computed by a scalar permutation rather than by tracking linear
+/// combinations through the rounds. The digest is NOT constrained to be the hash of `preimage`: use this only where
+/// the constraints are already fixed by synthesizing `poseidon_hash`, as when proving against parameters generated
+/// from it. Returns `SynthesisError::Unsatisfiable` under the same conditions as `poseidon_hash`.
+pub fn poseidon_hash_witness<CS, E, A>(
+    mut cs: CS,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if preimage.len() != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    check_constants(constants).map_err(invalid_constants)?;
+
+    // Without a witness (during parameter generation) the allocations are still made, but never assigned.
+    let values = preimage
+        .iter()
+        .map(AllocatedNum::get_value)
+        .collect::<Option<Vec<_>>>();
+    let known = values.is_some();
+    let witness = witness_values(
+        constants,
+        &values.unwrap_or_else(|| vec![E::Fr::zero(); A::to_usize()]),
+    );
+    let assignment = |value: &E::Fr| {
+        if known {
+            Ok(*value)
+        } else {
+            Err(SynthesisError::AssignmentMissing)
+        }
+    };
+
+    let (digest, intermediate) = witness.split_last().expect("witness is never empty");
+    for (i, value) in intermediate.iter().enumerate() {
+        cs.alloc(|| i.to_string(), || assignment(value))?;
+    }
+
+    AllocatedNum::alloc(cs.namespace(|| "hash result"), || assignment(digest))
+}
+
+/// The values of the variables `poseidon_hash` of `preimage` allocates, in allocation order. This mirrors
+/// `PoseidonCircuit::permute` round by round: the tag lane's first s-box is constant, the preimage lanes are already
+/// allocated for theirs, and every other s-box first allocates its input. The digest is allocated last.
+fn witness_values<E, A>(constants: &PoseidonConstants<E, A>, preimage: &[E::Fr]) -> Vec<E::Fr>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let width = constants.width();
+    let half_full_rounds = constants.half_full_rounds;
+    let partial_rounds = constants.partial_rounds;
+    let rounds = constants.full_rounds + partial_rounds;
+    let keys = &constants.compressed_round_constants;
+
+    let mut state = Vec::with_capacity(width);
+    state.push(constants.domain_tag);
+    state.extend_from_slice(preimage);
+
+    let mut witness = Vec::with_capacity(poseidon_constraints(constants));
+    let mut offset = 0;
+    for round in 0..rounds {
+        if round < half_full_rounds || round >= half_full_rounds + partial_rounds {
+            let first_round = round == 0;
+            let pre_offset = if first_round {
+                offset += width;
+                Some(offset - width)
+            } else {
+                None
+            };
+            let post_offset = if first_round || round != rounds - 1 {
+                offset += width;
+                Some(offset - width)
+            } else {
+                None
+            };
+
+            for (i, element) in state.iter_mut().enumerate() {
+                let pre_round_key = pre_offset.map(|o| &keys[o + i]);
+                let post_round_key = post_offset.map(|o| &keys[o + i]);
+                if first_round && i == 0 {
+                    crate::alpha_s_box::<E>(
+                        element,
+                        pre_round_key,
+                        post_round_key,
+                        constants.alpha,
+                    );
+                } else {
+                    s_box_witness::<E>(
+                        &mut witness,
+                        element,
+                        pre_round_key,
+                        post_round_key,
+                        !first_round,
+                        constants.alpha,
+                    );
+                }
+            }
+        } else {
+            s_box_witness::<E>(
+                &mut witness,
+                &mut state[0],
+                None,
+                Some(&keys[offset]),
+                true,
+                constants.alpha,
+            );
+            offset += 1;
+        }
+
+        // As in `PoseidonCircuit::product_mds`.
+        state = if round + 1 == half_full_rounds {
+            crate::matrix::apply_matrix::<E>(&constants.pre_sparse_matrix, &state)
+        } else if round >= half_full_rounds && round < half_full_rounds + partial_rounds {
+            let sparse_matrix = &constants.sparse_matrixes[round - half_full_rounds];
+            let mut result = state.clone();
+            result[0] = state.iter().zip(sparse_matrix.w_hat.iter()).fold(
+                E::Fr::zero(),
+                |mut acc, (x, w)| {
+                    let mut term = *x;
+                    term.mul_assign(w);
+                    acc.add_assign(&term);
+                    acc
+                },
+            );
+            for (x, v) in result.iter_mut().skip(1).zip(sparse_matrix.v_rest.iter()) {
+                let mut term = state[0];
+                term.mul_assign(v);
+                x.add_assign(&term);
+            }
+            result
+        } else {
+            crate::matrix::apply_matrix::<E>(&constants.mds_matrices.m, &state)
+        };
+    }
+
+    witness.push(state[1]);
+    witness
+}
+
+/// Apply the s-box to `l` as `alpha_s_box` and `alpha_s_box_pre_add` do, appending the values they allocate.
+fn s_box_witness<E: Engine>(
+    witness: &mut Vec<E::Fr>,
+    l: &mut E::Fr,
+    pre_round_key: Option<&E::Fr>,
+    post_round_key: Option<&E::Fr>,
+    allocate_input: bool,
+    alpha: Alpha,
+) {
+    if allocate_input {
+        witness.push(*l);
+    }
+    if let Some(key) = pre_round_key {
+        l.add_assign(key);
+    }
+
+    let mut l2 = *l;
+    l2.square();
+    witness.push(l2);
+    let factor = match alpha {
+        Alpha::Three => l2,
+        Alpha::Five => {
+            let mut l4 = l2;
+            l4.square();
+            witness.push(l4);
+            l4
+        }
+        Alpha::Seven => {
+            let mut l4 = l2;
+            l4.square();
+            witness.push(l4);
+            let mut l6 = l4;
+            l6.mul_assign(&l2);
+            witness.push(l6);
+            l6
+        }
+    };
+
+    l.mul_assign(&factor);
+    if let Some(key) = post_round_key {
+        l.add_assign(key);
+    }
+    witness.push(*l);
+}
+
+/// Create circuit for Poseidon hash as `poseidon_hash` does, returning the state after each round rather than the
+/// digest. Values missing from the witness, as during parameter generation, are recorded as `None`. Compare with
+/// `Poseidon::trace` to find the round at which a circuit stops matching the scalar hash.
+#[cfg(feature = "trace")]
+pub fn poseidon_hash_trace<CS, E, A>(
+    cs: CS,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<RoundTrace<E::Fr>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let mut p = PoseidonCircuit::try_new(
+        preimage_elements::<CS, E, A>(preimage, constants)?,
+        constants,
+    )
+    .map_err(invalid_constants)?;
+
+    let mut trace = Vec::with_capacity(constants.full_rounds + constants.partial_rounds);
+    p.permute_traced(cs, Some(&mut trace))?;
+
+    Ok(trace)
+}
+
+/// Create circuit for Poseidon hash, returning the digest as an unallocated `num::Num`.
+///
+/// Use this when the digest is immediately consumed by another linear constraint: folding the returned linear
+/// combination into that constraint saves the allocation (and constraint) `poseidon_hash` spends on its result.
+pub fn poseidon_hash_num<CS, E, A>(
+    cs: CS,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<num::Num<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let mut p = PoseidonCircuit::try_new(
+        preimage_elements::<CS, E, A>(preimage, constants)?,
+        constants,
+    )
+    .map_err(invalid_constants)?;
+
+    p.hash_to_num(cs)
+}
+
+/// Decompose `digest` into its low `n_bits` bits, least significant first, matching `poseidon::digest_to_bits`.
+///
+/// The full decomposition is constrained to be the canonical one, below the modulus: a value `x` with `x + p` below
+/// 2^255 would otherwise also decompose as the bits of `x + p`, letting a prover pick between two different
+/// challenges. The canonical bits are then truncated, so this costs the same for any `n_bits`. Returns
+/// `SynthesisError::Unsatisfiable` if `n_bits` exceeds the field's bit length.
+pub fn digest_to_bits<CS, E>(
+    mut cs: CS,
+    digest: &AllocatedNum<E>,
+    n_bits: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+{
+    if n_bits > E::Fr::NUM_BITS as usize {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    let mut bits = digest.to_bits_le_strict(cs.namespace(|| "canonical bits"))?;
+    bits.truncate(n_bits);
+
+    Ok(bits)
+}
+
+/// Create circuit for Poseidon hash of `preimage`, returning the low `n_bits` bits of the digest as `digest_to_bits`
+/// does.
+pub fn poseidon_hash_to_bits<CS, E, A>(
+    mut cs: CS,
+    preimage: &[AllocatedNum<E>],
+    n_bits: usize,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if n_bits > E::Fr::NUM_BITS as usize {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    let digest = poseidon_hash(cs.namespace(|| "hash"), preimage, constants)?;
+
+    digest_to_bits(cs.namespace(|| "bits"), &digest, n_bits)
+}
+
+/// Create circuit for Poseidon hash of a preimage of linear combinations, returning the digest as an unallocated
+/// `num::Num`.
+///
+/// Preimage elements built with `constant_num` are known as the circuit is built. If every element is, the digest is
+/// computed out of circuit and returned as a constant, adding no constraints: this suits fixed domain strings, or the
+/// digests of empty subtrees in a padding circuit. Otherwise this costs the same as `poseidon_hash_num`.
+pub fn poseidon_hash_nums<CS, E, A>(
+    cs: CS,
+    preimage: &[num::Num<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<num::Num<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if preimage.len() != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    let mut elements = Vec::with_capacity(constants.width());
+    elements.push(Elt::num_from_fr::<CS>(constants.domain_tag));
+    elements.extend(preimage.iter().cloned().map(Elt::Num));
+
+    let mut p = PoseidonCircuit::try_new(elements, constants).map_err(invalid_constants)?;
+
+    p.hash_to_num(cs)
+}
+
+/// A `num::Num` holding the constant `value`, for use as a preimage element of `poseidon_hash_nums`. It refers only to
+/// the ONE variable of `CS`.
+pub fn constant_num<CS: ConstraintSystem<E>, E: Engine>(value: E::Fr) -> num::Num<E> {
+    Elt::num_from_fr::<CS>(value).into_num()
+}
+
+/// Create circuit selecting the Poseidon hash of `preimage` if `condition` is true, and `passthrough` otherwise.
+///
+/// The hash is always synthesized, since constraints are paid either way. Its digest is left unallocated and folded
+/// into the single select constraint, `condition * (digest - passthrough) = result - passthrough`, so this costs no
+/// more than `poseidon_hash`. `condition` is constrained to be boolean by its own allocation.
+pub fn poseidon_hash_conditional<CS, E, A>(
+    mut cs: CS,
+    condition: &Boolean,
+    preimage: &[AllocatedNum<E>],
+    passthrough: &AllocatedNum<E>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let digest = poseidon_hash_num(cs.namespace(|| "hash"), preimage, constants)?;
+
+    let result = AllocatedNum::alloc(cs.namespace(|| "result"), || {
+        if condition
+            .get_value()
+            .ok_or_else(|| SynthesisError::AssignmentMissing)?
+        {
+            digest
+                .get_value()
+                .ok_or_else(|| SynthesisError::AssignmentMissing)
+        } else {
+            passthrough
+                .get_value()
+                .ok_or_else(|| SynthesisError::AssignmentMissing)
+        }
+    })?;
+
+    cs.enforce(
+        || "select",
+        |_| condition.lc(CS::one(), E::Fr::one()),
+        |_| digest.lc(E::Fr::one()) - passthrough.get_variable(),
+        |lc| lc + result.get_variable() - passthrough.get_variable(),
+    );
+
+    Ok(result)
+}
+
+/// Create circuit for Poseidon hash, returning a bit which is true exactly when the digest equals `claimed`.
+///
+/// Unlike `poseidon_hash_enforce_equal`, a mismatch is not unsatisfiable: the bit can feed later logic. The digest is
+/// left unallocated, and the equality costs three constraints on top of the hash.
+pub fn poseidon_hash_equals<CS, E, A>(
+    mut cs: CS,
+    preimage: &[AllocatedNum<E>],
+    claimed: &AllocatedNum<E>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<Boolean, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let digest = poseidon_hash_num(cs.namespace(|| "hash"), preimage, constants)?;
+    let mut minus_one = E::Fr::zero();
+    minus_one.sub_assign(&E::Fr::one());
+    let difference = digest.add(&num::Num::from(claimed.clone()).scale(minus_one));
+
+    let is_zero = difference.get_value().map(|d| d.is_zero());
+    let inverse = difference
+        .get_value()
+        .map(|d| d.inverse().unwrap_or_else(E::Fr::zero));
+
+    is_zero_bit(cs.namespace(|| "equals"), &difference, is_zero, inverse)
+}
+
+/// Allocate a bit which is true exactly when `value` is zero, from the witnesses `bit` and `inverse`. The constraints
+/// are `value * inverse = 1 - bit`, which forces the bit to zero unless `value` is zero, and `value * bit = 0`, which
+/// forces it to zero if `value` is not. The bit's allocation adds the third, booleanity, constraint.
+fn is_zero_bit<CS, E>(
+    mut cs: CS,
+    value: &num::Num<E>,
+    bit: Option<bool>,
+    inverse: Option<E::Fr>,
+) -> Result<Boolean, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+{
+    let bit = AllocatedBit::alloc(cs.namespace(|| "is zero"), bit)?;
+    let inverse = AllocatedNum::alloc(cs.namespace(|| "inverse"), || {
+        inverse.ok_or_else(|| SynthesisError::AssignmentMissing)
+    })?;
+
+    cs.enforce(
+        || "value * inverse = 1 - bit",
+        |_| value.lc(E::Fr::one()),
+        |lc| lc + inverse.get_variable(),
+        |lc| lc + CS::one() - bit.get_variable(),
+    );
+    cs.enforce(
+        || "value * bit = 0",
+        |_| value.lc(E::Fr::one()),
+        |lc| lc + bit.get_variable(),
+        |lc| lc,
+    );
+
+    Ok(Boolean::from(bit))
+}
+
+/// Create circuit for Poseidon hash, enforcing that the digest equals `expected` (typically a public input).
+///
+/// The equality takes the place of the constraint `poseidon_hash` would spend allocating its result, so this costs no
+/// more than hashing alone. The computed digest is returned for convenience.
+pub fn poseidon_hash_enforce_equal<CS, E, A>(
+    mut cs: CS,
+    preimage: &[AllocatedNum<E>],
+    expected: &AllocatedNum<E>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<num::Num<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let digest = poseidon_hash_num(cs.namespace(|| "hash"), preimage, constants)?;
+
+    cs.enforce(
+        || "digest equals expected",
+        |_| digest.lc(E::Fr::one()),
+        |lc| lc + CS::one(),
+        |lc| lc + expected.get_variable(),
+    );
+
+    Ok(digest)
+}
+
+/// Create circuit verifying that `path` leads from `leaf` to `root` in a binary Merkle tree.
+///
+/// Each path entry holds the sibling at that level, bottom first, and a direction bit which is true when the current
+/// node is the right child. The pair is ordered accordingly, hashed, and the final digest is enforced to equal `root`.
+/// The same `constants` serve every level; none are allocated.
+pub fn verify_merkle_path<CS, E>(
+    mut cs: CS,
+    leaf: AllocatedNum<E>,
+    path: &[(AllocatedNum<E>, Boolean)],
+    root: &AllocatedNum<E>,
+    constants: &PoseidonConstants<E, U2>,
+) -> Result<(), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+{
+    let mut current = leaf;
+
+    for (i, (sibling, is_right)) in path.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("level {}", i));
+
+        // (current, sibling) if current is the left child, (sibling, current) if it is the right.
+        let (left, right) = AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "order pair"),
+            &current,
+            sibling,
+            is_right,
+        )?;
+
+        current = poseidon_hash(cs.namespace(|| "hash"), &[left, right], constants)?;
+    }
+
+    cs.enforce(
+        || "computed root equals root",
+        |lc| lc + current.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + root.get_variable(),
+    );
+
+    Ok(())
+}
+
+/// Create circuit inserting `element` among `siblings` at the position given by `index_bits` (least significant bit
+/// first), producing the preimage for one step of an arity-N Merkle path. The siblings keep their order around the
+/// element. Returns `SynthesisError::Unsatisfiable` unless `siblings.len() + 1` is `2^index_bits.len()`.
+///
+/// The index bits are expanded to a one-hot selector per position, costing one constraint for each product of two
+/// or more bits. Each slot then costs a constraint to select the element, and all but the first and last slots one
+/// more to select between their two candidate siblings: 7 constraints at arity 4, 18 at arity 8.
+pub fn insert<CS, E>(
+    mut cs: CS,
+    element: &AllocatedNum<E>,
+    index_bits: &[Boolean],
+    siblings: &[AllocatedNum<E>],
+) -> Result<Vec<AllocatedNum<E>>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+{
+    let size = siblings.len() + 1;
+    if index_bits.len() >= std::mem::size_of::<usize>() * 8 || size != 1 << index_bits.len() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    if siblings.is_empty() {
+        return Ok(vec![element.clone()]);
+    }
+
+    // The product of each subset of the index bits, indexed by the subset's bitmask. The empty product is one.
+    let mut products = Vec::with_capacity(size);
+    products.push(Boolean::Constant(true));
+    for (i, bit) in index_bits.iter().enumerate() {
+        for subset in 0..(1 << i) {
+            let product = if subset == 0 {
+                bit.clone()
+            } else {
+                Boolean::and(
+                    cs.namespace(|| format!("product {}", subset | (1 << i))),
+                    &products[subset],
+                    bit,
+                )?
+            };
+            products.push(product);
+        }
+    }
+
+    // By inclusion-exclusion, the indicator for index k is the alternating sum of the products of its supersets.
+    let selectors = (0..size)
+        .map(|k| {
+            (0..size)
+                .filter(|subset| subset & k == k)
+                .fold(num::Num::<E>::zero(), |acc, subset| {
+                    let mut coeff = E::Fr::one();
+                    if (subset ^ k).count_ones() % 2 == 1 {
+                        coeff.negate();
+                    }
+                    acc.add_bool_with_coeff(CS::one(), &products[subset], coeff)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let value = |n: &AllocatedNum<E>| n.get_value().ok_or(SynthesisError::AssignmentMissing);
+
+    let mut result = Vec::with_capacity(size);
+    // The indicator that the element precedes the current slot.
+    let mut before = num::Num::<E>::zero();
+
+    for (j, at) in selectors.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("slot {}", j));
+
+        // Slot j holds siblings[j] if the element comes after it, and siblings[j - 1] if the element comes before.
+        // The last slot has no siblings[j], so there the element either occupies it or precedes it.
+        let base = if j < size - 1 {
+            &siblings[j]
+        } else {
+            &siblings[j - 1]
+        };
+
+        // before * (siblings[j - 1] - base), only needed when both candidate siblings exist.
+        let shift = if j > 0 && j < size - 1 {
+            let previous = &siblings[j - 1];
+            let shift = AllocatedNum::alloc(cs.namespace(|| "shift"), || {
+                let mut tmp = value(previous)?;
+                tmp.sub_assign(&value(base)?);
+                tmp.mul_assign(
+                    &before
+                        .get_value()
+                        .ok_or(SynthesisError::AssignmentMissing)?,
+                );
+                Ok(tmp)
+            })?;
+
+            cs.enforce(
+                || "shift constraint",
+                |_| before.lc(E::Fr::one()),
+                |lc| lc + previous.get_variable() - base.get_variable(),
+                |lc| lc + shift.get_variable(),
+            );
+            Some(shift)
+        } else {
+            None
+        };
+
+        // base + at * (element - base) + shift
+        let selected = AllocatedNum::alloc(cs.namespace(|| "selected"), || {
+            let mut tmp = value(element)?;
+            tmp.sub_assign(&value(base)?);
+            tmp.mul_assign(&at.get_value().ok_or(SynthesisError::AssignmentMissing)?);
+            tmp.add_assign(&value(base)?);
+            if let Some(shift) = &shift {
+                tmp.add_assign(&value(shift)?);
+            }
+            Ok(tmp)
+        })?;
+
+        cs.enforce(
+            || "selection constraint",
+            |_| at.lc(E::Fr::one()),
+            |lc| lc + element.get_variable() - base.get_variable(),
+            |lc| {
+                let lc = lc + selected.get_variable() - base.get_variable();
+                match &shift {
+                    Some(shift) => lc - shift.get_variable(),
+                    None => lc,
+                }
+            },
+        );
+
+        result.push(selected);
+        before = before.add(at);
+    }
+
+    Ok(result)
+}
+
+/// Initial state for hashing `preimage`: the domain tag of `constants` followed by the preimage.
+fn preimage_elements<CS, E, A>(
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<Vec<Elt<E>>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    // A preimage of the wrong size would either run off the end of the state or leave lanes unset. The constants are
+    // checked once, by `PoseidonCircuit::try_new`, when the state is hashed.
+    if preimage.len() != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    let tag_element = Elt::num_from_fr::<CS>(constants.domain_tag);
+    let mut elements = Vec::with_capacity(constants.width());
+    elements.push(tag_element);
+    elements.extend(preimage.iter().cloned().map(Elt::Allocated));
+
+    Ok(elements)
+}
+
+/// The namespace of round `round`, counting from zero across all rounds: "first round", "initial full round i",
+/// "partial round i", "final full round i" and "terminal full round", with `i` counting within each kind.
+fn round_name(round: usize, half_full_rounds: usize, partial_rounds: usize) -> String {
+    let rounds = 2 * half_full_rounds + partial_rounds;
+    if round == 0 {
+        "first round".to_string()
+    } else if round < half_full_rounds {
+        format!("initial full round {}", round)
+    } else if round < half_full_rounds + partial_rounds {
+        format!("partial round {}", round - half_full_rounds)
+    } else if round + 1 < rounds {
+        format!(
+            "final full round {}",
+            round - half_full_rounds - partial_rounds
+        )
+    } else {
+        "terminal full round".to_string()
+    }
+}
+
+/// Constants which do not match the state, or the state the rounds index into, are a caller error rather than an
+/// unsatisfiable circuit. Report them as invalid input, keeping the message which says what does not match.
+fn invalid_constants(err: Error) -> SynthesisError {
+    SynthesisError::IoError(io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+}
+
+/// The circuit takes its round counts from `constants`, so they must describe the round constants and matrices actually
+/// provided. Otherwise the circuit would silently compute a different function than the scalar hash, or index past the
+/// end of the constants partway through a round.
+fn check_constants<E, A>(constants: &PoseidonConstants<E, A>) -> Result<(), Error>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let width = constants.width();
+    let is_square = |m: &Matrix<E::Fr>| m.len() == width && m.iter().all(|row| row.len() == width);
+
+    if constants.half_full_rounds == 0 || constants.full_rounds != 2 * constants.half_full_rounds {
+        return Err(Error::Other(format!(
+            "full rounds ({}) must be twice half full rounds ({}), which must be positive",
+            constants.full_rounds, constants.half_full_rounds
+        )));
+    }
+    let rounds = constants.full_rounds + constants.partial_rounds;
+    if constants.round_constants.len() != width * rounds {
+        return Err(Error::Other(format!(
+            "expected {} round constants for width {} and {} rounds, got {}",
+            width * rounds,
+            width,
+            rounds,
+            constants.round_constants.len()
+        )));
+    }
+    let compressed = (constants.full_rounds * width) + constants.partial_rounds;
+    if constants.compressed_round_constants.len() != compressed {
+        return Err(Error::Other(format!(
+            "expected {} compressed round constants, got {}",
+            compressed,
+            constants.compressed_round_constants.len()
+        )));
+    }
+    if !is_square(&constants.mds_matrices.m) || !is_square(&constants.pre_sparse_matrix) {
+        return Err(Error::Other(format!(
+            "MDS matrices must be {} x {}",
+            width, width
+        )));
+    }
+    if constants.sparse_matrixes.len() != constants.partial_rounds
+        || constants
+            .sparse_matrixes
+            .iter()
+            .any(|m| m.w_hat.len() != width || m.v_rest.len() != width - 1)
+    {
+        return Err(Error::Other(format!(
+            "expected {} sparse matrices of width {}",
+            constants.partial_rounds, width
+        )));
+    }
+
+    Ok(())
+}
+
+/// Number of constraints `poseidon_hash` adds to a constraint system when hashing with `constants`. Callers composing
+/// larger circuits can use this to budget or pre-size their own constraint systems.
+pub fn poseidon_constraints<E, A>(constants: &PoseidonConstants<E, A>) -> usize
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let arity = A::to_usize();
+    let width = constants.width();
+
+    // The '- 1' term represents the first s-box for the domain tag, which is a constant and needs no constraint.
+    let s_boxes = (width * constants.full_rounds) + constants.partial_rounds - 1;
+    let s_box_constraints = s_box_constraints(constants.alpha) * s_boxes;
+
+    // Every s-box input which is not already allocated must be, except for the preimage elements in the first round.
+    // The extra allocation for the hash result makes up for the constant domain tag, giving '- arity'.
+    let mds_constraints = (width * constants.full_rounds) + constants.partial_rounds - arity;
+
+    s_box_constraints + mds_constraints
+}
+
+/// Digest of the constraints `poseidon_hash` synthesizes with `constants`: every allocation and, in order, every
+/// constraint's A, B and C terms, as variable indices and coefficients. Namespaces and annotations are not included.
+///
+/// Groth16 parameters are only valid for the exact constraint layout they were generated from. Persist this alongside
+/// parameters for a circuit embedding Poseidon, and compare it on load to fail fast when the layout has changed (as it
+/// would if the round counts, round constants or matrices did), rather than with a confusing verification failure.
+pub fn circuit_identity<E, A>(constants: &PoseidonConstants<E, A>) -> [u8; 32]
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let mut cs = IdentityCS::<E>::new();
+    let preimage = (0..A::to_usize())
+        .map(|i| {
+            AllocatedNum::alloc(cs.namespace(|| i.to_string()), || {
+                Err(SynthesisError::AssignmentMissing)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .expect("IdentityCS never assigns values");
+    poseidon_hash(cs.namespace(|| "hash"), &preimage, constants)
+        .expect("poseidon_hash failed without values");
+
+    let mut identity = [0u8; 32];
+    identity.copy_from_slice(cs.state.finalize().as_bytes());
+    identity
+}
+
+/// Constraint system which hashes its structure as it is synthesized, never evaluating assignments.
+struct IdentityCS<E: Engine> {
+    state: blake2s_simd::State,
+    num_inputs: usize,
+    num_aux: usize,
+    _e: PhantomData<E>,
+}
+
+impl<E: Engine> IdentityCS<E> {
+    fn new() -> Self {
+        let mut state = blake2s_simd::State::new();
+        state.update(b"neptune circuit identity");
+
+        IdentityCS {
+            state,
+            // The ONE input.
+            num_inputs: 1,
+            num_aux: 0,
+            _e: PhantomData,
+        }
+    }
+
+    fn update_lc(&mut self, lc: &LinearCombination<E>) {
+        self.state.update(&(lc.iter().count() as u64).to_le_bytes());
+        for (var, coeff) in lc.iter() {
+            let (kind, index) = match var.get_unchecked() {
+                Index::Input(index) => (0u8, index),
+                Index::Aux(index) => (1u8, index),
+            };
+            self.state.update(&[kind]);
+            self.state.update(&(index as u64).to_le_bytes());
+            for limb in coeff.into_repr().as_ref() {
+                self.state.update(&limb.to_le_bytes());
+            }
+        }
+    }
+}
+
+impl<E: Engine> ConstraintSystem<E> for IdentityCS<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _annotation: A, _f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.state.update(b"a");
+        self.num_aux += 1;
+
+        Ok(Variable::new_unchecked(Index::Aux(self.num_aux - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _annotation: A, _f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.state.update(b"i");
+        self.num_inputs += 1;
+
+        Ok(Variable::new_unchecked(Index::Input(self.num_inputs - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _annotation: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        self.state.update(b"c");
+        self.update_lc(&a(LinearCombination::zero()));
+        self.update_lc(&b(LinearCombination::zero()));
+        self.update_lc(&c(LinearCombination::zero()));
+    }
+
+    fn push_namespace<NR, N>(&mut self, _name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// Circuit for a sponge over the Poseidon permutation, for hashing data whose length is not the arity.
+///
+/// The first element of the state (the capacity) holds the `HashType::Sponge` domain tag, and the remaining `arity`
+/// elements are the rate. Elements are absorbed into the rate, permuting whenever it is full. The first squeeze pads
+/// the absorbed data with a single one and permutes; squeezing then reads the rate, permuting whenever it is
+/// exhausted. Absorbing after squeezing permutes first. `sponge::Sponge` computes the same outputs out of circuit.
+pub struct SpongeCircuit<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    elements: Vec<Elt<E>>,
+    pos: usize,
+    squeezing: bool,
+    permutations: usize,
+    squeezes: usize,
+    constants: &'a PoseidonConstants<E, A>,
+}
+
+impl<'a, E, A> SpongeCircuit<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        SpongeCircuit {
+            elements: Vec::new(),
+            pos: 0,
+            squeezing: false,
+            permutations: 0,
+            squeezes: 0,
+            constants,
+        }
+    }
+
+    /// Absorb `element` into the rate, permuting first if the rate is full or the sponge has been squeezed.
+    pub fn absorb<CS: ConstraintSystem<E>>(
+        &mut self,
+        cs: CS,
+        element: &AllocatedNum<E>,
+    ) -> Result<(), SynthesisError> {
+        self.absorb_elt(cs, Elt::Allocated(element.clone()))
+    }
+
+    /// Absorb an element which need not be allocated, such as a linear combination of bits.
+    fn absorb_elt<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        element: Elt<E>,
+    ) -> Result<(), SynthesisError> {
+        self.initialize::<CS>();
+
+        if self.squeezing {
+            self.permute(&mut cs)?;
+            self.squeezing = false;
+        }
+        if self.pos == A::to_usize() {
+            self.permute(&mut cs)?;
+        }
+
+        let i = 1 + self.pos;
+        self.elements[i] = self.elements[i].clone().add::<CS>(element)?;
+        self.pos += 1;
+
+        Ok(())
+    }
+
+    /// Squeeze the next output element, padding and permuting on the first squeeze and whenever the rate is
+    /// exhausted.
+    pub fn squeeze<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+    ) -> Result<AllocatedNum<E>, SynthesisError> {
+        self.initialize::<CS>();
+
+        if !self.squeezing {
+            if self.pos == A::to_usize() {
+                self.permute(&mut cs)?;
+            }
+
+            // Padding with a one ensures that absorbing [a, b] and [a, b, 0] squeeze different outputs.
+            let i = 1 + self.pos;
+            self.elements[i] = self.elements[i]
+                .clone()
+                .add::<CS>(Elt::num_from_fr::<CS>(E::Fr::one()))?;
+            self.permute(&mut cs)?;
+            self.squeezing = true;
+        }
+        if self.pos == A::to_usize() {
+            self.permute(&mut cs)?;
+        }
+
+        let i = 1 + self.pos;
+        let squeezes = self.squeezes;
+        let output = self.elements[i]
+            .ensure_allocated(&mut cs.namespace(|| format!("squeeze {}", squeezes)), true)?;
+        // Keep the allocated output in the state, so the next permutation need not allocate this lane again.
+        self.elements[i] = Elt::Allocated(output.clone());
+        self.pos += 1;
+        self.squeezes += 1;
+
+        Ok(output)
+    }
+
+    /// Set up the initial state: the domain tag followed by zeros. This needs a constraint system type to refer to
+    /// its ONE variable, so it is deferred until the first absorb or squeeze.
+    fn initialize<CS: ConstraintSystem<E>>(&mut self) {
+        if self.elements.is_empty() {
+            self.elements.push(Elt::num_from_fr::<CS>(
+                HashType::Sponge.domain_tag::<E::Fr, A>(),
+            ));
+            for _ in 0..A::to_usize() {
+                self.elements.push(Elt::Num(num::Num::zero()));
+            }
+        }
+    }
+
+    fn permute<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
+        let elements = std::mem::replace(&mut self.elements, Vec::new());
+        let permutations = self.permutations;
+
+        self.elements = permute_elements(
+            cs.namespace(|| format!("permutation {}", permutations)),
+            elements,
+            self.constants,
+        )?;
+        self.permutations += 1;
+        self.pos = 0;
+
+        Ok(())
+    }
+}
+
+/// Circuit for a Fiat–Shamir transcript over `SpongeCircuit`, matching `transcript::Transcript`.
+///
+/// Every operation first absorbs its label, as constants, so labels cost no constraints of their own. `absorb` then
+/// absorbs the element itself, and `challenge` squeezes one allocated element. Each challenge therefore depends on
+/// every label, element and challenge before it, in order.
+pub struct TranscriptCircuit<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    sponge: SpongeCircuit<'a, E, A>,
+    operations: usize,
+}
+
+impl<'a, E, A> TranscriptCircuit<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        TranscriptCircuit {
+            sponge: SpongeCircuit::new(constants),
+            operations: 0,
+        }
+    }
+
+    /// Absorb `element` under `label`.
+    pub fn absorb<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        label: &[u8],
+        element: &AllocatedNum<E>,
+    ) -> Result<(), SynthesisError> {
+        self.absorb_label(&mut cs, transcript::ABSORB, label)?;
+        self.sponge.absorb(
+            cs.namespace(|| format!("element {}", self.operations)),
+            element,
+        )?;
+        self.operations += 1;
+
+        Ok(())
+    }
+
+    /// Squeeze a challenge under `label`.
+    pub fn challenge<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        label: &[u8],
+    ) -> Result<AllocatedNum<E>, SynthesisError> {
+        self.absorb_label(&mut cs, transcript::CHALLENGE, label)?;
+        let challenge = self
+            .sponge
+            .squeeze(cs.namespace(|| format!("challenge {}", self.operations)))?;
+        self.operations += 1;
+
+        Ok(challenge)
+    }
+
+    fn absorb_label<CS: ConstraintSystem<E>>(
+        &mut self,
+        cs: &mut CS,
+        op: u64,
+        label: &[u8],
+    ) -> Result<(), SynthesisError> {
+        let operations = self.operations;
+        for (i, element) in transcript::label_elements::<E::Fr>(op, label)
+            .into_iter()
+            .enumerate()
+        {
+            self.sponge.absorb_elt(
+                cs.namespace(|| format!("label {} {}", operations, i)),
+                Elt::num_from_fr::<CS>(element),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Create circuit hashing `bits`, matching `sponge::hash_bits`.
+///
+/// A single one bit is appended, then the bits are packed little-endian into elements of at most `Fr::CAPACITY` bits,
+/// which are absorbed into a `SpongeCircuit` before squeezing the digest. The padding bit keeps bit strings which differ
+/// only in trailing zeros apart. Packing is linear, so it costs no constraints.
+pub fn poseidon_hash_bits<CS, E, A>(
+    mut cs: CS,
+    bits: &[Boolean],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let mut padded = bits.to_vec();
+    padded.push(Boolean::Constant(true));
+
+    let mut sponge = SpongeCircuit::new(constants);
+    for (i, chunk) in padded.chunks(E::Fr::CAPACITY as usize).enumerate() {
+        let mut packed = num::Num::<E>::zero();
+        let mut coeff = E::Fr::one();
+        for bit in chunk {
+            packed = packed.add_bool_with_coeff(CS::one(), bit, coeff);
+            coeff.double();
+        }
+
+        sponge.absorb_elt(cs.namespace(|| format!("absorb {}", i)), Elt::Num(packed))?;
+    }
+
+    sponge.squeeze(cs.namespace(|| "squeeze"))
+}
+
+/// Create circuit applying the Poseidon permutation to the whole `state`, matching `poseidon::poseidon_permute`.
+///
+/// Unlike `poseidon_hash`, no domain tag is set and every lane of the permuted state is returned, so this is the
+/// building block for custom sponge or duplex modes. Each output lane costs one allocation and one constraint. Returns
+/// `SynthesisError::Unsatisfiable` unless `state` holds exactly `arity + 1` elements.
+pub fn poseidon_permutation<CS, E, A>(
+    mut cs: CS,
+    state: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<Vec<AllocatedNum<E>>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if state.len() != constants.width() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    let elements = state.iter().cloned().map(Elt::Allocated).collect();
+    let permuted = permute_elements(cs.namespace(|| "permutation"), elements, constants)?;
+
+    permuted
+        .iter()
+        .enumerate()
+        .map(|(i, elt)| elt.ensure_allocated(&mut cs.namespace(|| format!("lane {}", i)), true))
+        .collect()
+}
+
+/// Apply the permutation to `elements`, returning the permuted state.
+fn permute_elements<CS, E, A>(
+    cs: CS,
+    elements: Vec<Elt<E>>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<Vec<Elt<E>>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let mut p = PoseidonCircuit::try_new(elements, constants).map_err(invalid_constants)?;
+    p.permute(cs)?;
+
+    Ok(p.elements)
+}
+
+/// Create circuit encrypting `message` under `key` and `nonce`, returning the allocated ciphertext and authentication
+/// tag. These are the outputs of `encryption::encrypt`, which describes the construction.
+pub fn poseidon_encrypt<CS, E, A>(
+    cs: CS,
+    key: &AllocatedNum<E>,
+    nonce: &AllocatedNum<E>,
+    message: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<(Vec<AllocatedNum<E>>, AllocatedNum<E>), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    encryption_circuit(cs, key, nonce, message, None, constants)
+}
+
+/// Create circuit enforcing that `ciphertext` and `tag` are the encryption of `message` under `key` and `nonce`. This
+/// costs no more than `poseidon_encrypt`, since each equality replaces an allocation. Returns
+/// `SynthesisError::Unsatisfiable` if the ciphertext and message lengths differ.
+pub fn poseidon_verify_encryption<CS, E, A>(
+    cs: CS,
+    key: &AllocatedNum<E>,
+    nonce: &AllocatedNum<E>,
+    message: &[AllocatedNum<E>],
+    ciphertext: &[AllocatedNum<E>],
+    tag: &AllocatedNum<E>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<(), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    if ciphertext.len() != message.len() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    encryption_circuit(cs, key, nonce, message, Some((ciphertext, tag)), constants)?;
+
+    Ok(())
+}
+
+/// The encryption duplex. Each output is allocated or, if `expected` is provided, enforced to equal the expected value.
+fn encryption_circuit<CS, E, A>(
+    mut cs: CS,
+    key: &AllocatedNum<E>,
+    nonce: &AllocatedNum<E>,
+    message: &[AllocatedNum<E>],
+    expected: Option<(&[AllocatedNum<E>], &AllocatedNum<E>)>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<(Vec<AllocatedNum<E>>, AllocatedNum<E>), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let rate = A::to_usize();
+
+    let mut elements = Vec::with_capacity(constants.width());
+    elements.push(Elt::num_from_fr::<CS>(
+        HashType::Encryption(message.len()).domain_tag::<E::Fr, A>(),
+    ));
+    elements.push(Elt::Allocated(key.clone()));
+    elements.push(Elt::Allocated(nonce.clone()));
+    elements.resize(constants.width(), Elt::Num(num::Num::zero()));
+
+    elements = permute_elements(cs.namespace(|| "permutation 0"), elements, constants)?;
+
+    let mut ciphertext = Vec::with_capacity(message.len());
+    for (block_index, block) in message.chunks(rate).enumerate() {
+        for (i, m) in block.iter().enumerate() {
+            let index = block_index * rate + i;
+            let sum = elements[1 + i]
+                .clone()
+                .add::<CS>(Elt::Allocated(m.clone()))?;
+
+            let c = allocate_or_enforce(
+                cs.namespace(|| format!("ciphertext {}", index)),
+                &sum,
+                expected.map(|(ciphertext, _)| &ciphertext[index]),
+            )?;
+            // The ciphertext replaces the rate element, and is already allocated for the next permutation.
+            elements[1 + i] = Elt::Allocated(c.clone());
+            ciphertext.push(c);
+        }
+
+        elements = permute_elements(
+            cs.namespace(|| format!("permutation {}", block_index + 1)),
+            elements,
+            constants,
+        )?;
+    }
+
+    let tag = allocate_or_enforce(
+        cs.namespace(|| "tag"),
+        &elements[1],
+        expected.map(|(_, tag)| tag),
+    )?;
+
+    Ok((ciphertext, tag))
+}
+
+/// Allocate `elt`, or if `expected` is provided, enforce that `elt` equals it.
+fn allocate_or_enforce<CS, E>(
+    mut cs: CS,
+    elt: &Elt<E>,
+    expected: Option<&AllocatedNum<E>>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+{
+    match expected {
+        Some(expected) => {
+            cs.enforce(
+                || "equals expected",
+                |_| elt.lc(),
+                |lc| lc + CS::one(),
+                |lc| lc + expected.get_variable(),
+            );
+            Ok(expected.clone())
+        }
+        None => elt.ensure_allocated(&mut cs, true),
+    }
+}
+
+/// Compute l^alpha and enforce constraint. If round_key is supplied, add it to result.
+fn alpha_s_box<CS: ConstraintSystem<E>, E: Engine>(
+    mut cs: CS,
+    e: &Elt<E>,
+    post_round_key: Option<E::Fr>,
+    alpha: Alpha,
+) -> Result<Elt<E>, SynthesisError> {
+    let l = e.ensure_allocated(&mut cs.namespace(|| "S-box input"), true)?;
+
+    // If round_key was supplied, add it after all exponentiation.
+    let l2 = l.square(cs.namespace(|| "l^2"))?;
+    let factor = s_box_factor(cs.namespace(|| "l^(alpha - 1)"), &l2, alpha)?;
+    let result = mul_sum(
+        cs.namespace(|| "(l^(alpha - 1) * l) + rk)"),
+        &factor,
+        &l,
+        None,
+        post_round_key,
+        true,
+    );
+
+    Ok(Elt::Allocated(result?))
+}
+
+/// Compute l^alpha and enforce constraint. If round_key is supplied, add it to l first.
+fn alpha_s_box_pre_add<CS: ConstraintSystem<E>, E: Engine>(
+    mut cs: CS,
+    e: &Elt<E>,
+    pre_round_key: Option<E::Fr>,
+    post_round_key: Option<E::Fr>,
+    alpha: Alpha,
+) -> Result<Elt<E>, SynthesisError> {
+    if let (Some(pre_round_key), Some(post_round_key)) = (pre_round_key, post_round_key) {
+        let l = e.ensure_allocated(&mut cs.namespace(|| "S-box input"), true)?;
+
+        // If round_key was supplied, add it to l before squaring.
+        let l2 = square_sum(cs.namespace(|| "(l+rk)^2"), pre_round_key, &l, true)?;
+        let factor = s_box_factor(cs.namespace(|| "(l+rk)^(alpha - 1)"), &l2, alpha)?;
+        let result = mul_sum(
+            cs.namespace(|| "(l+rk)^(alpha - 1) * (l + rk)"),
+            &factor,
+            &l,
+            Some(pre_round_key),
+            Some(post_round_key),
+            true,
+        );
+
+        Ok(Elt::Allocated(result?))
+    } else {
+        panic!("pre_round_key and post_round_key must both be provided.");
+    }
+}
+
+/// Given l^2, compute l^(alpha - 1) with the shortest multiplication chain: no constraint for alpha 3, one (l^4) for
+/// alpha 5 and two (l^4, then l^4 * l^2) for alpha 7.
+fn s_box_factor<CS: ConstraintSystem<E>, E: Engine>(
+    mut cs: CS,
+    l2: &AllocatedNum<E>,
+    alpha: Alpha,
+) -> Result<AllocatedNum<E>, SynthesisError> {
+    match alpha {
+        Alpha::Three => Ok(l2.clone()),
+        Alpha::Five => l2.square(cs.namespace(|| "l^4")),
+        Alpha::Seven => {
+            let l4 = l2.square(cs.namespace(|| "l^4"))?;
+            l4.mul(cs.namespace(|| "l^6"), l2)
+        }
+    }
+}
+
+/// Number of constraints for one S-box: the square, the chain to l^(alpha - 1), and the final multiplication.
+fn s_box_constraints(alpha: Alpha) -> usize {
+    match alpha {
+        Alpha::Three => 2,
+        Alpha::Five => 3,
+        Alpha::Seven => 4,
+    }
+}
+
+/// Compute l^alpha statically, for an element known when the circuit is built. Adds pre_round_key first.
+fn constant_alpha_s_box_pre_add_tag<CS: ConstraintSystem<E>, E: Engine>(
+    tag: &Elt<E>,
+    pre_round_key: Option<E::Fr>,
+    post_round_key: Option<E::Fr>,
+    alpha: Alpha,
+) -> Elt<E> {
+    let mut tag = tag.val().expect("missing tag val");
+    pre_round_key.expect("pre_round_key must be provided");
+    post_round_key.expect("post_round_key must be provided");
+
+    crate::alpha_s_box::<E>(
+        &mut tag,
+        pre_round_key.as_ref(),
+        post_round_key.as_ref(),
+        alpha,
+    );
+
+    Elt::num_from_fr::<CS>(tag)
+}
+
+/// Calculates square of sum and enforces that constraint.
+pub fn square_sum<CS: ConstraintSystem<E>, E: Engine>(
+    mut cs: CS,
+    to_add: E::Fr,
+    num: &AllocatedNum<E>,
+    enforce: bool,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let res = AllocatedNum::alloc(cs.namespace(|| "squared sum"), || {
+        let mut tmp = num
+            .get_value()
+            .ok_or_else(|| SynthesisError::AssignmentMissing)?;
+        tmp.add_assign(&to_add);
+        tmp.square();
+
+        Ok(tmp)
+    })?;
+
+    if enforce {
+        cs.enforce(
+            || "squared sum constraint",
+            |lc| lc + num.get_variable() + (to_add, CS::one()),
+            |lc| lc + num.get_variable() + (to_add, CS::one()),
+            |lc| lc + res.get_variable(),
+        );
+    }
+    Ok(res)
+}
+
+/// Calculates (a * (pre_add + b)) + post_add — and enforces that constraint.
+pub fn mul_sum<CS: ConstraintSystem<E>, E: Engine>(
+    mut cs: CS,
+    a: &AllocatedNum<E>,
+    b: &AllocatedNum<E>,
+    pre_add: Option<E::Fr>,
+    post_add: Option<E::Fr>,
+    enforce: bool,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let res = AllocatedNum::alloc(cs.namespace(|| "mul_sum"), || {
+        let mut tmp = b
+            .get_value()
+            .ok_or_else(|| SynthesisError::AssignmentMissing)?;
+        if let Some(x) = pre_add {
+            tmp.add_assign(&x);
+        }
+        tmp.mul_assign(
+            &a.get_value()
+                .ok_or_else(|| SynthesisError::AssignmentMissing)?,
+        );
+        if let Some(x) = post_add {
+            tmp.add_assign(&x);
+        }
+
+        Ok(tmp)
+    })?;
+
+    if enforce {
+        if let Some(x) = post_add {
+            let mut neg = E::Fr::zero();
+            neg.sub_assign(&x);
+
+            if let Some(pre) = pre_add {
+                cs.enforce(
+                    || "mul sum constraint pre-post-add",
+                    |lc| lc + b.get_variable() + (pre, CS::one()),
+                    |lc| lc + a.get_variable(),
+                    |lc| lc + res.get_variable() + (neg, CS::one()),
+                );
+            } else {
+                cs.enforce(
+                    || "mul sum constraint post-add",
+                    |lc| lc + b.get_variable(),
+                    |lc| lc + a.get_variable(),
+                    |lc| lc + res.get_variable() + (neg, CS::one()),
+                );
+            }
+        } else {
+            if let Some(pre) = pre_add {
+                cs.enforce(
+                    || "mul sum constraint pre-add",
+                    |lc| lc + b.get_variable() + (pre, CS::one()),
+                    |lc| lc + a.get_variable(),
+                    |lc| lc + res.get_variable(),
+                );
+            } else {
+                cs.enforce(
+                    || "mul sum constraint",
+                    |lc| lc + b.get_variable(),
+                    |lc| lc + a.get_variable(),
+                    |lc| lc + res.get_variable(),
+                );
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Calculates a * (b + to_add) — and enforces that constraint.
+pub fn mul_pre_sum<CS: ConstraintSystem<E>, E: Engine>(
+    mut cs: CS,
+    a: &AllocatedNum<E>,
+    b: &AllocatedNum<E>,
+    to_add: E::Fr,
+    enforce: bool,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+{
+    let res = AllocatedNum::alloc(cs.namespace(|| "mul_sum"), || {
+        let mut tmp = b
+            .get_value()
+            .ok_or_else(|| SynthesisError::AssignmentMissing)?;
+        tmp.add_assign(&to_add);
+        tmp.mul_assign(
+            &a.get_value()
+                .ok_or_else(|| SynthesisError::AssignmentMissing)?,
+        );
+
+        Ok(tmp)
+    })?;
+
+    if enforce {
+        cs.enforce(
+            || "mul sum constraint",
+            |lc| lc + b.get_variable() + (to_add, CS::one()),
+            |lc| lc + a.get_variable(),
+            |lc| lc + res.get_variable(),
+        );
+    }
+    Ok(res)
+}
+
+fn scalar_product_with_add<E: Engine, CS: ConstraintSystem<E>>(
+    elts: &[Elt<E>],
+    scalars: &[E::Fr],
+    to_add: E::Fr,
+) -> Result<Elt<E>, SynthesisError> {
+    let tmp = scalar_product::<E, CS>(elts, scalars)?;
+    let tmp2 = tmp.add::<CS>(Elt::<E>::num_from_fr::<CS>(to_add))?;
+
+    Ok(tmp2)
+}
+
+fn scalar_product<E: Engine, CS: ConstraintSystem<E>>(
+    elts: &[Elt<E>],
+    scalars: &[E::Fr],
+) -> Result<Elt<E>, SynthesisError> {
+    elts.iter()
+        .zip(scalars)
+        .try_fold(Elt::Num(num::Num::zero()), |acc, (elt, &scalar)| {
+            acc.add::<CS>(elt.clone().scale::<CS>(scalar)?)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use crate::poseidon::HashMode;
-    use crate::{scalar_from_u64, Poseidon, Strength};
-    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use crate::sponge::Sponge;
+    use crate::test_cs::TestConstraintSystem;
+    use crate::{scalar_from_u64, MdsError, ParameterError, Poseidon, Strength};
     use bellperson::ConstraintSystem;
+    use generic_array::sequence::GenericSequence;
     use generic_array::typenum;
     use paired::bls12_381::{Bls12, Fr};
-    use rand::SeedableRng;
+    use rand::{Rng, SeedableRng};
     use rand_xorshift::XorShiftRng;
 
     #[test]
-    fn test_poseidon_hash() {
-        test_poseidon_hash_aux::<typenum::U2>(Strength::Standard, 311);
-        test_poseidon_hash_aux::<typenum::U4>(Strength::Standard, 377);
-        test_poseidon_hash_aux::<typenum::U8>(Strength::Standard, 505);
-        test_poseidon_hash_aux::<typenum::U16>(Strength::Standard, 761);
-        test_poseidon_hash_aux::<typenum::U24>(Strength::Standard, 1009);
-        test_poseidon_hash_aux::<typenum::U36>(Strength::Standard, 1385);
+    fn test_poseidon_hash() {
+        test_poseidon_hash_aux::<typenum::U2>(Strength::Standard, 311);
+        test_poseidon_hash_aux::<typenum::U4>(Strength::Standard, 377);
+        test_poseidon_hash_aux::<typenum::U8>(Strength::Standard, 505);
+        test_poseidon_hash_aux::<typenum::U11>(Strength::Standard, 598);
+        test_poseidon_hash_aux::<typenum::U16>(Strength::Standard, 761);
+        test_poseidon_hash_aux::<typenum::U24>(Strength::Standard, 1009);
+        test_poseidon_hash_aux::<typenum::U36>(Strength::Standard, 1385);
+
+        test_poseidon_hash_aux::<typenum::U2>(Strength::Strengthened, 367);
+        test_poseidon_hash_aux::<typenum::U4>(Strength::Strengthened, 433);
+        test_poseidon_hash_aux::<typenum::U8>(Strength::Strengthened, 565);
+        test_poseidon_hash_aux::<typenum::U11>(Strength::Strengthened, 658);
+        test_poseidon_hash_aux::<typenum::U16>(Strength::Strengthened, 821);
+        test_poseidon_hash_aux::<typenum::U24>(Strength::Strengthened, 1069);
+        test_poseidon_hash_aux::<typenum::U36>(Strength::Strengthened, 1445);
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let data = alloc_data(&mut cs, &[fr(0), fr(1)]);
+        poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+
+        let printed = cs.pretty_print();
+        assert_eq!(
+            cs.num_constraints(),
+            printed
+                .lines()
+                .filter(|line| !line.starts_with(' '))
+                .count()
+        );
+
+        // The first s-box of the first preimage lane squares it plus the round key.
+        assert!(printed.contains(
+            "hash/first round/s-box 1/(l+rk)^2/squared sum constraint\n  A: \
+             0x0000000000000000000000000000000000000000000000000000000000000001 * data 0 + 0x"
+        ));
+        assert!(printed.contains("\nhash/partial round 0/solitary s-box/"));
+    }
+
+    #[test]
+    fn test_poseidon_hash_structure() {
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let structure_hash = |preimage: [u64; 2]| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let data = alloc_data(&mut cs, &[fr(preimage[0]), fr(preimage[1])]);
+            poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+            cs.hash()
+        };
+
+        // The structure does not depend on the witness.
+        let hash = structure_hash([1, 2]);
+        assert_eq!(hash, structure_hash([5, 9]));
+
+        assert_eq!(
+            "c1342f4d31170a35d5dc745d5633fd45e5c15472ad6ee5b75decc49864d0471d", hash,
+            "The arity 2 Poseidon constraint structure has changed. Existing Groth16 parameters for circuits using it \
+             are invalid. If the change is intended, update this expectation deliberately."
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_metrics() {
+        use crate::test_cs::Metrics;
+
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let width = constants.width();
+        let half_full_rounds = constants.half_full_rounds;
+        let partial_rounds = constants.partial_rounds;
+        let s_box = s_box_constraints(constants.alpha);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let data = alloc_data(&mut cs, &[fr(0), fr(1)]);
+        poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+
+        let metrics = cs.metrics_by_prefix(2);
+
+        let hash_metrics = metrics
+            .iter()
+            .filter(|(prefix, _)| prefix.starts_with("hash/"))
+            .map(|(prefix, metrics)| (prefix.as_str(), *metrics))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            constants.full_rounds + partial_rounds + 1,
+            hash_metrics.len()
+        );
+
+        for (round, (prefix, metrics)) in hash_metrics.iter().enumerate() {
+            // Each s-box costs the same in allocations as in constraints, plus one of each to allocate its input
+            // after the first round.
+            let s_boxes = if round == 0 {
+                // The domain tag's first s-box is constant.
+                width - 1
+            } else if round < half_full_rounds || round >= half_full_rounds + partial_rounds {
+                width
+            } else {
+                1
+            };
+            let (expected_prefix, expected) = if round == constants.full_rounds + partial_rounds {
+                ("hash/hash result".to_string(), 1)
+            } else {
+                let name = format!(
+                    "hash/{}",
+                    round_name(round, half_full_rounds, partial_rounds)
+                );
+                if round == 0 {
+                    (name, s_boxes * s_box)
+                } else {
+                    (name, s_boxes * (s_box + 1))
+                }
+            };
+
+            assert_eq!(expected_prefix, *prefix);
+            assert_eq!(expected, metrics.constraints, "constraints in {}", prefix);
+            assert_eq!(expected, metrics.aux, "allocations in {}", prefix);
+        }
+
+        let total = metrics
+            .iter()
+            .fold(Metrics::default(), |acc, (_, m)| Metrics {
+                constraints: acc.constraints + m.constraints,
+                aux: acc.aux + m.aux,
+                terms: acc.terms + m.terms,
+            });
+        assert_eq!(cs.num_constraints(), total.constraints);
+        assert_eq!(cs.num_aux(), total.aux);
+        assert_eq!(poseidon_constraints(&constants), total.constraints);
+    }
+
+    #[test]
+    fn test_round_constraints() {
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let width = constants.width();
+        let s_box = s_box_constraints(constants.alpha);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let data = alloc_data(&mut cs, &[fr(0), fr(1)]);
+        poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+
+        // The second full round allocates each lane's MDS output before its s-box. The MDS product itself is linear,
+        // and costs nothing.
+        assert_eq!(
+            width * (s_box + 1),
+            cs.constraints_under("hash/initial full round 1").len()
+        );
+        for lane in 0..width {
+            let prefix = format!("hash/initial full round 1/s-box {}", lane);
+            assert_eq!(s_box + 1, cs.constraints_under(&prefix).len());
+            assert_eq!(
+                1,
+                cs.constraints_under(&format!("{}/S-box input", prefix))
+                    .len()
+            );
+        }
+
+        // A partial round has a single s-box.
+        let partial = cs.constraints_under("hash/partial round 0");
+        assert_eq!(s_box + 1, partial.len());
+        assert!(partial.iter().all(|constraint| constraint
+            .name
+            .starts_with("hash/partial round 0/solitary s-box/")));
+        let input = cs.constraints_under("hash/partial round 0/solitary s-box/S-box input");
+        assert_eq!(1, input.len());
+        // Every lane feeds the sparse matrix product which the s-box input allocates.
+        assert_eq!(width, input[0].a.iter().count());
+
+        // Paths match whole components.
+        assert!(cs
+            .constraints_under("hash/partial round 0/solitary")
+            .is_empty());
+        assert_eq!(cs.num_constraints(), cs.constraints_under("hash").len());
+    }
+
+    #[test]
+    fn test_tampered_digest_is_unsatisfied() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let fr_data = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let data = alloc_data(&mut cs, &fr_data);
+        let digest = poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+        let path = "hash/hash result/allocate for Elt::Num";
+        assert_eq!(digest.get_value().unwrap(), cs.get(path));
+
+        let mut tampered = cs.get(path);
+        tampered.add_assign(&Fr::one());
+        cs.set(path, tampered);
+        assert!(cs
+            .which_is_unsatisfied()
+            .unwrap()
+            .starts_with("hash/hash result/enforce num allocation preserves lc"));
+
+        // So does tampering with an intermediate s-box output, which the next round's constraints depend on.
+        cs.set(path, digest.get_value().unwrap());
+        assert!(cs.is_satisfied());
+        let path = "hash/partial round 1/solitary s-box/(l^(alpha - 1) * l) + rk)/mul_sum";
+        let mut tampered = cs.get(path);
+        tampered.add_assign(&Fr::one());
+        cs.set(path, tampered);
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_strengthened_circuit_matches_scalar() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let standard =
+            PoseidonConstants::<Bls12, typenum::U4>::new_with_strength(Strength::Standard);
+        let strengthened =
+            PoseidonConstants::<Bls12, typenum::U4>::new_with_strength(Strength::Strengthened);
+        let (full_rounds, partial_rounds) = crate::round_numbers(4, &Strength::Strengthened);
+        assert_eq!(
+            strengthened,
+            PoseidonConstants::<Bls12, typenum::U4>::new_with_rounds(full_rounds, partial_rounds)
+        );
+
+        let fr_data = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let circuit_hash = |constants: &PoseidonConstants<Bls12, typenum::U4>| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let data = alloc_data(&mut cs, &fr_data);
+            let out = poseidon_hash(&mut cs, &data, constants).unwrap();
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+            out.get_value().unwrap()
+        };
+        let scalar_hash = |constants: &PoseidonConstants<Bls12, typenum::U4>| {
+            Poseidon::<Bls12, typenum::U4>::new_with_preimage(&fr_data, constants)
+                .hash_in_mode(HashMode::Correct)
+        };
+
+        assert_eq!(scalar_hash(&strengthened), circuit_hash(&strengthened));
+        assert_eq!(scalar_hash(&standard), circuit_hash(&standard));
+        assert_ne!(circuit_hash(&standard), circuit_hash(&strengthened));
+
+        // Round counts which do not describe the constants provided are rejected.
+        let mut inconsistent = strengthened.clone();
+        inconsistent.partial_rounds = standard.partial_rounds;
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let data = alloc_data(&mut cs, &fr_data);
+        match poseidon_hash(&mut cs, &data, &inconsistent) {
+            Err(SynthesisError::IoError(e)) => {
+                assert_eq!(std::io::ErrorKind::InvalidInput, e.kind());
+                assert!(e.to_string().contains("round constants"), "{}", e);
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_cauchy_mds_circuit_matches_scalar() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let default = PoseidonConstants::<Bls12, typenum::U2>::new();
+
+        // The default matrix, given explicitly, gives the default constants.
+        let x = [fr(0), fr(1), fr(2)];
+        let y = [fr(3), fr(4), fr(5)];
+        let m = crate::generate_mds_cauchy::<Bls12>(3, &x, &y).unwrap();
+        assert_eq!(
+            default,
+            PoseidonConstants::<Bls12, typenum::U2>::try_new_with_mds(m).unwrap()
+        );
+
+        let x = [fr(1), fr(2), fr(3)];
+        let y = [fr(7), fr(8), fr(9)];
+        let m = crate::generate_mds_cauchy::<Bls12>(3, &x, &y).unwrap();
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::try_new_with_mds(m).unwrap();
+
+        for _ in 0..10 {
+            let fr_data = [Fr::random(&mut rng), Fr::random(&mut rng)];
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let data = alloc_data(&mut cs, &fr_data);
+            let out = poseidon_hash(&mut cs, &data, &constants).unwrap();
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+            let mut p = Poseidon::new_with_preimage(&fr_data, &constants);
+            assert_eq!(p.hash_in_mode(HashMode::Correct), out.get_value().unwrap());
+            assert_eq!(p.hash_copy(), out.get_value().unwrap());
+            assert_ne!(
+                Poseidon::new_with_preimage(&fr_data, &default).hash(),
+                out.get_value().unwrap()
+            );
+        }
+
+        // The matrix must have the width of the constants, and pass `validate_mds`.
+        let m = crate::generate_mds_cauchy::<Bls12>(2, &x[..2], &y[..2]).unwrap();
+        assert_eq!(
+            Some(ParameterError::InsecureMatrix(MdsError::WrongShape(3))),
+            PoseidonConstants::<Bls12, typenum::U2>::try_new_with_mds(m).err()
+        );
+        assert_eq!(
+            Some(ParameterError::InsecureMatrix(
+                MdsError::InvariantSubspace { rounds: 1 }
+            )),
+            PoseidonConstants::<Bls12, typenum::U2>::try_new_with_mds(
+                crate::matrix::make_identity::<Bls12>(3)
+            )
+            .err()
+        );
+    }
+
+    #[test]
+    fn test_three_constraints_per_s_box() {
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let width = constants.width();
+
+        let allocate = |cs: &mut TestConstraintSystem<Bls12>| {
+            (0..width)
+                .map(|i| {
+                    Elt::Allocated(
+                        AllocatedNum::alloc(cs.namespace(|| format!("elt {}", i)), || {
+                            Ok(fr(i as u64))
+                        })
+                        .unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // First round: round keys are added before the s-box, and the constant tag lane is free.
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let mut elements = allocate(&mut cs);
+            elements[0] = Elt::num_from_fr::<TestConstraintSystem<Bls12>>(constants.domain_tag);
+            let mut p = PoseidonCircuit::try_new(elements, &constants).unwrap();
+            p.full_round(cs.namespace(|| "first round"), true, false)
+                .unwrap();
+            assert_eq!(3 * (width - 1), cs.num_constraints());
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        }
+
+        // Any other full round: three constraints for each of the width s-boxes.
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let elements = allocate(&mut cs);
+            let mut p = PoseidonCircuit::try_new(elements, &constants).unwrap();
+            p.constants_offset = 2 * width;
+            p.current_round = 1;
+            p.full_round(cs.namespace(|| "full round"), false, false)
+                .unwrap();
+            assert_eq!(3 * width, cs.num_constraints());
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        }
+
+        // Partial round: three constraints for the solitary s-box.
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let elements = allocate(&mut cs);
+            let mut p = PoseidonCircuit::try_new(elements, &constants).unwrap();
+            p.constants_offset = constants.half_full_rounds * width + width;
+            p.current_round = constants.half_full_rounds;
+            p.partial_round(cs.namespace(|| "partial round")).unwrap();
+            assert_eq!(3, cs.num_constraints());
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_wrong_preimage_length() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let data = alloc_data(&mut cs, &(0..5).map(fr).collect::<Vec<_>>());
+
+        let too_short = poseidon_hash(cs.namespace(|| "too short"), &data[..3], &constants);
+        assert!(matches!(too_short, Err(SynthesisError::Unsatisfiable)));
+
+        let too_long = poseidon_hash(cs.namespace(|| "too long"), &data, &constants);
+        assert!(matches!(too_long, Err(SynthesisError::Unsatisfiable)));
+
+        assert_eq!(0, cs.num_constraints());
+
+        let just_right = poseidon_hash(cs.namespace(|| "just right"), &data[..4], &constants);
+        assert!(just_right.is_ok());
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+    }
+
+    #[test]
+    fn test_try_new_rejects_malformed_shapes() {
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let width = constants.width();
+        let elements = |n: usize| {
+            (0..n)
+                .map(|i| Elt::num_from_fr::<TestConstraintSystem<Bls12>>(scalar_from_u64(i as u64)))
+                .collect::<Vec<_>>()
+        };
+        let rejects =
+            |constants: &PoseidonConstants<Bls12, typenum::U4>, n: usize, expected: &str| {
+                match PoseidonCircuit::try_new(elements(n), constants) {
+                    Err(Error::Other(msg)) => {
+                        assert!(msg.contains(expected), "unexpected error: {}", msg)
+                    }
+                    Err(e) => panic!("unexpected error: {}", e),
+                    Ok(_) => panic!("malformed shape accepted"),
+                }
+            };
+
+        assert!(PoseidonCircuit::try_new(elements(width), &constants).is_ok());
+
+        rejects(&constants, width - 1, "state elements");
+        rejects(&constants, width + 1, "state elements");
+
+        let mut short_constants = constants.clone();
+        short_constants.round_constants.pop();
+        rejects(&short_constants, width, "round constants");
+
+        let mut short_compressed = constants.clone();
+        short_compressed.compressed_round_constants.pop();
+        rejects(&short_compressed, width, "compressed round constants");
+
+        let mut short_mds = constants.clone();
+        short_mds.mds_matrices.m.pop();
+        rejects(&short_mds, width, "MDS matrices");
+
+        let mut narrow_mds = constants.clone();
+        narrow_mds.mds_matrices.m[0].pop();
+        rejects(&narrow_mds, width, "MDS matrices");
+
+        let mut narrow_pre_sparse = constants.clone();
+        narrow_pre_sparse.pre_sparse_matrix[width - 1].pop();
+        rejects(&narrow_pre_sparse, width, "MDS matrices");
+
+        let mut missing_sparse = constants.clone();
+        missing_sparse.sparse_matrixes.pop();
+        rejects(&missing_sparse, width, "sparse matrices");
+
+        let mut narrow_sparse = constants.clone();
+        narrow_sparse.sparse_matrixes[0].v_rest.pop();
+        rejects(&narrow_sparse, width, "sparse matrices");
+
+        let mut odd_full_rounds = constants.clone();
+        odd_full_rounds.full_rounds += 1;
+        rejects(&odd_full_rounds, width, "full rounds");
+    }
+
+    #[test]
+    fn test_poseidon_hash_domain_tags() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let fr_data = [Fr::random(&mut rng), Fr::random(&mut rng)];
+
+        let digests = [HashType::MerkleTree, HashType::ConstantLength(2)]
+            .iter()
+            .map(|hash_type| {
+                let constants =
+                    PoseidonConstants::<Bls12, typenum::U2>::new_with_hash_type(*hash_type);
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let data = alloc_data(&mut cs, &fr_data);
+
+                let out = poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+                let expected = Poseidon::new_with_preimage(&fr_data, &constants).hash();
+
+                assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+                assert_eq!(expected, out.get_value().unwrap());
+                // The tag is a constant whatever its value, so the cost is unchanged.
+                assert_eq!(poseidon_constraints(&constants), cs.num_constraints());
+                expected
+            })
+            .collect::<Vec<_>>();
+
+        assert_ne!(digests[0], digests[1]);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_poseidon_hash_trace() {
+        use crate::poseidon::trace_divergence;
+
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let width = constants.width();
+        let fr_data = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let circuit_trace = |constants: &PoseidonConstants<Bls12, typenum::U4>| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let data = alloc_data(&mut cs, &fr_data);
+            poseidon_hash_trace(cs.namespace(|| "trace"), &data, constants).unwrap()
+        };
+
+        let trace = circuit_trace(&constants);
+        let scalar_trace = Poseidon::new_with_preimage(&fr_data, &constants).trace();
+        assert_eq!(None, trace_divergence(&scalar_trace, &trace));
+
+        // Corrupting the key of the fourth partial round leaves the earlier rounds intact.
+        let half = constants.half_full_rounds;
+        let mut corrupted = constants.clone();
+        corrupted.compressed_round_constants[(half + 1) * width + 3].add_assign(&Fr::one());
+        assert_eq!(
+            Some(half + 3),
+            trace_divergence(&scalar_trace, &circuit_trace(&corrupted))
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_num() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let fr_data = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let allocate = |cs: &mut TestConstraintSystem<Bls12>| alloc_data(cs, &fr_data);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let data = allocate(&mut cs);
+        let allocated = poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+        assert_eq!(poseidon_constraints(&constants), cs.num_constraints());
+
+        let mut num_cs = TestConstraintSystem::<Bls12>::new();
+        let data = allocate(&mut num_cs);
+        let num = poseidon_hash_num(num_cs.namespace(|| "hash"), &data, &constants).unwrap();
+        assert_eq!(
+            poseidon_constraints(&constants) - 1,
+            num_cs.num_constraints()
+        );
+
+        assert_eq!(allocated.get_value(), num.get_value());
+
+        // Composing the Num into a constraint of our own costs nothing beyond that constraint.
+        let expected = AllocatedNum::alloc(num_cs.namespace(|| "expected"), || {
+            Ok(allocated.get_value().unwrap())
+        })
+        .unwrap();
+        num_cs.enforce(
+            || "digest is expected",
+            |_| num.lc(Fr::one()),
+            |lc| lc + TestConstraintSystem::<Bls12>::one(),
+            |lc| lc + expected.get_variable(),
+        );
+        assert_eq!(poseidon_constraints(&constants), num_cs.num_constraints());
+        assert!(num_cs.is_satisfied(), "{:?}", num_cs.which_is_unsatisfied());
+    }
+
+    #[test]
+    fn test_poseidon_hash_alloc() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let fr_data = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let expected = Poseidon::new_with_preimage(&fr_data, &constants).hash();
+
+        let is_public = [true, false, false, true];
+        let preimage = fr_data
+            .iter()
+            .zip(is_public.iter())
+            .map(|(fr, public)| (Some(*fr), *public))
+            .collect::<Vec<_>>();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let (allocated, digest) =
+            poseidon_hash_alloc(cs.namespace(|| "hash"), &preimage, &constants).unwrap();
+        digest.inputize(cs.namespace(|| "digest")).unwrap();
+
+        assert_eq!(expected, digest.get_value().unwrap());
+        assert_eq!(
+            fr_data,
+            allocated
+                .iter()
+                .map(|a| a.get_value().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(poseidon_constraints(&constants), cs.num_constraints() - 1);
+
+        // The public preimage elements come first, in preimage order, then the digest.
+        assert!(cs.verify(&[fr_data[0], fr_data[3], expected]));
+        assert!(!cs.verify(&[fr_data[3], fr_data[0], expected]));
+        assert!(!cs.verify(&[fr_data[0], fr_data[3]]));
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        assert!(matches!(
+            poseidon_hash_alloc(cs.namespace(|| "short"), &preimage[1..], &constants),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+    }
+
+    #[test]
+    fn test_digest_to_bits() {
+        let num_bits = Fr::NUM_BITS as usize;
+        let minus_one = {
+            let mut x = Fr::zero();
+            x.sub_assign(&Fr::one());
+            x
+        };
+        // 2^255 - p - 1 is the largest value which also fits in 255 bits after adding p, so its naive decomposition
+        // is ambiguous.
+        let ambiguous = {
+            let mut x = Fr::one();
+            for _ in 0..num_bits {
+                x.double();
+            }
+            x.sub_assign(&Fr::one());
+            x
+        };
+
+        for (i, value) in [Fr::zero(), scalar_from_u64(5), ambiguous, minus_one]
+            .iter()
+            .enumerate()
+        {
+            for n_bits in [0, 1, 128, num_bits].iter() {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let digest =
+                    AllocatedNum::alloc(cs.namespace(|| format!("digest {}", i)), || Ok(*value))
+                        .unwrap();
+                let bits = digest_to_bits(cs.namespace(|| "bits"), &digest, *n_bits).unwrap();
+
+                assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+                assert_eq!(
+                    crate::poseidon::digest_to_bits(value, *n_bits).unwrap(),
+                    bits.iter()
+                        .map(|b| b.get_value().unwrap())
+                        .collect::<Vec<_>>()
+                );
+            }
+        }
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let digest = AllocatedNum::alloc(cs.namespace(|| "digest"), || Ok(ambiguous)).unwrap();
+        assert!(digest_to_bits(cs.namespace(|| "too many"), &digest, num_bits + 1).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_to_bits() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let fr_data = [Fr::random(&mut rng), Fr::random(&mut rng)];
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimage = alloc_data(&mut cs, &fr_data);
+        let bits = poseidon_hash_to_bits(cs.namespace(|| "challenge"), &preimage, 128, &constants)
+            .unwrap();
+
+        let digest = Poseidon::new_with_preimage(&fr_data, &constants).hash();
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        assert_eq!(
+            crate::poseidon::digest_to_bits(&digest, 128).unwrap(),
+            bits.iter()
+                .map(|b| b.get_value().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_nums_constant_folds() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let fr_data = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let expected = Poseidon::new_with_preimage(&fr_data, &constants).hash();
+
+        // An all-constant preimage is hashed out of circuit.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimage = fr_data
+            .iter()
+            .map(|fr| constant_num::<TestConstraintSystem<Bls12>, Bls12>(*fr))
+            .collect::<Vec<_>>();
+        let out = poseidon_hash_nums(cs.namespace(|| "constant"), &preimage, &constants).unwrap();
+        assert_eq!(0, cs.num_constraints());
+        assert_eq!(expected, out.get_value().unwrap());
+        assert!(Elt::Num(out).is_constant::<TestConstraintSystem<Bls12>>());
+
+        // A single witness in the preimage means paying for the whole circuit, and the digest still matches.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let mut preimage = preimage;
+        let witness = AllocatedNum::alloc(cs.namespace(|| "witness"), || Ok(fr_data[2])).unwrap();
+        preimage[2] = witness.into();
+        let out = poseidon_hash_nums(cs.namespace(|| "mixed"), &preimage, &constants).unwrap();
+        assert!(cs.num_constraints() > 0);
+        assert_eq!(expected, out.get_value().unwrap());
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+    }
+
+    #[test]
+    fn test_poseidon_hash_conditional() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let fr_data = [Fr::random(&mut rng), Fr::random(&mut rng)];
+        let passthrough_value = Fr::random(&mut rng);
+        let digest = Poseidon::new_with_preimage(&fr_data, &constants).hash();
+
+        for (bit, negate) in [(true, false), (false, false), (true, true), (false, true)].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let preimage = alloc_data(&mut cs, &fr_data);
+            let passthrough =
+                AllocatedNum::alloc(cs.namespace(|| "passthrough"), || Ok(passthrough_value))
+                    .unwrap();
+            // The bit's allocation carries the booleanity constraint; the select relies on it.
+            let allocated = Boolean::from(
+                AllocatedBit::alloc(cs.namespace(|| "condition"), Some(*bit)).unwrap(),
+            );
+            let condition = if *negate { allocated.not() } else { allocated };
+            let constraints_before = cs.num_constraints();
+
+            let out = poseidon_hash_conditional(
+                cs.namespace(|| "conditional"),
+                &condition,
+                &preimage,
+                &passthrough,
+                &constants,
+            )
+            .unwrap();
+
+            let expected = if *bit != *negate {
+                digest
+            } else {
+                passthrough_value
+            };
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+            assert_eq!(expected, out.get_value().unwrap());
+            assert_eq!(
+                poseidon_constraints(&constants),
+                cs.num_constraints() - constraints_before
+            );
+        }
+
+        // A constant condition selects without needing a witness bit.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimage = alloc_data(&mut cs, &fr_data);
+        let out = poseidon_hash_conditional(
+            cs.namespace(|| "constant"),
+            &Boolean::Constant(true),
+            &preimage,
+            &preimage[0],
+            &constants,
+        )
+        .unwrap();
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        assert_eq!(digest, out.get_value().unwrap());
+    }
+
+    #[test]
+    fn test_poseidon_hash_equals() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let fr_data = [Fr::random(&mut rng), Fr::random(&mut rng)];
+        let digest = Poseidon::new_with_preimage(&fr_data, &constants).hash();
+        let other = Fr::random(&mut rng);
+
+        for (claimed_value, expected) in [(digest, true), (other, false)].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let preimage = alloc_data(&mut cs, &fr_data);
+            let claimed =
+                AllocatedNum::alloc(cs.namespace(|| "claimed"), || Ok(*claimed_value)).unwrap();
+
+            let equals =
+                poseidon_hash_equals(cs.namespace(|| "equals"), &preimage, &claimed, &constants)
+                    .unwrap();
+
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+            assert_eq!(Some(*expected), equals.get_value());
+            assert_eq!(poseidon_constraints(&constants) + 2, cs.num_constraints());
+        }
+    }
+
+    #[test]
+    fn test_is_zero_bit_is_sound() {
+        let satisfied = |value: Fr, bit: bool, inverse: Fr| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let value = AllocatedNum::alloc(cs.namespace(|| "value"), || Ok(value)).unwrap();
+            is_zero_bit(
+                cs.namespace(|| "is zero"),
+                &value.into(),
+                Some(bit),
+                Some(inverse),
+            )
+            .unwrap();
+            cs.is_satisfied()
+        };
+        let x = scalar_from_u64::<Fr>(7);
+        let x_inverse = x.inverse().unwrap();
+
+        // The honest witnesses.
+        assert!(satisfied(Fr::zero(), true, Fr::zero()));
+        assert!(satisfied(x, false, x_inverse));
+
+        // Claiming a zero is nonzero, or a nonzero is zero, fails whatever the inverse.
+        for inverse in [Fr::zero(), Fr::one(), x_inverse].iter() {
+            assert!(!satisfied(Fr::zero(), false, *inverse));
+            assert!(!satisfied(x, true, *inverse));
+        }
+        // The right bit with the wrong inverse fails too.
+        assert!(!satisfied(x, false, Fr::one()));
+    }
+
+    #[test]
+    fn test_poseidon_hash_enforce_equal() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let fr_data = [Fr::random(&mut rng), Fr::random(&mut rng)];
+        let digest = Poseidon::<Bls12, typenum::U2>::new_with_preimage(&fr_data, &constants).hash();
+
+        let synthesize = |claimed: Fr| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let expected =
+                AllocatedNum::alloc(cs.namespace(|| "expected"), || Ok(claimed)).unwrap();
+            expected
+                .inputize(cs.namespace(|| "expected input"))
+                .unwrap();
+            let data = alloc_data(&mut cs, &fr_data);
+
+            let before = cs.num_constraints();
+            let out = poseidon_hash_enforce_equal(
+                cs.namespace(|| "poseidon"),
+                &data,
+                &expected,
+                &constants,
+            )
+            .unwrap();
+
+            assert_eq!(Some(digest), out.get_value());
+            assert_eq!(
+                poseidon_constraints(&constants),
+                cs.num_constraints() - before
+            );
+            cs.is_satisfied()
+        };
+
+        assert!(synthesize(digest));
+
+        let mut wrong = digest;
+        wrong.add_assign(&Fr::one());
+        assert!(!synthesize(wrong));
+    }
+
+    #[test]
+    fn test_constants_shared_across_hashes() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+
+        let mut digest =
+            AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(Fr::random(&mut rng))).unwrap();
+        let mut expected = digest.get_value().unwrap();
+
+        for i in 0..5 {
+            let aux_before = cs.num_aux();
+            let sibling_value = Fr::random(&mut rng);
+            let sibling = AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || {
+                Ok(sibling_value)
+            })
+            .unwrap();
+
+            digest = poseidon_hash(
+                cs.namespace(|| format!("level {}", i)),
+                &[digest, sibling],
+                &constants,
+            )
+            .unwrap();
+            expected = Poseidon::<Bls12, typenum::U2>::new_with_preimage(
+                &[expected, sibling_value],
+                &constants,
+            )
+            .hash();
+
+            // Each hash costs only its own state: nothing is allocated for the matrices or round constants.
+            assert_eq!(
+                1 + poseidon_constraints(&constants),
+                cs.num_aux() - aux_before
+            );
+        }
+
+        assert_eq!(expected, digest.get_value().unwrap());
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+    }
+
+    #[test]
+    fn test_poseidon_hash_variable() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let fr_data = (0..5).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        for len in 0..=5 {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let data = alloc_data(&mut cs, &fr_data[..len]);
+
+            let out = poseidon_hash_variable(cs.namespace(|| "hash"), &data, &constants);
+            let expected = crate::poseidon::hash_variable(&constants, &fr_data[..len]);
+
+            if len > 4 {
+                assert!(out.is_err());
+                assert!(expected.is_err());
+            } else {
+                assert!(
+                    cs.is_satisfied(),
+                    "constraints not satisfied for length {}: {:?}",
+                    len,
+                    cs.which_is_unsatisfied()
+                );
+                assert_eq!(expected.unwrap(), out.unwrap().get_value().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_multi() {
+        test_poseidon_hash_multi_aux::<typenum::U2>();
+        test_poseidon_hash_multi_aux::<typenum::U8>();
+    }
+
+    fn test_poseidon_hash_multi_aux<A>()
+    where
+        A: Arity<Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let arity = A::to_usize();
+
+        let mut digests = Vec::new();
+        for len in [0, 1, arity, arity + 1, 3 * arity].iter() {
+            let fr_data = (0..*len).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let data = alloc_data(&mut cs, &fr_data);
+
+            let out = poseidon_hash_multi(cs.namespace(|| "hash"), &data, &constants).unwrap();
+            let expected = crate::poseidon::hash_multi(&constants, &fr_data);
+
+            assert!(
+                cs.is_satisfied(),
+                "constraints not satisfied for length {}: {:?}",
+                len,
+                cs.which_is_unsatisfied()
+            );
+            assert_eq!(
+                expected,
+                out.get_value().unwrap(),
+                "mismatch for length {}",
+                len
+            );
+            digests.push(expected);
+
+            // Trailing zeros are bound by the length.
+            let mut padded = fr_data.clone();
+            padded.push(Fr::zero());
+            assert_ne!(expected, crate::poseidon::hash_multi(&constants, &padded));
+        }
+
+        for (i, a) in digests.iter().enumerate() {
+            for b in digests.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_prf() {
+        test_poseidon_prf_aux::<typenum::U2>();
+        test_poseidon_prf_aux::<typenum::U4>();
+    }
+
+    fn test_poseidon_prf_aux<A>()
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let arity = A::to_usize();
+
+        let key_value = Fr::random(&mut rng);
+        let input_values = (1..arity).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let key = AllocatedNum::alloc(cs.namespace(|| "key"), || Ok(key_value)).unwrap();
+        let inputs = input_values
+            .iter()
+            .enumerate()
+            .map(|(i, fr)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || Ok(*fr)).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let out = poseidon_prf(cs.namespace(|| "prf"), &key, &inputs, &constants).unwrap();
+        let expected = crate::poseidon::prf(&constants, key_value, &input_values).unwrap();
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        assert_eq!(expected, out.get_value().unwrap());
+
+        // The same field elements, hashed plainly, must give something else.
+        let mut preimage = vec![key.clone()];
+        preimage.extend(inputs.iter().cloned());
+        let plain = poseidon_hash(cs.namespace(|| "hash"), &preimage, &constants).unwrap();
+        assert_ne!(out.get_value(), plain.get_value());
+
+        assert!(poseidon_prf(cs.namespace(|| "too many"), &key, &preimage, &constants).is_err());
+        assert!(crate::poseidon::prf(&constants, key_value, &[]).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_with_salt() {
+        test_poseidon_hash_with_salt_aux::<typenum::U2>();
+        test_poseidon_hash_with_salt_aux::<typenum::U4>();
+    }
+
+    fn test_poseidon_hash_with_salt_aux<A>()
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let arity = A::to_usize();
+
+        let salt_value = Fr::random(&mut rng);
+        let preimage_values = (0..arity).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let salt = AllocatedNum::alloc(cs.namespace(|| "salt"), || Ok(salt_value)).unwrap();
+        let preimage = preimage_values
+            .iter()
+            .enumerate()
+            .map(|(i, fr)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("preimage {}", i)), || Ok(*fr)).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let out = poseidon_hash_with_salt(cs.namespace(|| "salted"), &salt, &preimage, &constants)
+            .unwrap();
+        let expected =
+            crate::poseidon::hash_with_salt(&constants, salt_value, &preimage_values).unwrap();
+        assert_eq!(expected, out.get_value().unwrap());
+        cs.assert_all_aux_constrained();
+
+        assert!(poseidon_hash_with_salt(
+            cs.namespace(|| "too short"),
+            &salt,
+            &preimage[1..],
+            &constants
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_poseidon_encrypt() {
+        test_poseidon_encrypt_aux::<typenum::U2>();
+        test_poseidon_encrypt_aux::<typenum::U4>();
+    }
+
+    fn test_poseidon_encrypt_aux<A>()
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+
+        for len in 1..=8 {
+            let key_value = Fr::random(&mut rng);
+            let nonce_value = Fr::random(&mut rng);
+            let message_values = (0..len).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+            let (expected_ciphertext, expected_tag) =
+                crate::encryption::encrypt(&constants, key_value, nonce_value, &message_values);
+
+            let alloc = |cs: &mut TestConstraintSystem<Bls12>, name: &str, values: &[Fr]| {
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, fr)| {
+                        AllocatedNum::alloc(cs.namespace(|| format!("{} {}", name, i)), || Ok(*fr))
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let key = alloc(&mut cs, "key", &[key_value]).pop().unwrap();
+            let nonce = alloc(&mut cs, "nonce", &[nonce_value]).pop().unwrap();
+            let message = alloc(&mut cs, "message", &message_values);
+
+            let (ciphertext, tag) = poseidon_encrypt(
+                cs.namespace(|| "encrypt"),
+                &key,
+                &nonce,
+                &message,
+                &constants,
+            )
+            .unwrap();
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+            assert_eq!(
+                expected_ciphertext,
+                ciphertext
+                    .iter()
+                    .map(|c| c.get_value().unwrap())
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(expected_tag, tag.get_value().unwrap());
+
+            // Verifying a provided ciphertext costs the same as producing one.
+            let verify = |ciphertext_values: &[Fr], tag_value: Fr| {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let key = alloc(&mut cs, "key", &[key_value]).pop().unwrap();
+                let nonce = alloc(&mut cs, "nonce", &[nonce_value]).pop().unwrap();
+                let message = alloc(&mut cs, "message", &message_values);
+                let ciphertext = alloc(&mut cs, "ciphertext", ciphertext_values);
+                let tag = alloc(&mut cs, "tag", &[tag_value]).pop().unwrap();
+                let before = cs.num_constraints();
+
+                poseidon_verify_encryption(
+                    cs.namespace(|| "verify"),
+                    &key,
+                    &nonce,
+                    &message,
+                    &ciphertext,
+                    &tag,
+                    &constants,
+                )
+                .unwrap();
+                (cs.is_satisfied(), cs.num_constraints() - before)
+            };
+
+            assert_eq!(
+                (true, cs.num_constraints()),
+                verify(&expected_ciphertext, expected_tag)
+            );
+
+            let mut wrong_tag = expected_tag;
+            wrong_tag.add_assign(&Fr::one());
+            assert!(!verify(&expected_ciphertext, wrong_tag).0);
+
+            let mut wrong_ciphertext = expected_ciphertext.clone();
+            wrong_ciphertext[0].add_assign(&Fr::one());
+            assert!(!verify(&wrong_ciphertext, expected_tag).0);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_bits() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let capacity = Fr::CAPACITY as usize;
+
+        let mut digests = Vec::new();
+        for len in [
+            0,
+            1,
+            2,
+            capacity - 1,
+            capacity,
+            capacity + 1,
+            3 * capacity + 7,
+        ]
+        .iter()
+        {
+            let bit_values = (0..*len).map(|_| rng.gen::<bool>()).collect::<Vec<_>>();
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let bits = bit_values
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    Boolean::from(
+                        AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), Some(*b))
+                            .unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let out = poseidon_hash_bits(cs.namespace(|| "hash"), &bits, &constants).unwrap();
+            let expected = crate::sponge::hash_bits(&constants, &bit_values);
+            assert!(
+                cs.is_satisfied(),
+                "constraints not satisfied for {} bits: {:?}",
+                len,
+                cs.which_is_unsatisfied()
+            );
+            assert_eq!(
+                expected,
+                out.get_value().unwrap(),
+                "mismatch for {} bits",
+                len
+            );
+
+            digests.push(expected);
+        }
+
+        // Trailing zeros are significant.
+        assert_ne!(
+            crate::sponge::hash_bits(&constants, &[true]),
+            crate::sponge::hash_bits(&constants, &[true, false])
+        );
+        for (i, a) in digests.iter().enumerate() {
+            for b in digests.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_permutation() {
+        test_poseidon_permutation_aux::<typenum::U2>();
+        test_poseidon_permutation_aux::<typenum::U4>();
+        test_poseidon_permutation_aux::<typenum::U8>();
+    }
+
+    fn test_poseidon_permutation_aux<A>()
+    where
+        A: Arity<Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let width = constants.width();
+
+        let mut expected = GenericArray::<Fr, A::ConstantsSize>::generate(|_| Fr::random(&mut rng));
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let state = expected
+            .iter()
+            .enumerate()
+            .map(|(i, fr)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("state {}", i)), || Ok(*fr)).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let permuted =
+            poseidon_permutation(cs.namespace(|| "permute"), &state, &constants).unwrap();
+        crate::poseidon::poseidon_permute(&constants, &mut expected);
+
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        assert_eq!(width, permuted.len());
+        // Compare every lane, not only the digest lane.
+        for (i, (expected, actual)) in expected.iter().zip(permuted.iter()).enumerate() {
+            assert_eq!(*expected, actual.get_value().unwrap(), "lane {} differs", i);
+        }
+        // No tag lane is constant here, so the first round has a full set of s-boxes, and every lane is allocated.
+        assert_eq!(
+            poseidon_constraints(&constants) + s_box_constraints(constants.alpha) + width - 1,
+            cs.num_constraints()
+        );
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        assert!(matches!(
+            poseidon_permutation(cs.namespace(|| "short"), &state[1..], &constants),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+    }
+
+    #[test]
+    fn test_transcript_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let x = Fr::random(&mut rng);
+        let y = Fr::random(&mut rng);
+
+        // Absorb the labelled elements in the given order, then squeeze three challenges, both in and out of circuit.
+        let run = |absorbs: &[(&[u8], Fr)]| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let mut transcript = TranscriptCircuit::new(&constants);
+            let mut expected = crate::transcript::Transcript::new(&constants);
+
+            for (i, (label, value)) in absorbs.iter().enumerate() {
+                let element =
+                    AllocatedNum::alloc(cs.namespace(|| format!("value {}", i)), || Ok(*value))
+                        .unwrap();
+                transcript
+                    .absorb(cs.namespace(|| format!("absorb {}", i)), label, &element)
+                    .unwrap();
+                expected.absorb(label, *value);
+            }
+
+            let challenges = (0..3)
+                .map(|i| {
+                    let challenge = transcript
+                        .challenge(cs.namespace(|| format!("challenge {}", i)), b"challenge")
+                        .unwrap()
+                        .get_value()
+                        .unwrap();
+                    assert_eq!(expected.challenge(b"challenge"), challenge);
+                    challenge
+                })
+                .collect::<Vec<_>>();
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+            challenges
+        };
+
+        let (x_label, y_label): (&[u8], &[u8]) = (b"x", b"y");
+        let in_order = run(&[(x_label, x), (y_label, y)]);
+        let reordered = run(&[(y_label, y), (x_label, x)]);
+
+        for (a, b) in in_order.iter().zip(reordered.iter()) {
+            assert_ne!(a, b);
+        }
+        // The labels matter too: swapping only the values changes every challenge.
+        let relabelled = run(&[(x_label, y), (y_label, x)]);
+        for (a, b) in in_order.iter().zip(relabelled.iter()) {
+            assert_ne!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_fixed_size_hashes() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let fr_data = (0..8).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let data = alloc_data(&mut cs, &fr_data);
+
+        let out2 = poseidon_hash2(cs.namespace(|| "hash2"), &data[0], &data[1]).unwrap();
+        let out4 = poseidon_hash4(
+            cs.namespace(|| "hash4"),
+            &[
+                data[0].clone(),
+                data[1].clone(),
+                data[2].clone(),
+                data[3].clone(),
+            ],
+        )
+        .unwrap();
+        let out8 = poseidon_hash8(
+            cs.namespace(|| "hash8"),
+            &[
+                data[0].clone(),
+                data[1].clone(),
+                data[2].clone(),
+                data[3].clone(),
+                data[4].clone(),
+                data[5].clone(),
+                data[6].clone(),
+                data[7].clone(),
+            ],
+        )
+        .unwrap();
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+        let mut preimage8 = [Fr::zero(); 8];
+        preimage8.copy_from_slice(&fr_data);
+        assert_eq!(
+            crate::poseidon::hash2(fr_data[0], fr_data[1]),
+            out2.get_value().unwrap()
+        );
+        assert_eq!(
+            crate::poseidon::hash4([fr_data[0], fr_data[1], fr_data[2], fr_data[3]]),
+            out4.get_value().unwrap()
+        );
+        assert_eq!(crate::poseidon::hash8(preimage8), out8.get_value().unwrap());
+    }
+
+    #[test]
+    fn test_mixed_arities() {
+        use crate::poseidon::{POSEIDON_CONSTANTS_11, POSEIDON_CONSTANTS_8};
+
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let mut alloc = |cs: &mut TestConstraintSystem<Bls12>, name: &str, n: usize| {
+            (0..n)
+                .map(|i| {
+                    let fr = Fr::random(&mut rng);
+                    AllocatedNum::alloc(cs.namespace(|| format!("{} {}", name, i)), || Ok(fr))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // An arity 11 column hash feeding an arity 8 tree node, with both sets of constants live at once.
+        let column = alloc(&mut cs, "column", 11);
+        let mut node = alloc(&mut cs, "node", 7);
+        let column_digest = poseidon_hash(
+            cs.namespace(|| "column hash"),
+            &column,
+            &*POSEIDON_CONSTANTS_11,
+        )
+        .unwrap();
+        node.push(column_digest.clone());
+        let node_digest =
+            poseidon_hash(cs.namespace(|| "node hash"), &node, &*POSEIDON_CONSTANTS_8).unwrap();
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+        let values = |nums: &[AllocatedNum<Bls12>]| {
+            nums.iter()
+                .map(|num| num.get_value().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let expected_column =
+            Poseidon::new_with_preimage(&values(&column), &*POSEIDON_CONSTANTS_11).hash();
+        assert_eq!(expected_column, column_digest.get_value().unwrap());
+        assert_eq!(
+            Poseidon::new_with_preimage(&values(&node), &*POSEIDON_CONSTANTS_8).hash(),
+            node_digest.get_value().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_batch() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimages = (0..5)
+            .map(|i| {
+                (0..4)
+                    .map(|j| {
+                        let value = Fr::random(&mut rng);
+                        AllocatedNum::alloc(cs.namespace(|| format!("data {} {}", i, j)), || {
+                            Ok(value)
+                        })
+                        .unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let digests =
+            poseidon_hash_batch(cs.namespace(|| "batch"), &preimages, &constants).unwrap();
+        assert_eq!(preimages.len(), digests.len());
+        assert_eq!(
+            preimages.len() * poseidon_constraints(&constants),
+            cs.num_constraints()
+        );
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+        for (preimage, digest) in preimages.iter().zip(digests.iter()) {
+            let fr_data = preimage
+                .iter()
+                .map(|n| n.get_value().unwrap())
+                .collect::<Vec<_>>();
+            let expected =
+                Poseidon::<Bls12, typenum::U4>::new_with_preimage(&fr_data, &constants).hash();
+            assert_eq!(expected, digest.get_value().unwrap());
+        }
+
+        assert!(
+            poseidon_hash_batch(cs.namespace(|| "empty"), &[], &constants)
+                .unwrap()
+                .is_empty()
+        );
+
+        // One bad preimage rejects the whole batch before anything is synthesized.
+        let constraints = cs.num_constraints();
+        let mut bad = preimages.clone();
+        bad[3].pop();
+        assert!(poseidon_hash_batch(cs.namespace(|| "bad"), &bad, &constants).is_err());
+        assert_eq!(constraints, cs.num_constraints());
+    }
+
+    #[test]
+    fn test_poseidon_hash_witness() {
+        test_poseidon_hash_witness_aux(&PoseidonConstants::<Bls12, typenum::U2>::new());
+        test_poseidon_hash_witness_aux(&PoseidonConstants::<Bls12, typenum::U4>::new_with_alpha(
+            Strength::Standard,
+            Alpha::Seven,
+        ));
+        test_poseidon_hash_witness_aux(&PoseidonConstants::<Bls12, typenum::U8>::new_with_alpha(
+            Strength::Strengthened,
+            Alpha::Seven,
+        ));
+        test_poseidon_hash_witness_aux(&PoseidonConstants::<Bls12, typenum::U11>::new());
+    }
 
-        test_poseidon_hash_aux::<typenum::U2>(Strength::Strengthened, 367);
-        test_poseidon_hash_aux::<typenum::U4>(Strength::Strengthened, 433);
-        test_poseidon_hash_aux::<typenum::U8>(Strength::Strengthened, 565);
-        test_poseidon_hash_aux::<typenum::U16>(Strength::Strengthened, 821);
-        test_poseidon_hash_aux::<typenum::U24>(Strength::Strengthened, 1069);
-        test_poseidon_hash_aux::<typenum::U36>(Strength::Strengthened, 1445);
+    fn test_poseidon_hash_witness_aux<A: Arity<Fr>>(constants: &PoseidonConstants<Bls12, A>) {
+        use crate::witness_cs::WitnessCS;
+
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let fr_data = (0..A::to_usize())
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            cs: &mut CS,
+            fr_data: &[Fr],
+            hash: impl FnOnce(&mut CS, &[AllocatedNum<Bls12>]) -> AllocatedNum<Bls12>,
+        ) {
+            let data = alloc_data(cs, fr_data);
+            hash(&mut *cs, &data)
+                .inputize(cs.namespace(|| "digest"))
+                .unwrap();
+        }
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        synthesize(&mut cs, &fr_data, |cs, data| {
+            poseidon_hash(cs.namespace(|| "hash"), data, constants).unwrap()
+        });
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+        let mut witness_cs = WitnessCS::<Bls12>::new();
+        synthesize(&mut witness_cs, &fr_data, |cs, data| {
+            poseidon_hash_witness(cs.namespace(|| "hash"), data, constants).unwrap()
+        });
+
+        let expected = Poseidon::new_with_preimage(&fr_data, constants).hash();
+        assert_eq!(&[Fr::one(), expected], witness_cs.input_assignment());
+        assert!(cs.is_satisfied_by(witness_cs.input_assignment(), witness_cs.aux_assignment()));
+
+        let mut corrupted = witness_cs.aux_assignment().to_vec();
+        corrupted[A::to_usize() + 1].add_assign(&Fr::one());
+        assert!(!cs.is_satisfied_by(witness_cs.input_assignment(), &corrupted));
+    }
+
+    #[test]
+    fn test_poseidon_hash_random_preimages() {
+        test_poseidon_hash_random_preimages_aux::<typenum::U2>();
+        test_poseidon_hash_random_preimages_aux::<typenum::U8>();
+        test_poseidon_hash_random_preimages_aux::<typenum::U11>();
+    }
+
+    /// The sparse-matrix partial rounds must agree with the unoptimized scalar hash for every preimage.
+    fn test_poseidon_hash_random_preimages_aux<A>()
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+
+        for n in 0..10 {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let fr_data = (0..A::to_usize())
+                .map(|_| Fr::random(&mut rng))
+                .collect::<Vec<_>>();
+            let data = alloc_data(&mut cs, &fr_data);
+
+            let out = poseidon_hash(&mut cs, &data, &constants).expect("poseidon hashing failed");
+
+            let mut p = Poseidon::<Bls12, A>::new_with_preimage(&fr_data, &constants);
+            let expected: Fr = p.hash_in_mode(HashMode::Correct);
+
+            assert!(
+                cs.is_satisfied(),
+                "constraints not satisfied: {:?}",
+                cs.which_is_unsatisfied()
+            );
+            assert_eq!(
+                expected,
+                out.get_value().unwrap(),
+                "circuit and non-circuit do not match for preimage {}",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_merkle_path() {
+        test_verify_merkle_path_aux(1);
+        test_verify_merkle_path_aux(20);
+    }
+
+    fn test_verify_merkle_path_aux(depth: usize) {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+
+        let leaf = Fr::random(&mut rng);
+        let siblings = (0..depth).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let directions = (0..depth).map(|i| i % 3 == 1).collect::<Vec<_>>();
+
+        let mut root = leaf;
+        for (sibling, is_right) in siblings.iter().zip(directions.iter()) {
+            let pair = if *is_right {
+                [*sibling, root]
+            } else {
+                [root, *sibling]
+            };
+            root = Poseidon::<Bls12, typenum::U2>::new_with_preimage(&pair, &constants).hash();
+        }
+
+        let synthesize = |directions: &[bool]| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let leaf = AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(leaf)).unwrap();
+            let root = AllocatedNum::alloc(cs.namespace(|| "root"), || Ok(root)).unwrap();
+            let path = siblings
+                .iter()
+                .zip(directions.iter())
+                .enumerate()
+                .map(|(i, (sibling, is_right))| {
+                    let sibling =
+                        AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || {
+                            Ok(*sibling)
+                        })
+                        .unwrap();
+                    let is_right = Boolean::from(
+                        AllocatedBit::alloc(
+                            cs.namespace(|| format!("direction {}", i)),
+                            Some(*is_right),
+                        )
+                        .unwrap(),
+                    );
+                    (sibling, is_right)
+                })
+                .collect::<Vec<_>>();
+
+            verify_merkle_path(&mut cs, leaf, &path, &root, &constants).unwrap();
+            cs.is_satisfied()
+        };
+
+        assert!(synthesize(&directions), "valid path not satisfied");
+
+        let mut flipped = directions.clone();
+        flipped[depth - 1] = !flipped[depth - 1];
+        assert!(!synthesize(&flipped), "flipped direction satisfied");
+    }
+
+    #[test]
+    fn test_insert() {
+        test_insert_aux::<typenum::U4>(7);
+        test_insert_aux::<typenum::U8>(18);
+    }
+
+    fn test_insert_aux<A>(expected_constraints: usize)
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let arity = A::to_usize();
+        let bits = arity.trailing_zeros() as usize;
+
+        let element = Fr::random(&mut rng);
+        let siblings = (0..arity - 1)
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        // Synthesize a path step for `index`, returning the inserted preimage and the constraints insertion cost,
+        // and enforcing that the digest is `expected`.
+        let synthesize = |cs: &mut TestConstraintSystem<Bls12>, index: usize, expected: Fr| {
+            let element = AllocatedNum::alloc(cs.namespace(|| "element"), || Ok(element)).unwrap();
+            let siblings = siblings
+                .iter()
+                .enumerate()
+                .map(|(i, sibling)| {
+                    AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || Ok(*sibling))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            let index_bits = (0..bits)
+                .map(|i| {
+                    Boolean::from(
+                        AllocatedBit::alloc(
+                            cs.namespace(|| format!("index bit {}", i)),
+                            Some((index >> i) & 1 == 1),
+                        )
+                        .unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let expected =
+                AllocatedNum::alloc(cs.namespace(|| "expected"), || Ok(expected)).unwrap();
+
+            let constraints_before = cs.num_constraints();
+            let preimage =
+                insert(cs.namespace(|| "insert"), &element, &index_bits, &siblings).unwrap();
+            let insert_constraints = cs.num_constraints() - constraints_before;
+
+            poseidon_hash_enforce_equal(cs.namespace(|| "hash"), &preimage, &expected, &constants)
+                .unwrap();
+
+            (
+                preimage
+                    .iter()
+                    .map(|n| n.get_value().unwrap())
+                    .collect::<Vec<_>>(),
+                insert_constraints,
+            )
+        };
+
+        for index in 0..arity {
+            let mut expected_preimage = siblings.clone();
+            expected_preimage.insert(index, element);
+            let digest =
+                Poseidon::<Bls12, A>::new_with_preimage(&expected_preimage, &constants).hash();
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let (preimage, insert_constraints) = synthesize(&mut cs, index, digest);
+            assert_eq!(
+                expected_preimage, preimage,
+                "wrong preimage for index {}",
+                index
+            );
+            assert_eq!(expected_constraints, insert_constraints);
+            assert!(
+                cs.is_satisfied(),
+                "constraints not satisfied for index {}: {:?}",
+                index,
+                cs.which_is_unsatisfied()
+            );
+
+            // Claiming the same digest with any other index must fail.
+            for wrong_index in (0..arity).filter(|i| *i != index) {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                synthesize(&mut cs, wrong_index, digest);
+                assert!(
+                    !cs.is_satisfied(),
+                    "index {} satisfied digest for index {}",
+                    wrong_index,
+                    index
+                );
+            }
+        }
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let element = AllocatedNum::alloc(cs.namespace(|| "element"), || Ok(element)).unwrap();
+        assert!(insert(&mut cs, &element, &[Boolean::Constant(true)], &[]).is_err());
+    }
+
+    #[test]
+    fn test_sponge_circuit() {
+        test_sponge_circuit_aux::<typenum::U2>();
+        test_sponge_circuit_aux::<typenum::U4>();
+    }
+
+    /// The circuit sponge must squeeze exactly what the scalar sponge does, for absorb/squeeze sequences which fill,
+    /// overflow and fall short of the rate, and which absorb again after squeezing.
+    fn test_sponge_circuit_aux<A>()
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let rate = A::to_usize();
+
+        // Alternating absorb and squeeze counts.
+        let schedules = [
+            vec![0, 1],
+            vec![1, 1],
+            vec![rate, 2 * rate + 1],
+            vec![rate + 1, 1, 2, 3],
+            vec![3 * rate - 1, rate, 1, rate],
+        ];
+
+        for schedule in schedules.iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let mut sponge = Sponge::new(&constants);
+            let mut sponge_circuit = SpongeCircuit::new(&constants);
+            let mut absorbed = 0;
+            let mut squeezed = 0;
+
+            for (step, count) in schedule.iter().enumerate() {
+                for _ in 0..*count {
+                    if step % 2 == 0 {
+                        let x = Fr::random(&mut rng);
+                        let allocated =
+                            AllocatedNum::alloc(cs.namespace(|| format!("x {}", absorbed)), || {
+                                Ok(x)
+                            })
+                            .unwrap();
+
+                        sponge.absorb(x);
+                        sponge_circuit
+                            .absorb(cs.namespace(|| format!("absorb {}", absorbed)), &allocated)
+                            .unwrap();
+                        absorbed += 1;
+                    } else {
+                        let output = sponge_circuit
+                            .squeeze(cs.namespace(|| format!("squeeze {}", squeezed)))
+                            .unwrap();
+
+                        assert_eq!(
+                            sponge.squeeze(),
+                            output.get_value().unwrap(),
+                            "circuit and non-circuit sponge outputs differ at squeeze {} of {:?}",
+                            squeezed,
+                            schedule
+                        );
+                        squeezed += 1;
+                    }
+                }
+            }
+
+            assert!(
+                cs.is_satisfied(),
+                "constraints not satisfied: {:?}",
+                cs.which_is_unsatisfied()
+            );
+        }
     }
 
     fn test_poseidon_hash_aux<A>(strength: Strength, expected_constraints: usize)
@@ -620,37 +4040,20 @@ mod tests {
         let arity = A::to_usize();
         let constants = PoseidonConstants::<Bls12, A>::new_with_strength(strength);
 
-        let expected_constraints_calculated = {
-            let arity_tag_constraints = 0;
-            let width = 1 + arity;
-            // The '- 1' term represents the first s-box for the arity tag, which is a constant and needs no constraint.
-            let s_boxes = (width * constants.full_rounds) + constants.partial_rounds - 1;
-            let s_box_constraints = 3 * s_boxes;
-            let mds_constraints =
-                (width * constants.full_rounds) + constants.partial_rounds - arity;
-            let total_constraints = arity_tag_constraints + s_box_constraints + mds_constraints;
-
-            total_constraints
-        };
-        let mut i = 0;
-
-        let mut fr_data = vec![Fr::zero(); arity];
-        let data: Vec<AllocatedNum<Bls12>> = (0..arity)
-            .enumerate()
-            .map(|_| {
-                let fr = Fr::random(&mut rng);
-                fr_data[i] = fr;
-                i += 1;
-                AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || Ok(fr)).unwrap()
-            })
-            .collect::<Vec<_>>();
+        let expected_constraints_calculated = poseidon_constraints(&constants);
+        let fr_data = (0..arity).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let data = alloc_data(&mut cs, &fr_data);
 
-        let out = poseidon_hash(&mut cs, data, &constants).expect("poseidon hashing failed");
+        let out = poseidon_hash(&mut cs, &data, &constants).expect("poseidon hashing failed");
 
         let mut p = Poseidon::<Bls12, A>::new_with_preimage(&fr_data, &constants);
         let expected: Fr = p.hash_in_mode(HashMode::Correct);
 
-        assert!(cs.is_satisfied(), "constraints not satisfied");
+        assert!(
+            cs.is_satisfied(),
+            "constraints not satisfied: {:?}",
+            cs.which_is_unsatisfied()
+        );
 
         assert_eq!(
             expected,
@@ -669,12 +4072,277 @@ mod tests {
             cs.num_constraints(),
             "constraint number changed",
         );
+
+        // Besides the preimage, the only auxiliary variables are those bound by a constraint.
+        assert_eq!(
+            arity + expected_constraints,
+            cs.num_aux(),
+            "auxiliary variable number changed",
+        );
+
+        // A verifier is given the digest as the only public input.
+        out.inputize(cs.namespace(|| "digest")).unwrap();
+        assert!(cs.verify(&[expected]), "correct digest rejected");
+
+        let mut wrong_digest = expected;
+        wrong_digest.add_assign(&Fr::one());
+        assert!(!cs.verify(&[wrong_digest]), "wrong digest accepted");
+    }
+
+    #[test]
+    fn test_product_mds_is_linear() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let width = constants.width();
+
+        let values = (0..width).map(|i| fr(i as u64 + 1)).collect::<Vec<_>>();
+        let elements = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                Elt::Allocated(
+                    AllocatedNum::alloc(cs.namespace(|| format!("elt {}", i)), || Ok(*v)).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut p = PoseidonCircuit::try_new(elements, &constants).unwrap();
+        p.product_mds::<TestConstraintSystem<Bls12>>().unwrap();
+
+        // The MDS entries are constants, so the product is purely linear and needs no constraints.
+        assert_eq!(0, cs.num_constraints());
+
+        let m = &constants.mds_matrices.m;
+        for (j, elt) in p.elements.iter().enumerate() {
+            let mut expected = Fr::zero();
+            for (i, v) in values.iter().enumerate() {
+                let mut tmp = m[i][j];
+                tmp.mul_assign(v);
+                expected.add_assign(&tmp);
+            }
+            assert!(elt.is_num());
+            assert_eq!(expected, elt.val().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_alpha_s_box_constraints() {
+        for (alpha, expected_constraints) in
+            [(Alpha::Three, 2), (Alpha::Five, 3), (Alpha::Seven, 4)].iter()
+        {
+            let round_key = fr(7);
+            let mut expected = fr(3);
+            crate::alpha_s_box::<Bls12>(&mut expected, None, Some(&round_key), *alpha);
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let x = AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(fr(3))).unwrap();
+            let res = alpha_s_box(
+                cs.namespace(|| "s-box"),
+                &Elt::Allocated(x),
+                Some(round_key),
+                *alpha,
+            )
+            .unwrap();
+            assert_eq!(*expected_constraints, cs.num_constraints());
+            assert_eq!(*expected_constraints, s_box_constraints(*alpha));
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+            assert_eq!(expected, res.val().unwrap());
+
+            let mut expected = fr(3);
+            crate::alpha_s_box::<Bls12>(&mut expected, Some(&round_key), Some(&round_key), *alpha);
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let x = AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(fr(3))).unwrap();
+            let res = alpha_s_box_pre_add(
+                cs.namespace(|| "s-box"),
+                &Elt::Allocated(x),
+                Some(round_key),
+                Some(round_key),
+                *alpha,
+            )
+            .unwrap();
+            assert_eq!(*expected_constraints, cs.num_constraints());
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+            assert_eq!(expected, res.val().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_alpha() {
+        // Standard U2 is 311 constraints with alpha 5: one constraint per s-box more or less for 7 or 3.
+        test_poseidon_hash_alpha_aux::<typenum::U2>(Alpha::Three, 311 - 78);
+        test_poseidon_hash_alpha_aux::<typenum::U2>(Alpha::Five, 311);
+        test_poseidon_hash_alpha_aux::<typenum::U2>(Alpha::Seven, 311 + 78);
+        test_poseidon_hash_alpha_aux::<typenum::U8>(Alpha::Three, 505 - 128);
+        test_poseidon_hash_alpha_aux::<typenum::U8>(Alpha::Seven, 505 + 128);
+    }
+
+    /// The circuit must agree with the scalar hash for every S-box exponent.
+    fn test_poseidon_hash_alpha_aux<A>(alpha: Alpha, expected_constraints: usize)
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let default_constants = PoseidonConstants::<Bls12, A>::new();
+        let constants = if alpha == Alpha::Three {
+            // x^3 is not a permutation of this field, so no constants are generated for it, but the gadgets still
+            // compute it. The round constants do not depend on the exponent.
+            let mut constants = default_constants.clone();
+            constants.alpha = alpha;
+            constants
+        } else {
+            PoseidonConstants::<Bls12, A>::new_with_alpha(Strength::Standard, alpha)
+        };
+
+        for n in 0..3 {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let fr_data = (0..A::to_usize())
+                .map(|_| Fr::random(&mut rng))
+                .collect::<Vec<_>>();
+            let data = alloc_data(&mut cs, &fr_data);
+
+            let out = poseidon_hash(cs.namespace(|| "hash"), &data, &constants).unwrap();
+
+            let mut p = Poseidon::<Bls12, A>::new_with_preimage(&fr_data, &constants);
+            let expected = p.hash_in_mode(HashMode::Correct);
+            let mut p = Poseidon::<Bls12, A>::new_with_preimage(&fr_data, &constants);
+            assert_eq!(expected, p.hash());
+
+            assert!(
+                cs.is_satisfied(),
+                "constraints not satisfied: {:?}",
+                cs.which_is_unsatisfied()
+            );
+            assert_eq!(
+                expected,
+                out.get_value().unwrap(),
+                "mismatch for preimage {}",
+                n
+            );
+            assert_eq!(expected_constraints, cs.num_constraints());
+            assert_eq!(expected_constraints, poseidon_constraints(&constants));
+
+            if alpha != Alpha::Five {
+                let mut p = Poseidon::<Bls12, A>::new_with_preimage(&fr_data, &default_constants);
+                assert_ne!(expected, p.hash());
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_key_folded_into_s_box() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let x = AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(fr(3))).unwrap();
+        let round_key = fr(7);
+
+        let res = alpha_s_box(
+            cs.namespace(|| "s-box"),
+            &Elt::Allocated(x),
+            Some(round_key),
+            Alpha::Five,
+        )
+        .unwrap();
+
+        // Adding the round key must not cost a constraint of its own.
+        assert_eq!(3, cs.num_constraints());
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+        assert_eq!(fr(3 * 3 * 3 * 3 * 3 + 7), res.val().unwrap());
+    }
+
+    #[test]
+    fn test_domain_tag_is_not_a_witness() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+
+        let tag = Elt::num_from_fr::<TestConstraintSystem<Bls12>>(constants.domain_tag);
+        let keys = &constants.compressed_round_constants;
+        let res = constant_alpha_s_box_pre_add_tag::<TestConstraintSystem<Bls12>, Bls12>(
+            &tag,
+            Some(keys[0]),
+            Some(keys[constants.width()]),
+            constants.alpha,
+        );
+
+        // The tag, and its first s-box, only ever refer to the constant one variable: there is nothing to tamper with.
+        assert_eq!(0, cs.num_constraints());
+        assert!(res.is_num());
+        res.lc().iter().for_each(|(var, _)| {
+            assert_eq!(
+                TestConstraintSystem::<Bls12>::one().get_unchecked(),
+                var.get_unchecked()
+            );
+        });
+
+        let mut expected = constants.domain_tag;
+        crate::quintic_s_box::<Bls12>(
+            &mut expected,
+            Some(&keys[0]),
+            Some(&keys[constants.width()]),
+        );
+        assert_eq!(expected, res.val().unwrap());
+
+        // Hashing allocates nothing for the tag.
+        let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(fr(1))).unwrap();
+        let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(fr(2))).unwrap();
+        poseidon_hash(cs.namespace(|| "hash"), &[a, b], &constants).unwrap();
+        assert_eq!(1, cs.num_inputs());
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+    }
+
+    #[test]
+    fn test_all_aux_constrained() {
+        test_all_aux_constrained_aux::<typenum::U2>();
+        test_all_aux_constrained_aux::<typenum::U4>();
+        test_all_aux_constrained_aux::<typenum::U8>();
+    }
+
+    /// No witness of the hash, or of a sponge built on it, may be left for the prover to choose.
+    fn test_all_aux_constrained_aux<A>()
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let arity = A::to_usize();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimage = (0..arity)
+            .map(|i| {
+                let x = Fr::random(&mut rng);
+                AllocatedNum::alloc(cs.namespace(|| format!("preimage {}", i)), || Ok(x)).unwrap()
+            })
+            .collect::<Vec<_>>();
+        poseidon_hash(cs.namespace(|| "hash"), &preimage, &constants).unwrap();
+
+        let mut sponge = SpongeCircuit::new(&constants);
+        for (i, x) in preimage.iter().enumerate() {
+            sponge
+                .absorb(cs.namespace(|| format!("absorb {}", i)), x)
+                .unwrap();
+        }
+        sponge.squeeze(cs.namespace(|| "squeeze")).unwrap();
+
+        cs.assert_all_aux_constrained();
     }
 
     fn fr(n: u64) -> <Bls12 as Engine>::Fr {
         scalar_from_u64::<<Bls12 as Engine>::Fr>(n)
     }
 
+    /// Allocate each of `values`, in turn, under the namespace "data i".
+    fn alloc_data<CS: ConstraintSystem<Bls12>>(
+        cs: &mut CS,
+        values: &[Fr],
+    ) -> Vec<AllocatedNum<Bls12>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || Ok(*value)).unwrap()
+            })
+            .collect()
+    }
+
     fn efr(n: u64) -> Elt<Bls12> {
         Elt::num_from_fr::<TestConstraintSystem<Bls12>>(fr(n))
     }
@@ -742,7 +4410,7 @@ mod tests {
             });
 
             res.ensure_allocated(&mut cs, true).unwrap();
-            assert!(cs.is_satisfied());
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
         }
         {
             let mut cs = TestConstraintSystem::<Bls12>::new();
@@ -802,10 +4470,31 @@ mod tests {
             let v = allocated.get_value().unwrap();
             assert_eq!(fr(452), v); // (7 * 56) + (8 * 3) + (9 * 4) = 448
 
-            assert!(cs.is_satisfied());
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
         }
     }
 
+    #[test]
+    fn test_add_defers_allocation() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let n3 = AllocatedNum::alloc(cs.namespace(|| "three"), || Ok(fr(3))).unwrap();
+        let n4 = AllocatedNum::alloc(cs.namespace(|| "four"), || Ok(fr(4))).unwrap();
+
+        let res = Elt::Allocated(n3)
+            .add::<TestConstraintSystem<Bls12>>(efr(5))
+            .unwrap()
+            .add::<TestConstraintSystem<Bls12>>(Elt::Allocated(n4))
+            .unwrap();
+
+        assert!(res.is_num());
+        assert_eq!(fr(12), res.val().unwrap());
+        assert_eq!(0, cs.num_constraints());
+
+        let allocated = res.ensure_allocated(&mut cs, true).unwrap();
+        assert_eq!(fr(12), allocated.get_value().unwrap());
+        assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+    }
+
     #[test]
     fn test_scalar_product_with_add() {
         let two = efr(2);
@@ -822,4 +4511,100 @@ mod tests {
         assert!(res.is_num());
         assert_eq!(fr(59), res.val().unwrap());
     }
+
+    /// Runs the gadgets with no witness values, as during parameter generation.
+    struct SetupCircuit<'a> {
+        constants: &'a PoseidonConstants<Bls12, typenum::U2>,
+    }
+
+    impl<'a> bellperson::Circuit<Bls12> for SetupCircuit<'a> {
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            let alloc = |cs: &mut CS, name: &str| {
+                AllocatedNum::alloc(cs.namespace(|| name), || {
+                    Err(SynthesisError::AssignmentMissing)
+                })
+            };
+            let a = alloc(cs, "a")?;
+            let b = alloc(cs, "b")?;
+            let bit = Boolean::from(AllocatedBit::alloc(cs.namespace(|| "bit"), None)?);
+
+            let hash = poseidon_hash(
+                cs.namespace(|| "hash"),
+                &[a.clone(), b.clone()],
+                self.constants,
+            )?;
+            poseidon_hash_variable(cs.namespace(|| "variable"), &[a.clone()], self.constants)?;
+            poseidon_prf(cs.namespace(|| "prf"), &a, &[b.clone()], self.constants)?;
+            poseidon_hash_bits(cs.namespace(|| "bits"), &[bit.clone()], self.constants)?;
+            poseidon_encrypt(
+                cs.namespace(|| "encrypt"),
+                &a,
+                &b,
+                &[hash.clone(), a.clone(), b.clone()],
+                self.constants,
+            )?;
+            insert(cs.namespace(|| "insert"), &a, &[bit.clone()], &[b.clone()])?;
+
+            let mut sponge = SpongeCircuit::new(self.constants);
+            sponge.absorb(cs.namespace(|| "absorb"), &hash)?;
+            let squeezed = sponge.squeeze(cs.namespace(|| "squeeze"))?;
+            verify_merkle_path(
+                cs.namespace(|| "merkle path"),
+                a,
+                &[(b, bit)],
+                &squeezed,
+                self.constants,
+            )?;
+
+            squeezed.inputize(cs.namespace(|| "output"))
+        }
+    }
+
+    #[test]
+    fn test_circuit_identity() {
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let identity = circuit_identity(&constants);
+        assert_eq!(
+            identity,
+            circuit_identity(&PoseidonConstants::<Bls12, typenum::U4>::new())
+        );
+
+        assert_ne!(
+            identity,
+            circuit_identity(&PoseidonConstants::<Bls12, typenum::U2>::new())
+        );
+        assert_ne!(
+            identity,
+            circuit_identity(&PoseidonConstants::<Bls12, typenum::U4>::new_with_rounds(
+                constants.full_rounds,
+                constants.partial_rounds + 1
+            ))
+        );
+
+        let mut changed_matrix = constants.clone();
+        changed_matrix.mds_matrices.m[1][2].add_assign(&Fr::one());
+        assert_ne!(identity, circuit_identity(&changed_matrix));
+
+        let mut changed_key = constants.clone();
+        changed_key.compressed_round_constants[0].add_assign(&Fr::one());
+        assert_ne!(identity, circuit_identity(&changed_key));
+    }
+
+    #[test]
+    fn test_gadgets_without_assignment() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        // Reduced rounds keep parameter generation fast.
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new_with_rounds(2, 1);
+
+        bellperson::groth16::generate_random_parameters::<Bls12, _, _>(
+            SetupCircuit {
+                constants: &constants,
+            },
+            &mut rng,
+        )
+        .expect("parameter generation failed");
+    }
 }
@@ -0,0 +1,135 @@
+use crate::circuit::poseidon_hash;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::groth16::{self, Parameters, Proof};
+use bellperson::{Circuit, ConstraintSystem, SynthesisError};
+use paired::bls12_381::{Bls12, Fr};
+use rand_core::RngCore;
+
+/// A circuit proving knowledge of a preimage of a public Poseidon digest. The preimage is private and the digest
+/// is the only public input.
+///
+/// This is a template for wiring the Poseidon gadget into Groth16 proofs: `generate_parameters`, `prove` and
+/// `verify` show the whole round trip.
+pub struct PoseidonDemoCircuit<'a, A>
+where
+    A: Arity<Fr>,
+{
+    preimage: Vec<Option<Fr>>,
+    constants: &'a PoseidonConstants<Bls12, A>,
+}
+
+impl<'a, A> PoseidonDemoCircuit<'a, A>
+where
+    A: Arity<Fr>,
+{
+    /// Create a circuit with a known `preimage`, for proving.
+    pub fn new(preimage: &[Fr], constants: &'a PoseidonConstants<Bls12, A>) -> Self {
+        PoseidonDemoCircuit {
+            preimage: preimage.iter().map(|x| Some(*x)).collect(),
+            constants,
+        }
+    }
+
+    /// Create a circuit with no assignment, for parameter generation.
+    pub fn blank(constants: &'a PoseidonConstants<Bls12, A>) -> Self {
+        PoseidonDemoCircuit {
+            preimage: vec![None; A::to_usize()],
+            constants,
+        }
+    }
+}
+
+impl<'a, A> Circuit<Bls12> for PoseidonDemoCircuit<'a, A>
+where
+    A: Arity<Fr>,
+{
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let preimage = self
+            .preimage
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("preimage {}", i)), || {
+                    x.ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let digest = poseidon_hash(cs.namespace(|| "poseidon hash"), &preimage, self.constants)?;
+
+        digest.inputize(cs.namespace(|| "digest"))
+    }
+}
+
+/// Generate Groth16 parameters for `PoseidonDemoCircuit` with `constants`.
+pub fn generate_parameters<A, R>(
+    constants: &PoseidonConstants<Bls12, A>,
+    rng: &mut R,
+) -> Result<Parameters<Bls12>, SynthesisError>
+where
+    A: Arity<Fr>,
+    R: RngCore,
+{
+    groth16::generate_random_parameters::<Bls12, _, _>(PoseidonDemoCircuit::blank(constants), rng)
+}
+
+/// Prove knowledge of `preimage`. Returns the proof and the digest it proves, which the verifier needs.
+pub fn prove<A, R>(
+    params: &Parameters<Bls12>,
+    constants: &PoseidonConstants<Bls12, A>,
+    preimage: &[Fr],
+    rng: &mut R,
+) -> Result<(Proof<Bls12>, Fr), SynthesisError>
+where
+    A: Arity<Fr>,
+    R: RngCore,
+{
+    if preimage.len() != A::to_usize() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    let digest = Poseidon::new_with_preimage(preimage, constants).hash();
+
+    let proof =
+        groth16::create_random_proof(PoseidonDemoCircuit::new(preimage, constants), params, rng)?;
+
+    Ok((proof, digest))
+}
+
+/// Verify that `proof` shows knowledge of a preimage of `digest`.
+pub fn verify(
+    params: &Parameters<Bls12>,
+    proof: &Proof<Bls12>,
+    digest: &Fr,
+) -> Result<bool, SynthesisError> {
+    let pvk = groth16::prepare_verifying_key(&params.vk);
+
+    groth16::verify_proof(&pvk, proof, &[*digest])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::Field;
+    use generic_array::typenum;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_groth16_round_trip() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        // Reduced rounds keep parameter generation and proving fast. These constants are NOT secure.
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new_with_rounds(2, 1);
+
+        let params = generate_parameters(&constants, &mut rng).unwrap();
+
+        let preimage = [Fr::random(&mut rng), Fr::random(&mut rng)];
+        let (proof, digest) = prove(&params, &constants, &preimage, &mut rng).unwrap();
+
+        assert!(verify(&params, &proof, &digest).unwrap());
+
+        let mut wrong_digest = digest;
+        wrong_digest.add_assign(&Fr::one());
+        assert!(!verify(&params, &proof, &wrong_digest).unwrap());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::{constant_time_eq, Error};
+use ff::{Field, ScalarEngine};
+
+/// Encrypt `message` under `key` and `nonce`, returning the ciphertext and an authentication tag.
+///
+/// The state starts as the `HashType::Encryption` tag for the message length, then `key`, then `nonce`, with any
+/// remaining elements zero, and is permuted. The message is processed in blocks of `arity` elements: each ciphertext
+/// element is the message element plus the corresponding rate element, and replaces it. The state is permuted after
+/// every block. The tag is the first rate element of the final state. `circuit::poseidon_encrypt` computes the same
+/// outputs in a circuit.
+///
+/// A nonce must never be reused with the same key.
+pub fn encrypt<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    key: E::Fr,
+    nonce: E::Fr,
+    message: &[E::Fr],
+) -> (Vec<E::Fr>, E::Fr)
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = initial_state(constants, key, nonce, message.len());
+
+    let mut ciphertext = Vec::with_capacity(message.len());
+    for block in message.chunks(A::to_usize()) {
+        for (i, m) in block.iter().enumerate() {
+            p.elements[1 + i].add_assign(m);
+            ciphertext.push(p.elements[1 + i]);
+        }
+        p.permute();
+    }
+
+    (ciphertext, p.elements[1])
+}
+
+/// Decrypt `ciphertext` produced by `encrypt` with the same `key` and `nonce`. Returns `Error::AuthenticationFailed`,
+/// and no message, if `tag` does not match.
+pub fn decrypt<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    key: E::Fr,
+    nonce: E::Fr,
+    ciphertext: &[E::Fr],
+    tag: &E::Fr,
+) -> Result<Vec<E::Fr>, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = initial_state(constants, key, nonce, ciphertext.len());
+
+    let mut message = Vec::with_capacity(ciphertext.len());
+    for block in ciphertext.chunks(A::to_usize()) {
+        for (i, c) in block.iter().enumerate() {
+            let mut m = *c;
+            m.sub_assign(&p.elements[1 + i]);
+            message.push(m);
+            p.elements[1 + i] = *c;
+        }
+        p.permute();
+    }
+
+    if constant_time_eq(&p.elements[1], tag) {
+        Ok(message)
+    } else {
+        Err(Error::AuthenticationFailed)
+    }
+}
+
+fn initial_state<'a, E, A>(
+    constants: &'a PoseidonConstants<E, A>,
+    key: E::Fr,
+    nonce: E::Fr,
+    len: usize,
+) -> Poseidon<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = Poseidon::new(constants);
+    p.elements[0] = HashType::Encryption(len).domain_tag::<E::Fr, A>();
+    p.elements[1] = key;
+    p.elements[2] = nonce;
+    p.permute();
+
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let key = Fr::random(&mut rng);
+        let nonce = Fr::random(&mut rng);
+
+        for len in 0..8 {
+            let message = (0..len).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+            let (ciphertext, tag) = encrypt(&constants, key, nonce, &message);
+            assert_eq!(message.len(), ciphertext.len());
+
+            assert_eq!(
+                message,
+                decrypt(&constants, key, nonce, &ciphertext, &tag).unwrap()
+            );
+
+            let mut wrong_key = key;
+            wrong_key.add_assign(&Fr::one());
+            assert!(decrypt(&constants, wrong_key, nonce, &ciphertext, &tag).is_err());
+
+            let mut wrong_nonce = nonce;
+            wrong_nonce.add_assign(&Fr::one());
+            assert!(decrypt(&constants, key, wrong_nonce, &ciphertext, &tag).is_err());
+
+            if len > 0 {
+                let mut tampered = ciphertext.clone();
+                tampered[len - 1].add_assign(&Fr::one());
+                assert!(decrypt(&constants, key, nonce, &tampered, &tag).is_err());
+
+                // The length is part of the initial state, so truncating the ciphertext invalidates the tag.
+                assert!(decrypt(&constants, key, nonce, &ciphertext[..len - 1], &tag).is_err());
+            }
+        }
+    }
+}
@@ -5,8 +5,8 @@ use std::{error, fmt};
 #[derive(Debug, Clone)]
 /// Possible error states for the hashing.
 pub enum Error {
-    /// The allowed number of leaves cannot be greater than the arity of the tree.
-    FullBuffer,
+    /// The allowed number of leaves cannot be greater than the arity of the tree, which is given.
+    FullBuffer(usize),
     /// Attempt to reference an index element that is out of bounds
     IndexOutOfBounds,
     /// The provided leaf was not found in the tree
@@ -14,9 +14,98 @@ pub enum Error {
     #[cfg(feature = "gpu")]
     ClError(cl::ClError),
     DecodingError,
+    /// The authentication tag does not match the ciphertext, so decryption failed
+    AuthenticationFailed,
     Other(String),
 }
 
+/// Reasons Poseidon constants cannot be generated for a set of parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterError {
+    /// Full rounds must be positive and even, as they are split evenly around the partial rounds, and partial rounds
+    /// must be positive.
+    InvalidRounds {
+        full_rounds: usize,
+        partial_rounds: usize,
+    },
+    /// The width is below two, the smallest from which the sparse matrices can be derived.
+    InvalidWidth(usize),
+    /// The S-box exponent shares a factor with p - 1, so x^alpha is not a permutation of the field.
+    InvalidAlpha(u64),
+    /// The MDS matrix, or its minor used by the optimized rounds, is not invertible.
+    SingularMatrix,
+    /// Fewer round constants were generated than the rounds consume.
+    NotEnoughRoundConstants { needed: usize, generated: usize },
+    /// No MDS matrix generated for the width passed `validate_mds`; the error is that of the last one tried.
+    InsecureMatrix(MdsError),
+}
+
+/// Reasons `validate_mds` rejects an MDS matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MdsError {
+    /// The matrix is not square with the given width.
+    WrongShape(usize),
+    /// The matrix is not invertible.
+    Singular,
+    /// Some nonzero subspace of states never activates the partial-round S-box when the matrix is applied `rounds`
+    /// rounds at a time, so a subspace trail passes through any number of partial rounds.
+    InvariantSubspace { rounds: usize },
+}
+
+impl error::Error for MdsError {}
+
+impl fmt::Display for MdsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            MdsError::WrongShape(width) => write!(f, "MDS matrix is not {0}x{0}", width),
+            MdsError::Singular => write!(f, "MDS matrix is not invertible"),
+            MdsError::InvariantSubspace { rounds } => write!(
+                f,
+                "MDS matrix admits an infinitely long subspace trail over {} round(s)",
+                rounds
+            ),
+        }
+    }
+}
+
+impl From<MdsError> for ParameterError {
+    fn from(e: MdsError) -> Self {
+        ParameterError::InsecureMatrix(e)
+    }
+}
+
+impl error::Error for ParameterError {}
+
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ParameterError::InvalidRounds {
+                full_rounds,
+                partial_rounds,
+            } => write!(
+                f,
+                "full_rounds ({}) must be positive and even, and partial_rounds ({}) must be positive",
+                full_rounds, partial_rounds
+            ),
+            ParameterError::InvalidWidth(width) => {
+                write!(f, "width ({}) must be at least 2", width)
+            }
+            ParameterError::InvalidAlpha(alpha) => write!(
+                f,
+                "alpha ({}) must be coprime to p - 1 for x^alpha to be a permutation",
+                alpha
+            ),
+            ParameterError::SingularMatrix => write!(f, "MDS matrix is not invertible"),
+            ParameterError::NotEnoughRoundConstants { needed, generated } => write!(
+                f,
+                "not enough round constants: {} needed, {} generated",
+                needed, generated
+            ),
+            ParameterError::InsecureMatrix(e) => write!(f, "no secure MDS matrix found: {}", e),
+        }
+    }
+}
+
 #[cfg(feature = "gpu")]
 impl From<cl::ClError> for Error {
     fn from(e: cl::ClError) -> Self {
@@ -29,15 +118,17 @@ impl error::Error for Error {}
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            Error::FullBuffer => write!(
+            Error::FullBuffer(arity) => write!(
                 f,
-                "The size of the buffer cannot be greater than the hash arity."
+                "The size of the buffer cannot be greater than the hash arity ({}).",
+                arity
             ),
             Error::IndexOutOfBounds => write!(f, "The referenced index is outs of bounds."),
             Error::GPUError(s) => write!(f, "GPU Error: {}", s),
             #[cfg(feature = "gpu")]
             Error::ClError(e) => write!(f, "OpenCL Error: {}", e),
             Error::DecodingError => write!(f, "PrimeFieldDecodingError"),
+            Error::AuthenticationFailed => write!(f, "Authentication tag mismatch."),
             Error::Other(s) => write!(f, "{}", s),
         }
     }
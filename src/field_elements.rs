@@ -0,0 +1,190 @@
+use crate::poseidon::{hash_multi, POSEIDON_CONSTANTS_8};
+use crate::{scalar_from_u64, scalar_from_u64s, Scalar};
+use ff::Field;
+
+/// Derive `ToFieldElements` for a struct, encoding its fields in declaration order as a tuple of them would be. A
+/// field marked `#[neptune(skip)]` is left out, and one marked `#[neptune(with = "path")]` is encoded by calling
+/// `path(&field, out)`.
+#[cfg(feature = "derive")]
+pub use neptune_derive::ToFieldElements;
+
+/// Values which encode as a sequence of field elements, so that application types can be hashed with `hash_object`
+/// and every implementation agrees on their digests. The encodings are:
+///
+/// | Type                | Elements                                                                             |
+/// |---------------------|--------------------------------------------------------------------------------------|
+/// | `Fr`                | the element itself                                                                   |
+/// | `u64`               | the element equal to the value                                                       |
+/// | `bool`              | one for `true`, zero for `false`                                                     |
+/// | `[u8; 32]`          | bytes 0 to 15, then bytes 16 to 31, each read as a little-endian 128-bit integer     |
+/// | `[T]`, `Vec<T>`     | the number of items, as a `u64`, then the encoding of each item in order             |
+/// | tuples (up to 8)    | the encoding of each member in order, with no separator                              |
+///
+/// Variable-size values carry their own length, so concatenating the encodings of tuple members is unambiguous.
+pub trait ToFieldElements {
+    /// Append the encoding of `self` to `out`.
+    fn to_field_elements(&self, out: &mut Vec<Scalar>);
+}
+
+impl ToFieldElements for Scalar {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        out.push(*self);
+    }
+}
+
+impl ToFieldElements for u64 {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        out.push(scalar_from_u64(*self));
+    }
+}
+
+impl ToFieldElements for bool {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        out.push(if *self { Scalar::one() } else { Scalar::zero() });
+    }
+}
+
+impl ToFieldElements for [u8; 32] {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        let mut limbs = [0u64; 4];
+        for (limb, bytes) in limbs.iter_mut().zip(self.chunks(8)) {
+            let mut le_bytes = [0u8; 8];
+            le_bytes.copy_from_slice(bytes);
+            *limb = u64::from_le_bytes(le_bytes);
+        }
+
+        out.push(scalar_from_u64s([limbs[0], limbs[1], 0, 0]));
+        out.push(scalar_from_u64s([limbs[2], limbs[3], 0, 0]));
+    }
+}
+
+impl<T: ToFieldElements> ToFieldElements for [T] {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        (self.len() as u64).to_field_elements(out);
+        for item in self {
+            item.to_field_elements(out);
+        }
+    }
+}
+
+impl<T: ToFieldElements> ToFieldElements for Vec<T> {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        self[..].to_field_elements(out);
+    }
+}
+
+impl<'a, T: ToFieldElements + ?Sized> ToFieldElements for &'a T {
+    fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+        (**self).to_field_elements(out);
+    }
+}
+
+macro_rules! impl_to_field_elements_for_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: ToFieldElements),+> ToFieldElements for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn to_field_elements(&self, out: &mut Vec<Scalar>) {
+                let ($($name,)+) = self;
+                $($name.to_field_elements(out);)+
+            }
+        }
+    };
+}
+
+impl_to_field_elements_for_tuple!(A);
+impl_to_field_elements_for_tuple!(A B);
+impl_to_field_elements_for_tuple!(A B C);
+impl_to_field_elements_for_tuple!(A B C D);
+impl_to_field_elements_for_tuple!(A B C D E);
+impl_to_field_elements_for_tuple!(A B C D E F);
+impl_to_field_elements_for_tuple!(A B C D E F G);
+impl_to_field_elements_for_tuple!(A B C D E F G H);
+
+/// The encoding of `value`, as `ToFieldElements` defines it.
+pub fn to_field_elements<T: ToFieldElements + ?Sized>(value: &T) -> Vec<Scalar> {
+    let mut out = Vec::new();
+    value.to_field_elements(&mut out);
+    out
+}
+
+/// Hash the encoding of `value` with `poseidon::hash_multi` and the default arity 8 constants. The number of elements
+/// in the encoding is part of the domain tag.
+pub fn hash_object<T: ToFieldElements + ?Sized>(value: &T) -> Scalar {
+    hash_multi(&*POSEIDON_CONSTANTS_8, &to_field_elements(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(
+            vec![
+                scalar_from_u64s([0x0706050403020100, 0x0f0e0d0c0b0a0908, 0, 0]),
+                scalar_from_u64s([0x1716151413121110, 0x1f1e1d1c1b1a1918, 0, 0]),
+            ],
+            to_field_elements(&bytes)
+        );
+
+        assert_eq!(vec![scalar_from_u64::<Scalar>(7)], to_field_elements(&7u64));
+        assert_eq!(vec![Scalar::one()], to_field_elements(&true));
+        assert_eq!(vec![Scalar::zero()], to_field_elements(&false));
+        assert_eq!(vec![Scalar::zero()], to_field_elements(&Vec::<u64>::new()));
+
+        let values = [1u64, 2, 3];
+        let expected = [3, 1, 2, 3]
+            .iter()
+            .map(|x| scalar_from_u64::<Scalar>(*x))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, to_field_elements(&values[..]));
+        assert_eq!(expected, to_field_elements(&values.to_vec()));
+
+        // The length prefix distinguishes how tuple members split the same elements.
+        assert_ne!(
+            to_field_elements(&(&values[..1], &values[1..])),
+            to_field_elements(&(&values[..2], &values[2..]))
+        );
+    }
+
+    #[test]
+    fn test_hash_object() {
+        assert_eq!(
+            scalar_from_u64s([
+                0xc650aefb36ac84e5,
+                0xa90c4c11d9cb0321,
+                0x81daacd4199b6bbd,
+                0x65d728e10220f6ff,
+            ]),
+            hash_object(&7u64)
+        );
+        assert_eq!(
+            scalar_from_u64s([
+                0xc3041170770087f9,
+                0x2d32b5bd84937ffe,
+                0x5c716c0faedea8cf,
+                0x6d302871080b69fc,
+            ]),
+            hash_object(&false)
+        );
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let record = (true, bytes, &[1u64, 2, 3][..]);
+        assert_eq!(
+            scalar_from_u64s([
+                0xe60bd7dd6d7daba0,
+                0x4a7bd34c10a0d186,
+                0x87635f27322c03fb,
+                0x245d2e3c74e35408,
+            ]),
+            hash_object(&record)
+        );
+    }
+}
@@ -163,9 +163,9 @@ impl<A> GPUConstants<A>
 where
     A: Arity<Fr>,
 {
-    fn arity_tag(&self, ctx: &FutharkContext) -> Result<Array_u64_1d, Error> {
-        let arity_tag = self.0.arity_tag;
-        array_u64_1d_from_fr(ctx, arity_tag)
+    fn domain_tag(&self, ctx: &FutharkContext) -> Result<Array_u64_1d, Error> {
+        let domain_tag = self.0.domain_tag;
+        array_u64_1d_from_fr(ctx, domain_tag)
     }
 
     fn round_keys(&self, ctx: &FutharkContext) -> Result<Array_u64_2d, Error> {
@@ -354,7 +354,7 @@ fn init_hash2(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
         Strength::Standard => {
             let state = ctx
                 .init2(
-                    constants.arity_tag(&ctx)?,
+                    constants.domain_tag(&ctx)?,
                     constants.round_keys(&ctx)?,
                     constants.mds_matrix(&ctx)?,
                     constants.pre_sparse_matrix(&ctx)?,
@@ -366,7 +366,7 @@ fn init_hash2(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
         Strength::Strengthened => {
             let state = ctx
                 .init2s(
-                    constants.arity_tag(&ctx)?,
+                    constants.domain_tag(&ctx)?,
                     constants.round_keys(&ctx)?,
                     constants.mds_matrix(&ctx)?,
                     constants.pre_sparse_matrix(&ctx)?,
@@ -384,7 +384,7 @@ fn init_hash8(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
         Strength::Standard => {
             let state = ctx
                 .init8(
-                    constants.arity_tag(&ctx)?,
+                    constants.domain_tag(&ctx)?,
                     constants.round_keys(&ctx)?,
                     constants.mds_matrix(&ctx)?,
                     constants.pre_sparse_matrix(&ctx)?,
@@ -397,7 +397,7 @@ fn init_hash8(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
         Strength::Strengthened => {
             let state = ctx
                 .init8s(
-                    constants.arity_tag(&ctx)?,
+                    constants.domain_tag(&ctx)?,
                     constants.round_keys(&ctx)?,
                     constants.mds_matrix(&ctx)?,
                     constants.pre_sparse_matrix(&ctx)?,
@@ -417,7 +417,7 @@ fn init_hash11(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSt
         Strength::Standard => {
             let state = ctx
                 .init11(
-                    constants.arity_tag(&ctx)?,
+                    constants.domain_tag(&ctx)?,
                     constants.round_keys(&ctx)?,
                     constants.mds_matrix(&ctx)?,
                     constants.pre_sparse_matrix(&ctx)?,
@@ -430,7 +430,7 @@ fn init_hash11(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSt
         Strength::Strengthened => {
             let state = ctx
                 .init11s(
-                    constants.arity_tag(&ctx)?,
+                    constants.domain_tag(&ctx)?,
                     constants.round_keys(&ctx)?,
                     constants.mds_matrix(&ctx)?,
                     constants.pre_sparse_matrix(&ctx)?,
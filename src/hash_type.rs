@@ -0,0 +1,192 @@
+use crate::poseidon::Arity;
+use crate::scalar_from_u64;
+use ff::{Field, PrimeField};
+
+/// The kind of hashing being performed. Each kind places its own domain tag in the first (capacity) element of the
+/// Poseidon state, so a digest computed in one mode can never be mistaken for a digest computed in another.
+///
+/// | HashType            | Domain tag      |
+/// |---------------------|-----------------|
+/// | `MerkleTree`        | 2^arity - 1     |
+/// | `ConstantLength(n)` | n * 2^64        |
+/// | `Sponge`            | 2^32            |
+/// | `Prf`               | 2^33            |
+/// | `Encryption(n)`     | 2^34 + n * 2^64 |
+/// | `Salt`              | 2^35            |
+/// | `Mac(n)`            | 2^36 + n * 2^64 |
+/// | `Squeeze(n)`        | 2^37 + n * 2^64 |
+/// | `Personalized(n)`   | 2^38 + n * 2^64 |
+/// | `Chained(n)`        | 2^39 + n * 2^64 |
+/// | `Bytes(n)`          | 2^40 + n * 2^64 |
+/// | `U64s(n)`           | 2^41 + n * 2^64 |
+///
+/// Tree tags are odd and all other tags are even, and no two tags in the table coincide.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashType {
+    /// Fixed-arity hashing, as used for the nodes of a Merkle tree. This is the original arity tag.
+    MerkleTree,
+    /// Hashing a preimage of `n` elements, at most the arity, zero-padded to the arity. The length is part of the tag,
+    /// so preimages differing only in trailing zeros hash differently.
+    ConstantLength(usize),
+    /// Variable-length hashing through a sponge.
+    Sponge,
+    /// A keyed pseudorandom function: the first element of the preimage is the key.
+    Prf,
+    /// Authenticated encryption of a message of `n` elements, keyed by the first two elements of the state.
+    Encryption(usize),
+    /// Hashing a salt alone, to derive the capacity element for a salted hash.
+    Salt,
+    /// Authenticating a message of `n` elements, keyed by the second element of the state.
+    Mac(usize),
+    /// Hashing a full preimage to `n` output elements.
+    Squeeze(usize),
+    /// Hashing `n` elements, the first of which is the digest of a personalization string, as
+    /// `poseidon::hash_bytes_with_domain` does.
+    Personalized(usize),
+    /// Hashing a preimage of `n` elements, of any length, by chaining permutations, as `poseidon::hash_multi` does.
+    Chained(usize),
+    /// Hashing the `n` elements a byte string is packed into, chained as by `Chained`, as `poseidon::hash_bytes` does.
+    Bytes(usize),
+    /// Hashing `n` integers, chained as by `Chained`, as `poseidon::hash_u64s` does.
+    U64s(usize),
+}
+
+impl HashType {
+    /// The domain tag for this kind of hashing at arity `A`.
+    pub fn domain_tag<Fr: PrimeField, A: Arity<Fr>>(&self) -> Fr {
+        match self {
+            HashType::MerkleTree => A::tag(),
+            HashType::ConstantLength(n) => {
+                let mut tag = pow2::<Fr>(64);
+                tag.mul_assign(&scalar_from_u64::<Fr>(*n as u64));
+                tag
+            }
+            HashType::Sponge => pow2::<Fr>(32),
+            HashType::Prf => pow2::<Fr>(33),
+            HashType::Encryption(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(34));
+                tag
+            }
+            HashType::Salt => pow2::<Fr>(35),
+            HashType::Mac(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(36));
+                tag
+            }
+            HashType::Squeeze(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(37));
+                tag
+            }
+            HashType::Personalized(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(38));
+                tag
+            }
+            HashType::Chained(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(39));
+                tag
+            }
+            HashType::Bytes(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(40));
+                tag
+            }
+            HashType::U64s(n) => {
+                let mut tag = HashType::ConstantLength(*n).domain_tag::<Fr, A>();
+                tag.add_assign(&pow2::<Fr>(41));
+                tag
+            }
+        }
+    }
+}
+
+/// 2^n as a field element.
+fn pow2<Fr: PrimeField>(n: usize) -> Fr {
+    let mut res = Fr::one();
+    for _ in 0..n {
+        res.double();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generic_array::typenum::{U2, U36};
+    use paired::bls12_381::Fr;
+
+    #[test]
+    fn test_domain_tags() {
+        assert_eq!(
+            scalar_from_u64::<Fr>(3),
+            HashType::MerkleTree.domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 32),
+            HashType::Sponge.domain_tag::<Fr, U2>()
+        );
+        assert_ne!(
+            HashType::MerkleTree.domain_tag::<Fr, U36>(),
+            HashType::Sponge.domain_tag::<Fr, U36>()
+        );
+
+        let mut tag = scalar_from_u64::<Fr>(1 << 32);
+        tag.mul_assign(&scalar_from_u64::<Fr>(3 << 32));
+        assert_eq!(tag, HashType::ConstantLength(3).domain_tag::<Fr, U2>());
+        assert_eq!(
+            Fr::zero(),
+            HashType::ConstantLength(0).domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 33),
+            HashType::Prf.domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 35),
+            HashType::Salt.domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 36),
+            HashType::Mac(0).domain_tag::<Fr, U2>()
+        );
+        assert_ne!(
+            HashType::Encryption(3).domain_tag::<Fr, U2>(),
+            HashType::Mac(3).domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 37),
+            HashType::Squeeze(0).domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 38),
+            HashType::Personalized(0).domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 39),
+            HashType::Chained(0).domain_tag::<Fr, U2>()
+        );
+        assert_ne!(
+            HashType::ConstantLength(3).domain_tag::<Fr, U2>(),
+            HashType::Chained(3).domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 40),
+            HashType::Bytes(0).domain_tag::<Fr, U2>()
+        );
+        assert_eq!(
+            scalar_from_u64::<Fr>(1 << 41),
+            HashType::U64s(0).domain_tag::<Fr, U2>()
+        );
+        assert_ne!(
+            HashType::ConstantLength(1).domain_tag::<Fr, U2>(),
+            HashType::Bytes(1).domain_tag::<Fr, U2>()
+        );
+        assert_ne!(
+            HashType::Bytes(1).domain_tag::<Fr, U2>(),
+            HashType::U64s(1).domain_tag::<Fr, U2>()
+        );
+    }
+}
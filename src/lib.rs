@@ -3,25 +3,81 @@
 #[macro_use]
 extern crate lazy_static;
 
-pub use crate::poseidon::{Arity, Poseidon};
-use crate::round_constants::generate_constants;
-pub use error::Error;
-use ff::{Field, PrimeField, ScalarEngine};
+pub use crate::poseidon::{init, Arity, Poseidon};
+pub use error::{Error, MdsError, ParameterError};
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
 use generic_array::GenericArray;
+pub use mds::{generate_mds_cauchy, validate_mds};
 pub use paired::bls12_381::Fr as Scalar;
 use paired::bls12_381::FrRepr;
 
 /// Poseidon circuit
 pub mod circuit;
+
+/// Example Groth16 circuit proving knowledge of a Poseidon preimage
+#[cfg(feature = "demo")]
+pub mod demo;
+
+/// Authenticated encryption with the Poseidon permutation
+pub mod encryption;
 pub mod error;
+
+/// Encoding application values as field elements for hashing
+pub mod field_elements;
+
+/// Domain separation for the different kinds of hashing
+pub mod hash_type;
+
+/// Message authentication with the Poseidon permutation
+pub mod mac;
 mod matrix;
 mod mds;
 
+/// Process-wide counts of hashing throughput
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+/// Multi-threaded hashing of many preimages
+#[cfg(feature = "parallel")]
+pub mod parallel;
+
+/// Pool of reusable hashers for hashing on many threads
+pub mod pool;
+
 /// Poseidon hash
 pub mod poseidon;
+
 mod poseidon_alt;
+/// Poseidon byte hashing through the RustCrypto `digest` traits
+#[cfg(feature = "digest")]
+pub mod poseidon_digest;
 mod preprocessing;
-mod round_constants;
+
+/// Round constant generation with the Grain LFSR of the Poseidon reference
+pub mod round_constants;
+
+/// Round numbers from a target security level, as the Poseidon reference computes them
+pub mod round_numbers;
+
+/// Export of Poseidon circuits in the snarkjs R1CS and witness formats
+#[cfg(feature = "snarkjs")]
+pub mod snarkjs;
+
+/// Sponge construction for variable-length hashing
+pub mod sponge;
+
+/// Constraint system for testing circuits
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_cs;
+
+/// Fiat–Shamir transcript over the Poseidon sponge
+pub mod transcript;
+
+/// Known-answer vectors generated by parameters/known_vectors.py
+pub mod vectors;
+
+/// Constraint system recording only the witness, for proving-time synthesis
+pub mod witness_cs;
 
 /// Tree Builder
 #[cfg(feature = "gpu")]
@@ -53,6 +109,28 @@ pub enum Strength {
 
 pub(crate) const DEFAULT_STRENGTH: Strength = Strength::Standard;
 
+/// The S-box exponent. Five is the smallest exponent giving a permutation of the BLS12-381 scalar field, and the
+/// default. Three and seven are for fields where gcd(5, p - 1) != 1. Constants are only generated for an exponent
+/// coprime to p - 1, so three, which divides p - 1 for BLS12-381, is rejected there.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Alpha {
+    Three,
+    Five,
+    Seven,
+}
+
+impl Alpha {
+    pub fn exponent(&self) -> u64 {
+        match self {
+            Alpha::Three => 3,
+            Alpha::Five => 5,
+            Alpha::Seven => 7,
+        }
+    }
+}
+
+pub(crate) const DEFAULT_ALPHA: Alpha = Alpha::Five;
+
 pub trait BatchHasher<A>
 where
     A: Arity<Scalar>,
@@ -81,24 +159,10 @@ where
     }
 }
 
-// Round numbers for supported arities. Not all arities have been supplied.
-// If a new arity need be supported, the appropriate value should be included by running
-// the script: https://extgit.iaik.tugraz.at/krypto/hadeshash/blob/master/code/scripts/calc_round_numbers.py
+// Round numbers for x^5 at 128-bit security, computed as the reference script computes them. The field size is taken
+// as 256 bits, as the recorded output of the script in `parameters/` was generated with it.
 fn round_numbers_base(arity: usize) -> (usize, usize) {
-    let width = arity + 1;
-
-    let full_rounds = 8;
-    let partial_rounds = match width {
-        2 | 3 => 55,
-        4 | 5 | 6 | 7 => 56,
-        8 | 9 | 10 | 11 | 12 => 57,
-        17 | 25 => 59,
-        37 => 60,
-        65 => 61,
-        _ => panic!(format!("unsupported arity, {}", arity)),
-    };
-
-    (full_rounds, partial_rounds)
+    round_numbers::calc_round_numbers(256, arity + 1, 128, 5)
 }
 
 // In case of newly-discovered attacks, we may need stronger security.
@@ -125,27 +189,45 @@ pub fn round_numbers(arity: usize, strength: &Strength) -> (usize, usize) {
     }
 }
 
-/// convert
+/// The field element equal to `i`: `i` is the least significant limb of its canonical representation.
 pub fn scalar_from_u64<Fr: PrimeField>(i: u64) -> Fr {
     Fr::from_repr(<Fr::Repr as From<u64>>::from(i)).unwrap()
 }
 
-/// create field element from four u64
+/// The field element whose canonical representation has limbs `parts`, least significant first.
 pub fn scalar_from_u64s(parts: [u64; 4]) -> Scalar {
     Scalar::from_repr(FrRepr(parts)).unwrap()
 }
 
-const SBOX: u8 = 1; // x^5
-const FIELD: u8 = 1; // Gf(p)
-
-fn round_constants<E: ScalarEngine>(arity: usize, strength: &Strength) -> Vec<E::Fr> {
-    let t = arity + 1;
-
-    let (full_rounds, partial_rounds) = round_numbers(arity, strength);
+/// Whether `a` and `b` are equal, comparing their canonical little-endian encodings in time independent of where they
+/// differ. Check secret values such as authentication tags with this rather than `==`. With the `subtle` feature the
+/// encodings are compared by `subtle::ConstantTimeEq`.
+pub fn constant_time_eq<Fr: PrimeField>(a: &Fr, b: &Fr) -> bool {
+    let encode = |x: &Fr| {
+        let mut bytes = Vec::with_capacity(32);
+        x.into_repr()
+            .write_le(&mut bytes)
+            .expect("writing to a Vec cannot fail");
+        bytes
+    };
+    let (a, b) = (encode(a), encode(b));
 
-    let r_f = full_rounds as u16;
-    let r_p = partial_rounds as u16;
+    #[cfg(feature = "subtle")]
+    {
+        use subtle::ConstantTimeEq;
+        a.ct_eq(&b).into()
+    }
+    #[cfg(not(feature = "subtle"))]
+    {
+        a.iter().zip(&b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+}
 
+fn round_constants<E: ScalarEngine>(
+    arity: usize,
+    full_rounds: usize,
+    partial_rounds: usize,
+) -> Vec<E::Fr> {
     let fr_num_bits = E::Fr::NUM_BITS;
     let field_size = {
         assert!(fr_num_bits <= std::u16::MAX as u32);
@@ -153,7 +235,7 @@ fn round_constants<E: ScalarEngine>(arity: usize, strength: &Strength) -> Vec<E:
         fr_num_bits as u16
     };
 
-    generate_constants::<E>(FIELD, SBOX, field_size, t as u16, r_f, r_p)
+    round_constants::round_constants::<E>(field_size, arity + 1, full_rounds, partial_rounds)
 }
 
 /// Apply the quintic S-Box (s^5) to a given item
@@ -175,9 +257,45 @@ pub(crate) fn quintic_s_box<E: ScalarEngine>(
     }
 }
 
+/// Apply the S-Box (s^alpha) to a given item
+pub(crate) fn alpha_s_box<E: ScalarEngine>(
+    l: &mut E::Fr,
+    pre_add: Option<&E::Fr>,
+    post_add: Option<&E::Fr>,
+    alpha: Alpha,
+) {
+    if let Some(x) = pre_add {
+        l.add_assign(x);
+    }
+    let mut l2 = *l;
+    l2.square();
+    // l^(alpha - 1)
+    let factor = match alpha {
+        Alpha::Three => l2,
+        Alpha::Five => {
+            let mut l4 = l2;
+            l4.square();
+            l4
+        }
+        Alpha::Seven => {
+            let mut l6 = l2;
+            l6.square();
+            l6.mul_assign(&l2);
+            l6
+        }
+    };
+    l.mul_assign(&factor);
+    if let Some(x) = post_add {
+        l.add_assign(x);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use paired::bls12_381::Bls12;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
 
     #[test]
     fn test_strengthened_round_constants() {
@@ -209,4 +327,45 @@ mod tests {
             );
         })
     }
+
+    #[test]
+    fn test_constant_time_eq() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for _ in 0..100 {
+            let a = Scalar::random(&mut rng);
+            let b = Scalar::random(&mut rng);
+            assert_eq!(a == b, constant_time_eq(&a, &b));
+            assert!(constant_time_eq(&a, &a));
+
+            // Adjacent values, which usually differ only in their lowest byte.
+            let mut c = a;
+            c.add_assign(&Scalar::one());
+            assert!(!constant_time_eq(&a, &c));
+        }
+        assert!(constant_time_eq(&Scalar::zero(), &scalar_from_u64(0)));
+    }
+
+    #[test]
+    fn test_alpha_s_box() {
+        let x = scalar_from_u64::<Scalar>(7);
+        let rk = scalar_from_u64::<Scalar>(11);
+
+        for alpha in [Alpha::Three, Alpha::Five, Alpha::Seven].iter() {
+            let mut expected = x;
+            expected.add_assign(&rk);
+            let mut expected = expected.pow([alpha.exponent()]);
+            expected.add_assign(&rk);
+
+            let mut l = x;
+            alpha_s_box::<Bls12>(&mut l, Some(&rk), Some(&rk), *alpha);
+            assert_eq!(expected, l, "wrong s-box for {:?}", alpha);
+        }
+
+        let mut quintic = x;
+        quintic_s_box::<Bls12>(&mut quintic, Some(&rk), None);
+        let mut l = x;
+        alpha_s_box::<Bls12>(&mut l, Some(&rk), None, Alpha::Five);
+        assert_eq!(quintic, l);
+    }
 }
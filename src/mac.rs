@@ -0,0 +1,153 @@
+use crate::constant_time_eq;
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use ff::{Field, ScalarEngine};
+
+/// A message authentication code keyed by a single field element.
+///
+/// The state starts as the `HashType::Mac` tag for the message length, then `key`, with any remaining elements zero,
+/// and is permuted. The message is absorbed in blocks of `arity` elements, each added into the rate elements, and the
+/// state is permuted after every block. The tag is the first rate element of the final state. Since the length is part
+/// of the initial state, a tag for one message says nothing about the tag of any extension of it.
+#[derive(Debug)]
+pub struct PoseidonMac<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    constants: &'a PoseidonConstants<E, A>,
+}
+
+impl<'a, E, A> PoseidonMac<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        PoseidonMac { constants }
+    }
+
+    /// The tag of `message` under `key`.
+    pub fn mac(&self, key: E::Fr, message: &[E::Fr]) -> E::Fr {
+        let mut p = Poseidon::new(self.constants);
+        p.elements[0] = HashType::Mac(message.len()).domain_tag::<E::Fr, A>();
+        p.elements[1] = key;
+        p.permute();
+
+        for block in message.chunks(A::to_usize()) {
+            for (i, m) in block.iter().enumerate() {
+                p.elements[1 + i].add_assign(m);
+            }
+            p.permute();
+        }
+
+        p.elements[1]
+    }
+
+    /// Whether `tag` is the tag of `message` under `key`, compared with `constant_time_eq`.
+    pub fn verify(&self, key: E::Fr, message: &[E::Fr], tag: &E::Fr) -> bool {
+        constant_time_eq(&self.mac(key, message), tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar_from_u64s;
+    use ff::{PrimeField, PrimeFieldRepr};
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn message(values: &[u64]) -> Vec<Fr> {
+        values.iter().map(|x| crate::scalar_from_u64(*x)).collect()
+    }
+
+    #[test]
+    fn mac_values() {
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let mac = PoseidonMac::new(&constants);
+        let key = crate::scalar_from_u64::<Fr>(7);
+
+        assert_eq!(
+            scalar_from_u64s([
+                0xcbd8a83df0b33ed6,
+                0xc13e133ee2118cd2,
+                0x8bf3e11b13a97934,
+                0x1d29a23c3f420555,
+            ]),
+            mac.mac(key, &[])
+        );
+        assert_eq!(
+            scalar_from_u64s([
+                0xd90ada4a670cd045,
+                0x9241ddfc0e7df603,
+                0x1caf06d08ec9d17e,
+                0x6b49b7c55cc1f46b,
+            ]),
+            mac.mac(key, &message(&[1, 2, 3]))
+        );
+        assert_eq!(
+            scalar_from_u64s([
+                0x28806c25db7a7136,
+                0x88bf51e47e6a36e3,
+                0x09502e9070a18bea,
+                0x4ab7ffac2f206ea5,
+            ]),
+            mac.mac(crate::scalar_from_u64(6), &message(&[1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn mac_key_sensitivity() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let mac = PoseidonMac::new(&constants);
+
+        for len in 0..10 {
+            let message = (0..len).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+            let key = Fr::random(&mut rng);
+            let tag = mac.mac(key, &message);
+            assert!(mac.verify(key, &message, &tag));
+
+            // Flip each bit of the lowest limb of the key in turn.
+            for bit in 0..64 {
+                let mut repr = key.into_repr();
+                repr.as_mut()[0] ^= 1 << bit;
+                let flipped = Fr::from_repr(repr).unwrap();
+
+                assert_ne!(tag, mac.mac(flipped, &message));
+                assert!(!mac.verify(flipped, &message, &tag));
+            }
+        }
+    }
+
+    #[test]
+    fn mac_extension() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let mac = PoseidonMac::new(&constants);
+        let key = Fr::random(&mut rng);
+
+        let message = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let tag = mac.mac(key, &message);
+
+        // Appending a zero, a full block, or the tag itself, does not yield a message the tag authenticates.
+        for suffix in &[vec![Fr::zero()], vec![Fr::zero(); 2], vec![tag]] {
+            let mut extended = message.clone();
+            extended.extend(suffix);
+            assert!(!mac.verify(key, &extended, &tag));
+        }
+
+        // Nor does truncating the message.
+        for len in 0..message.len() {
+            assert!(!mac.verify(key, &message[..len], &tag));
+        }
+
+        // A tampered tag is rejected.
+        let mut wrong = tag;
+        wrong.add_assign(&Fr::one());
+        assert!(!mac.verify(key, &message, &wrong));
+    }
+}
@@ -1,8 +1,10 @@
 use ff::{Field, ScalarEngine};
 
+use crate::error::{MdsError, ParameterError};
 use crate::matrix;
 use crate::matrix::{
-    apply_matrix, invert, is_identity, is_invertible, is_square, mat_mul, minor, Matrix, Scalar,
+    apply_matrix, invert, is_identity, is_invertible, is_square, left_apply_matrix, mat_mul, minor,
+    Matrix, Scalar,
 };
 use crate::scalar_from_u64;
 
@@ -17,25 +19,99 @@ pub struct MDSMatrices<E: ScalarEngine> {
 }
 
 pub fn create_mds_matrices<'a, E: ScalarEngine>(t: usize) -> MDSMatrices<E> {
-    let m = generate_mds::<E>(t);
-    derive_mds_matrices(m)
+    try_create_mds_matrices(t).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Number of Cauchy matrices `try_create_mds_matrices` generates for a width before giving up on finding a secure one.
+const MDS_ATTEMPTS: usize = 100;
+
+/// As `create_mds_matrices`, returning an error for a width below two, if the matrix or its minor is singular, or if
+/// no generated matrix passes `validate_mds`. As in the reference, a rejected matrix is replaced by the next one
+/// generated, with every `y` shifted up by one; the first, unshifted matrix passes at every width the crate has
+/// default constants for.
+pub fn try_create_mds_matrices<'a, E: ScalarEngine>(
+    t: usize,
+) -> Result<MDSMatrices<E>, ParameterError> {
+    if t < 2 {
+        return Err(ParameterError::InvalidWidth(t));
+    }
+
+    let mut rejection = MdsError::Singular;
+    for offset in 0..MDS_ATTEMPTS {
+        let m = generate_mds::<E>(t, offset);
+        match validate_mds::<E>(&m, t) {
+            Ok(()) => return try_derive_mds_matrices(m),
+            Err(err) => rejection = err,
+        }
+    }
+
+    Err(rejection.into())
+}
+
+/// Check `matrix` against the subspace trail conditions of the Poseidon reference (algorithms 1 to 3 of its
+/// parameter generation, for the single S-box of a partial round). The state is multiplied by the matrix as a row
+/// vector, so after `k` applications of `M^r` the S-box sees the product of the initial state with the first column of
+/// `M^(rk)`. Unless these columns, for `k` below `width`, span the whole space, the states orthogonal to all of them
+/// form a subspace which never activates the S-box, and passes linearly through any number of partial rounds. Each
+/// `r` from 1 to `4 * width` is checked, which also rejects a matrix with a power that is a multiple of the identity.
+pub fn validate_mds<E: ScalarEngine>(
+    matrix: &Matrix<Scalar<E>>,
+    width: usize,
+) -> Result<(), MdsError> {
+    if matrix::rows(matrix) != width || matrix.iter().any(|row| row.len() != width) {
+        return Err(MdsError::WrongShape(width));
+    }
+    if !is_invertible::<E>(matrix) {
+        return Err(MdsError::Singular);
+    }
+
+    let mut first_column = vec![Scalar::<E>::zero(); width];
+    first_column[0] = Scalar::<E>::one();
+
+    let mut power = matrix.clone();
+    for rounds in 1..=4 * width {
+        if rounds > 1 {
+            power =
+                mat_mul::<E>(&power, matrix).expect("matrices are square and of the same width");
+        }
+
+        let mut columns = Vec::with_capacity(width);
+        let mut column = first_column.clone();
+        for _ in 0..width {
+            let next = left_apply_matrix::<E>(&power, &column);
+            columns.push(column);
+            column = next;
+        }
+
+        if !is_invertible::<E>(&columns) {
+            return Err(MdsError::InvariantSubspace { rounds });
+        }
+    }
+
+    Ok(())
 }
 
 pub fn derive_mds_matrices<'a, E: ScalarEngine>(m: Matrix<Scalar<E>>) -> MDSMatrices<E> {
-    let m_inv = invert::<E>(&m).unwrap(); // m is MDS so invertible.
+    try_derive_mds_matrices(m).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub(crate) fn try_derive_mds_matrices<'a, E: ScalarEngine>(
+    m: Matrix<Scalar<E>>,
+) -> Result<MDSMatrices<E>, ParameterError> {
+    let m_inv = invert::<E>(&m).ok_or(ParameterError::SingularMatrix)?;
     let m_hat = minor::<E>(&m, 0, 0);
-    let m_hat_inv = invert::<E>(&m_hat).unwrap(); // If this returns None, then `mds_matrix` was not correctly generated.
+    let m_hat_inv = invert::<E>(&m_hat).ok_or(ParameterError::SingularMatrix)?;
     let m_prime = make_prime::<E>(&m);
     let m_double_prime = make_double_prime::<E>(&m, &m_hat_inv);
 
-    MDSMatrices {
+    Ok(MDSMatrices {
         m,
         m_inv,
         m_hat,
         m_hat_inv,
         m_prime,
         m_double_prime,
-    }
+    })
 }
 
 /// A `SparseMatrix` is specifically one of the form of M''.
@@ -115,9 +191,8 @@ pub fn factor_to_sparse_matrices<E: ScalarEngine>(
     (pre_sparse, all)
 }
 
-fn generate_mds<E: ScalarEngine>(t: usize) -> Matrix<Scalar<E>> {
+fn generate_mds<E: ScalarEngine>(t: usize, offset: usize) -> Matrix<Scalar<E>> {
     // Source: https://github.com/dusk-network/dusk-poseidon-merkle/commit/776c37734ea2e71bb608ce4bc58fdb5f208112a7#diff-2eee9b20fb23edcc0bf84b14167cbfdc
-    let mut matrix: Vec<Vec<E::Fr>> = Vec::with_capacity(t);
     let mut xs: Vec<E::Fr> = Vec::with_capacity(t);
     let mut ys: Vec<E::Fr> = Vec::with_capacity(t);
 
@@ -130,27 +205,52 @@ fn generate_mds<E: ScalarEngine>(t: usize) -> Matrix<Scalar<E>> {
     // For an MDS matrix, every possible mxm submatrix, must have det(M) != 0
     for i in 0..t {
         let x = scalar_from_u64((i) as u64);
-        let y = scalar_from_u64((i + t) as u64);
+        let y = scalar_from_u64((i + t + offset) as u64);
         xs.push(x);
         ys.push(y);
     }
 
-    for i in 0..t {
-        let mut row: Vec<E::Fr> = Vec::with_capacity(t);
-        for j in 0..t {
-            // Generate the entry at (i,j)
-            let mut tmp = xs[i];
-            tmp.add_assign(&ys[j]);
-            let entry = tmp.inverse().unwrap();
-            row.insert(j, entry);
-        }
-        matrix.push(row);
+    // To ensure correctness, we would check all sub-matrices for invertibility. Meanwhile, `try_derive_mds_matrices`
+    // checks the matrix and its minor.
+    cauchy::<E>(&xs, &ys).expect("x + y is positive, so never zero")
+}
+
+/// The Cauchy matrix `M[i][j] = 1 / (x[i] + y[j])`, as other Poseidon implementations build their MDS matrices, for
+/// constructing `PoseidonConstants` which agree with theirs through `PoseidonConstants::try_new_with_mds`. Returns
+/// `MdsError::WrongShape` unless `x` and `y` both have `width` elements, and `MdsError::Singular` unless every
+/// denominator is nonzero and the matrix is invertible, as it is whenever the elements of `x` are distinct and those of
+/// `y` are too.
+pub fn generate_mds_cauchy<E: ScalarEngine>(
+    width: usize,
+    x: &[Scalar<E>],
+    y: &[Scalar<E>],
+) -> Result<Matrix<Scalar<E>>, MdsError> {
+    if x.len() != width || y.len() != width {
+        return Err(MdsError::WrongShape(width));
     }
 
-    // To ensure correctness, we would check all sub-matrices for invertibility. Meanwhile, this is a simple sanity check.
-    assert!(is_invertible::<E>(&matrix));
+    // A zero denominator leaves the matrix undefined; repeated x or y values leave it singular.
+    let matrix = cauchy::<E>(x, y).ok_or(MdsError::Singular)?;
+    if !is_invertible::<E>(&matrix) {
+        return Err(MdsError::Singular);
+    }
 
-    matrix
+    Ok(matrix)
+}
+
+/// The Cauchy matrix of `xs` and `ys`, or `None` if some `xs[i] + ys[j]` is zero.
+fn cauchy<E: ScalarEngine>(xs: &[Scalar<E>], ys: &[Scalar<E>]) -> Option<Matrix<Scalar<E>>> {
+    xs.iter()
+        .map(|x| {
+            ys.iter()
+                .map(|y| {
+                    let mut tmp = *x;
+                    tmp.add_assign(y);
+                    tmp.inverse()
+                })
+                .collect()
+        })
+        .collect()
 }
 
 fn make_prime<E: ScalarEngine>(m: &Matrix<Scalar<E>>) -> Matrix<Scalar<E>> {
@@ -251,6 +351,106 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_invalid_mds_matrices() {
+        assert_eq!(
+            Some(ParameterError::InvalidWidth(1)),
+            try_create_mds_matrices::<Bls12>(1).err()
+        );
+        assert_eq!(
+            Some(ParameterError::InvalidWidth(0)),
+            try_create_mds_matrices::<Bls12>(0).err()
+        );
+
+        let one = Fr::one();
+        assert_eq!(
+            Some(ParameterError::SingularMatrix),
+            try_derive_mds_matrices::<Bls12>(vec![vec![one, one], vec![one, one]]).err()
+        );
+    }
+
+    #[test]
+    fn test_generate_mds_cauchy() {
+        let s = scalar_from_u64::<Fr>;
+        let inv = |x: u64| s(x).inverse().unwrap();
+
+        // 1 / (x + y) for x in [1, 2] and y in [3, 4].
+        assert_eq!(
+            vec![vec![inv(4), inv(5)], vec![inv(5), inv(6)]],
+            generate_mds_cauchy::<Bls12>(2, &[s(1), s(2)], &[s(3), s(4)]).unwrap()
+        );
+
+        // The generated matrices are the Cauchy matrices of x = 0, 1, ... and y = width, width + 1, ...
+        for width in 2..=12 {
+            let x = (0..width).map(|i| s(i as u64)).collect::<Vec<_>>();
+            let y = (width..2 * width).map(|i| s(i as u64)).collect::<Vec<_>>();
+            assert_eq!(
+                generate_mds::<Bls12>(width, 0),
+                generate_mds_cauchy::<Bls12>(width, &x, &y).unwrap()
+            );
+        }
+
+        let mut minus_one = Fr::zero();
+        minus_one.sub_assign(&Fr::one());
+        assert_eq!(
+            Err(MdsError::Singular),
+            generate_mds_cauchy::<Bls12>(2, &[s(1), s(2)], &[minus_one, s(3)])
+        );
+        assert_eq!(
+            Err(MdsError::Singular),
+            generate_mds_cauchy::<Bls12>(2, &[s(1), s(1)], &[s(3), s(4)])
+        );
+        assert_eq!(
+            Err(MdsError::WrongShape(3)),
+            generate_mds_cauchy::<Bls12>(3, &[s(1), s(2)], &[s(3), s(4)])
+        );
+    }
+
+    #[test]
+    fn test_validate_mds() {
+        // Every width up to arity 11, and the widths of the larger default arities.
+        for width in (2..=12).chain([17, 25, 37].iter().cloned()) {
+            let m = generate_mds::<Bls12>(width, 0);
+            assert_eq!(Ok(()), validate_mds::<Bls12>(&m, width), "width {}", width);
+            assert_eq!(m, create_mds_matrices::<Bls12>(width).m);
+        }
+
+        let (zero, one) = (Fr::zero(), Fr::one());
+        let s = scalar_from_u64::<Fr>;
+
+        // The S-box input never changes under the identity.
+        assert_eq!(
+            Err(MdsError::InvariantSubspace { rounds: 1 }),
+            validate_mds::<Bls12>(&matrix::make_identity::<Bls12>(3), 3)
+        );
+
+        // A swap mixes the state, but its square is the identity.
+        assert_eq!(
+            Err(MdsError::InvariantSubspace { rounds: 2 }),
+            validate_mds::<Bls12>(&vec![vec![zero, one], vec![one, zero]], 2)
+        );
+
+        // The last element never reaches the S-box.
+        let split = vec![
+            vec![s(2), s(1), zero],
+            vec![s(1), s(3), zero],
+            vec![zero, zero, s(5)],
+        ];
+        assert_eq!(
+            Err(MdsError::InvariantSubspace { rounds: 1 }),
+            validate_mds::<Bls12>(&split, 3)
+        );
+
+        assert_eq!(
+            Err(MdsError::Singular),
+            validate_mds::<Bls12>(&vec![vec![one, one], vec![one, one]], 2)
+        );
+        assert_eq!(
+            Err(MdsError::WrongShape(3)),
+            validate_mds::<Bls12>(&generate_mds::<Bls12>(2, 0), 3)
+        );
+    }
+
     #[test]
     fn test_swapping() {
         test_swapping_aux(3);
@@ -310,7 +510,7 @@ mod tests {
     fn test_factor_to_sparse_matrices_aux(width: usize, n: usize) {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
 
-        let m = generate_mds::<Bls12>(width);
+        let m = generate_mds::<Bls12>(width, 0);
         let m2 = m.clone();
 
         let (pre_sparse, sparse) = factor_to_sparse_matrices::<Bls12>(m, n);
@@ -354,7 +554,7 @@ mod tests {
     }
 
     fn test_factor_to_sparse_matrixes_aux(width: usize, n: usize) {
-        let m = generate_mds::<Bls12>(width);
+        let m = generate_mds::<Bls12>(width, 0);
         let m2 = m.clone();
 
         let (pre_sparse, sparse_matrices) = factor_to_sparse_matrices::<Bls12>(m, n);
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static HASHES: AtomicU64 = AtomicU64::new(0);
+static ELEMENTS: AtomicU64 = AtomicU64::new(0);
+static BATCHES: AtomicU64 = AtomicU64::new(0);
+static BATCHED_HASHES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts of the hashing done by this process since it started, or since the last `reset`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// Hashes computed, on the CPU or in GPU batches, in any mode. Each permutation of a sponge, of `hash_squeeze` or
+    /// of `poseidon_permute` counts as one hash.
+    pub hashes: u64,
+    /// Preimage elements input to those hashes, or absorbed by a sponge. A short preimage counts the elements input,
+    /// not its padding.
+    pub elements: u64,
+    /// Batches hashed by `parallel::hash_many`, `parallel::hash_layer` or a batch hasher, including those of the tree
+    /// builders.
+    pub batches: u64,
+    /// Hashes in those batches, so that `batched_hashes / batches` is the mean batch size.
+    pub batched_hashes: u64,
+}
+
+/// The current counts. Each counter is read atomically, but hashing on other threads may land between the reads.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        hashes: HASHES.load(Ordering::Relaxed),
+        elements: ELEMENTS.load(Ordering::Relaxed),
+        batches: BATCHES.load(Ordering::Relaxed),
+        batched_hashes: BATCHED_HASHES.load(Ordering::Relaxed),
+    }
+}
+
+/// Set every count to zero.
+pub fn reset() {
+    HASHES.store(0, Ordering::Relaxed);
+    ELEMENTS.store(0, Ordering::Relaxed);
+    BATCHES.store(0, Ordering::Relaxed);
+    BATCHED_HASHES.store(0, Ordering::Relaxed);
+}
+
+pub(crate) fn record_hashes(hashes: usize, elements: usize) {
+    HASHES.fetch_add(hashes as u64, Ordering::Relaxed);
+    ELEMENTS.fetch_add(elements as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn record_batch(size: usize) {
+    BATCHES.fetch_add(1, Ordering::Relaxed);
+    BATCHED_HASHES.fetch_add(size as u64, Ordering::Relaxed);
+}
@@ -0,0 +1,153 @@
+use crate::error::Error;
+use crate::poseidon::{check_layer_len, Arity, Poseidon, PoseidonConstants};
+use ff::ScalarEngine;
+use generic_array::GenericArray;
+use rayon::prelude::*;
+
+/// Hash every preimage with `constants`, sharing the work across the global rayon pool. Digests are returned in
+/// preimage order. Each worker thread resets one `Poseidon` between hashes rather than constructing a fresh one.
+pub fn hash_many<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    preimages: &[GenericArray<E::Fr, A>],
+) -> Vec<E::Fr>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+    PoseidonConstants<E, A>: Sync,
+{
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_batch(preimages.len());
+
+    preimages
+        .par_iter()
+        .map_init(
+            || Poseidon::new(constants),
+            |hasher, preimage| hash_reset(hasher, preimage),
+        )
+        .collect()
+}
+
+/// As `hash_many`, for preimages held as slices or vectors, such as windows of a tree layer. Returns `Error::Other`,
+/// before hashing anything, unless every preimage holds exactly `arity` elements.
+pub fn hash_many_slices<E, A, P>(
+    constants: &PoseidonConstants<E, A>,
+    preimages: &[P],
+) -> Result<Vec<E::Fr>, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+    P: AsRef<[E::Fr]> + Sync,
+    PoseidonConstants<E, A>: Sync,
+{
+    if let Some(i) = preimages
+        .iter()
+        .position(|preimage| preimage.as_ref().len() != A::to_usize())
+    {
+        return Err(Error::Other(format!(
+            "expected preimages of {} elements, got {} at index {}",
+            A::to_usize(),
+            preimages[i].as_ref().len(),
+            i
+        )));
+    }
+
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_batch(preimages.len());
+
+    Ok(preimages
+        .par_iter()
+        .map_init(
+            || Poseidon::new(constants),
+            |hasher, preimage| hash_reset(hasher, preimage.as_ref()),
+        )
+        .collect())
+}
+
+/// As `poseidon::hash_layer`, hashing the chunks of `leaves` across the global rayon pool. Returns `Error::Other`
+/// unless the number of leaves is a multiple of the arity.
+pub fn hash_layer<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    leaves: &[E::Fr],
+) -> Result<Vec<E::Fr>, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+    PoseidonConstants<E, A>: Sync,
+{
+    check_layer_len::<E, A>(leaves.len())?;
+
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_batch(leaves.len() / A::to_usize());
+
+    Ok(leaves
+        .par_chunks(A::to_usize())
+        .map_init(
+            || Poseidon::new(constants),
+            |hasher, preimage| hash_reset(hasher, preimage),
+        )
+        .collect())
+}
+
+fn hash_reset<E, A>(hasher: &mut Poseidon<E, A>, preimage: &[E::Fr]) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    // `set_preimage` resets the hasher before copying the preimage in.
+    hasher.set_preimage(preimage);
+
+    hasher.hash()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::Field;
+    use generic_array::sequence::GenericSequence;
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_hash_many() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+
+        let preimages = (0..3000)
+            .map(|_| GenericArray::<Fr, typenum::U4>::generate(|_| Fr::random(&mut rng)))
+            .collect::<Vec<_>>();
+        let expected = preimages
+            .iter()
+            .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, hash_many(&constants, &preimages));
+
+        let vectors = preimages
+            .iter()
+            .map(|preimage| preimage.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, hash_many_slices(&constants, &vectors).unwrap());
+
+        let mut ragged = vectors;
+        ragged[1234].pop();
+        assert!(hash_many_slices(&constants, &ragged).is_err());
+    }
+
+    #[test]
+    fn test_hash_layer() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U8>::new();
+        let leaves = (0..8 * 1000)
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            crate::poseidon::hash_layer(&constants, &leaves).unwrap(),
+            hash_layer(&constants, &leaves).unwrap()
+        );
+        assert!(hash_layer(&constants, &[]).unwrap().is_empty());
+        assert!(hash_layer(&constants, &leaves[1..]).is_err());
+    }
+}
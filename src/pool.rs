@@ -0,0 +1,125 @@
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use ff::ScalarEngine;
+use std::sync::Mutex;
+
+/// Hashers sharing one set of constants, kept for reuse by any number of threads. A server hashing on many threads
+/// can lend each request a hasher from the pool rather than constructing one per request. The pool grows to the
+/// largest number of hashers ever in use at once.
+#[derive(Debug)]
+pub struct HasherPool<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    constants: &'a PoseidonConstants<E, A>,
+    idle: Mutex<Vec<Poseidon<'a, E, A>>>,
+}
+
+impl<'a, E, A> HasherPool<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        HasherPool {
+            constants,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Call `f` with a hasher in its initial state, as after `Poseidon::reset`, returning its result. Whatever `f`
+    /// leaves in the hasher is discarded before it is lent again. If `f` panics, the hasher is dropped rather than
+    /// returned to the pool.
+    pub fn with_hasher<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Poseidon<'a, E, A>) -> R,
+    {
+        let idle = self.idle.lock().expect("pool lock poisoned").pop();
+        let mut hasher = match idle {
+            Some(mut hasher) => {
+                hasher.reset();
+                hasher
+            }
+            None => Poseidon::new(self.constants),
+        };
+
+        let result = f(&mut hasher);
+        self.idle.lock().expect("pool lock poisoned").push(hasher);
+
+        result
+    }
+
+    /// Number of hashers waiting to be lent.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().expect("pool lock poisoned").len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon::POSEIDON_CONSTANTS_4;
+    use generic_array::typenum::U4;
+    use paired::bls12_381::{Bls12, Fr};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn preimage(i: u64) -> Vec<Fr> {
+        (0..4).map(|j| crate::scalar_from_u64(4 * i + j)).collect()
+    }
+
+    #[test]
+    fn test_no_leakage() {
+        let pool = HasherPool::new(&*POSEIDON_CONSTANTS_4);
+        let expected = Poseidon::new_with_preimage(&preimage(0), &*POSEIDON_CONSTANTS_4).hash();
+
+        // Leave a partial preimage behind.
+        pool.with_hasher(|p| p.input(crate::scalar_from_u64(9)).unwrap());
+        assert_eq!(1, pool.idle());
+
+        let digest = pool.with_hasher(|p| {
+            assert_eq!(1, p.pos());
+            for x in preimage(0) {
+                p.input(x).unwrap();
+            }
+            p.hash()
+        });
+        assert_eq!(expected, digest);
+        assert_eq!(1, pool.idle());
+    }
+
+    #[test]
+    fn test_concurrent_hashing() {
+        let pool = Arc::new(HasherPool::<Bls12, U4>::new(&*POSEIDON_CONSTANTS_4));
+        let expected = (0..2000)
+            .map(|i| Poseidon::new_with_preimage(&preimage(i), &*POSEIDON_CONSTANTS_4).hash())
+            .collect::<Vec<_>>();
+
+        let threads = (0..8)
+            .map(|t| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    (0..2000)
+                        .filter(|i| i % 8 == t)
+                        .map(|i| {
+                            let digest = pool.with_hasher(|p| {
+                                for x in preimage(i) {
+                                    p.input(x).unwrap();
+                                }
+                                p.hash()
+                            });
+                            (i, digest)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in threads {
+            for (i, digest) in handle.join().unwrap() {
+                assert_eq!(expected[i as usize], digest);
+            }
+        }
+        assert!(pool.idle() <= 8);
+    }
+}
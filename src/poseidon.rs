@@ -1,16 +1,25 @@
+use crate::error::ParameterError;
+use crate::hash_type::HashType;
 use crate::matrix::Matrix;
-use crate::mds::{create_mds_matrices, factor_to_sparse_matrixes, MDSMatrices, SparseMatrix};
+use crate::mds::{
+    create_mds_matrices, factor_to_sparse_matrixes, try_create_mds_matrices,
+    try_derive_mds_matrices, validate_mds, MDSMatrices, SparseMatrix,
+};
 use crate::poseidon_alt::{hash_correct, hash_optimized_dynamic};
 use crate::preprocessing::compress_round_constants;
-use crate::{matrix, quintic_s_box, BatchHasher, Strength, DEFAULT_STRENGTH};
+use crate::{alpha_s_box, matrix, Alpha, BatchHasher, Strength, DEFAULT_ALPHA, DEFAULT_STRENGTH};
 use crate::{round_constants, round_numbers, scalar_from_u64, Error};
-use ff::{Field, PrimeField, ScalarEngine};
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
 use generic_array::{sequence::GenericSequence, typenum, ArrayLength, GenericArray};
 use paired::bls12_381;
 use paired::bls12_381::Bls12;
+use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
 use typenum::marker_traits::Unsigned;
 use typenum::*;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
 
 /// The arity tag is the first element of a Poseidon permutation.
 /// This extra element is necessary for 128-bit security.
@@ -88,7 +97,12 @@ impl_arity!(
 );
 
 /// The `Poseidon` structure will accept a number of inputs equal to the arity.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// A hasher borrows its constants and owns only its state of `width` elements, so cloning it copies the state and
+/// the reference, never the constant tables. It is `Send` and `Sync` for the engines this crate supports: borrow
+/// `'static` constants, such as `POSEIDON_CONSTANTS_4`, to move hashers or their clones into other threads, or lend
+/// hashers over local constants from a `pool::HasherPool`.
+#[derive(Clone, PartialEq)]
 pub struct Poseidon<'a, E, A = U2>
 where
     E: ScalarEngine,
@@ -103,6 +117,22 @@ where
     _e: PhantomData<E>,
 }
 
+/// Shows the state and the position in the round schedule, but not the constants, which would drown them out.
+impl<'a, E, A> fmt::Debug for Poseidon<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Poseidon")
+            .field("elements", &self.elements)
+            .field("pos", &self.pos)
+            .field("constants_offset", &self.constants_offset)
+            .field("current_round", &self.current_round)
+            .finish()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PoseidonConstants<E, A>
 where
@@ -114,14 +144,34 @@ where
     pub compressed_round_constants: Vec<E::Fr>,
     pub pre_sparse_matrix: Matrix<E::Fr>,
     pub sparse_matrixes: Vec<SparseMatrix<E>>,
-    pub arity_tag: E::Fr,
+    pub hash_type: HashType,
+    pub domain_tag: E::Fr,
     pub full_rounds: usize,
     pub half_full_rounds: usize,
     pub partial_rounds: usize,
+    pub alpha: Alpha,
     _a: PhantomData<A>,
 }
 
-#[derive(Debug, PartialEq)]
+/// The state after each round of a permutation, oldest first. Values are optional so that circuits, whose witnesses
+/// may be missing, record the same type.
+pub type RoundTrace<Fr> = Vec<Vec<Option<Fr>>>;
+
+/// The first round at which two traces differ, if any.
+pub fn trace_divergence<Fr: PartialEq>(a: &RoundTrace<Fr>, b: &RoundTrace<Fr>) -> Option<usize> {
+    a.iter()
+        .zip(b.iter())
+        .position(|(a, b)| a != b)
+        .or_else(|| {
+            if a.len() == b.len() {
+                None
+            } else {
+                Some(std::cmp::min(a.len(), b.len()))
+            }
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HashMode {
     // The initial and correct version of the algorithm. We should preserve the ability to hash this way for reference
     // and to preserve confidence in our tests along thew way.
@@ -146,14 +196,104 @@ where
     }
 
     pub fn new_with_strength(strength: Strength) -> Self {
+        let (full_rounds, partial_rounds) = round_numbers(A::to_usize(), &strength);
+
+        Self::new_with_rounds(full_rounds, partial_rounds)
+    }
+
+    /// Create default constants for `hash_type`. Its domain tag is placed in the first element of every state hashed
+    /// with them, in and out of circuit. `new` uses `HashType::MerkleTree`, the arity tag.
+    pub fn new_with_hash_type(hash_type: HashType) -> Self {
+        let mut constants = Self::new();
+        constants.hash_type = hash_type;
+        constants.domain_tag = hash_type.domain_tag::<E::Fr, A>();
+
+        constants
+    }
+
+    /// Create constants for explicit round counts, rather than those of a `Strength`. The round constants are
+    /// generated for exactly these counts, so the scalar hash and the circuit built from the result always agree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `full_rounds` is not a positive even number (they are split evenly around the partial rounds), or if
+    /// `partial_rounds` is zero.
+    pub fn new_with_rounds(full_rounds: usize, partial_rounds: usize) -> Self {
+        Self::new_with_rounds_and_alpha(full_rounds, partial_rounds, DEFAULT_ALPHA)
+    }
+
+    /// Create constants for `strength` with S-box exponent `alpha`. The round constants are the same for every
+    /// exponent, as the reference seed does not encode it; only the S-box changes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if x^`alpha` is not a permutation of the field.
+    pub fn new_with_alpha(strength: Strength, alpha: Alpha) -> Self {
+        let (full_rounds, partial_rounds) = round_numbers(A::to_usize(), &strength);
+
+        Self::new_with_rounds_and_alpha(full_rounds, partial_rounds, alpha)
+    }
+
+    /// Create constants for explicit round counts and S-box exponent. See `new_with_rounds` for the requirements on
+    /// the round counts.
+    pub fn new_with_rounds_and_alpha(
+        full_rounds: usize,
+        partial_rounds: usize,
+        alpha: Alpha,
+    ) -> Self {
+        Self::try_new_with_rounds_and_alpha(full_rounds, partial_rounds, alpha)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// As `new_with_rounds_and_alpha`, returning a `ParameterError` rather than panicking if the round counts are
+    /// invalid, `alpha` is not coprime to p - 1, or the generated constants fail validation.
+    pub fn try_new_with_rounds_and_alpha(
+        full_rounds: usize,
+        partial_rounds: usize,
+        alpha: Alpha,
+    ) -> Result<Self, ParameterError> {
+        let (mds_matrices, round_constants) =
+            generate_parameters::<E>(A::to_usize(), full_rounds, partial_rounds, alpha)?;
+
+        Ok(Self::from_parameters(
+            mds_matrices,
+            round_constants,
+            full_rounds,
+            partial_rounds,
+            alpha,
+        ))
+    }
+
+    /// Create default constants with `mds` as the MDS matrix in place of the generated one, such as a matrix from
+    /// `generate_mds_cauchy` matching another implementation. The matrix must be `width` square and pass
+    /// `validate_mds`. The circuits use the matrix of the constants they are given, so they agree with the scalar hash.
+    pub fn try_new_with_mds(mds: Matrix<E::Fr>) -> Result<Self, ParameterError> {
         let arity = A::to_usize();
-        let width = arity + 1;
+        let (full_rounds, partial_rounds) = round_numbers(arity, &DEFAULT_STRENGTH);
 
-        let mds_matrices = create_mds_matrices::<E>(width);
+        validate_mds::<E>(&mds, arity + 1)?;
+        let mds_matrices = try_derive_mds_matrices::<E>(mds)?;
+        let round_constants = generate_round_constants::<E>(arity, full_rounds, partial_rounds)?;
+
+        Ok(Self::from_parameters(
+            mds_matrices,
+            round_constants,
+            full_rounds,
+            partial_rounds,
+            DEFAULT_ALPHA,
+        ))
+    }
 
-        let (full_rounds, partial_rounds) = round_numbers(arity, &strength);
+    /// Precompute the optimized forms of validated parameters.
+    fn from_parameters(
+        mds_matrices: MDSMatrices<E>,
+        round_constants: Vec<E::Fr>,
+        full_rounds: usize,
+        partial_rounds: usize,
+        alpha: Alpha,
+    ) -> Self {
+        let width = A::to_usize() + 1;
         let half_full_rounds = full_rounds / 2;
-        let round_constants = round_constants::<E>(arity, &strength);
         let compressed_round_constants = compress_round_constants::<E>(
             width,
             full_rounds,
@@ -166,12 +306,6 @@ where
         let (pre_sparse_matrix, sparse_matrixes) =
             factor_to_sparse_matrixes::<E>(mds_matrices.m.clone(), partial_rounds);
 
-        // Ensure we have enough constants for the sbox rounds
-        assert!(
-            width * (full_rounds + partial_rounds) <= round_constants.len(),
-            "Not enough round constants"
-        );
-
         assert_eq!(
             full_rounds * width + partial_rounds,
             compressed_round_constants.len()
@@ -183,10 +317,12 @@ where
             compressed_round_constants,
             pre_sparse_matrix,
             sparse_matrixes,
-            arity_tag: A::tag(),
+            hash_type: HashType::MerkleTree,
+            domain_tag: HashType::MerkleTree.domain_tag::<E::Fr, A>(),
             full_rounds,
             half_full_rounds,
             partial_rounds,
+            alpha,
             _a: PhantomData::<A>,
         }
     }
@@ -202,6 +338,120 @@ where
     pub fn width(&self) -> usize {
         A::ConstantsSize::to_usize()
     }
+
+    /// Returns the arity tag, the domain tag of `HashType::MerkleTree`. This was the `arity_tag` field, before the
+    /// constants carried a `domain_tag` for their hash type; the two differ for any other hash type.
+    #[inline]
+    pub fn arity_tag(&self) -> E::Fr {
+        A::tag()
+    }
+}
+
+/// Validate `alpha`, then generate and validate the MDS matrices and round constants for `arity`. The round counts are
+/// validated before the round constants are generated.
+fn generate_parameters<E: ScalarEngine>(
+    arity: usize,
+    full_rounds: usize,
+    partial_rounds: usize,
+    alpha: Alpha,
+) -> Result<(MDSMatrices<E>, Vec<E::Fr>), ParameterError> {
+    check_alpha::<E>(alpha)?;
+
+    let mds_matrices = try_create_mds_matrices::<E>(arity + 1)?;
+    let round_constants = generate_round_constants::<E>(arity, full_rounds, partial_rounds)?;
+
+    Ok((mds_matrices, round_constants))
+}
+
+fn check_rounds(full_rounds: usize, partial_rounds: usize) -> Result<(), ParameterError> {
+    if full_rounds == 0 || full_rounds % 2 != 0 || partial_rounds == 0 {
+        return Err(ParameterError::InvalidRounds {
+            full_rounds,
+            partial_rounds,
+        });
+    }
+
+    Ok(())
+}
+
+/// x^`alpha` permutes the field exactly when `alpha` is coprime to p - 1.
+fn check_alpha<E: ScalarEngine>(alpha: Alpha) -> Result<(), ParameterError> {
+    let exponent = alpha.exponent();
+
+    // p mod alpha, from the most significant limb down, then gcd(alpha, p - 1) by Euclid.
+    let p = E::Fr::char()
+        .as_ref()
+        .iter()
+        .rev()
+        .fold(0u128, |acc, limb| {
+            ((acc << 64) | *limb as u128) % exponent as u128
+        }) as u64;
+    let (mut a, mut b) = (exponent, (p + exponent - 1) % exponent);
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+
+    if a != 1 {
+        return Err(ParameterError::InvalidAlpha(exponent));
+    }
+
+    Ok(())
+}
+
+/// Validate the round counts, then generate the round constants for `arity` and check there are enough of them.
+fn generate_round_constants<E: ScalarEngine>(
+    arity: usize,
+    full_rounds: usize,
+    partial_rounds: usize,
+) -> Result<Vec<E::Fr>, ParameterError> {
+    check_rounds(full_rounds, partial_rounds)?;
+
+    let width = arity + 1;
+    let round_constants = round_constants::<E>(arity, full_rounds, partial_rounds);
+
+    // Ensure we have enough constants for the sbox rounds
+    let needed = width * (full_rounds + partial_rounds);
+    if round_constants.len() < needed {
+        return Err(ParameterError::NotEnoughRoundConstants {
+            needed,
+            generated: round_constants.len(),
+        });
+    }
+
+    Ok(round_constants)
+}
+
+/// Overwrites every state element with zero. The constants are public and are not touched.
+#[cfg(feature = "zeroize")]
+impl<'a, E, A> Zeroize for Poseidon<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    fn zeroize(&mut self) {
+        for element in self.elements.iter_mut() {
+            // Volatile writes, which the compiler may not elide even though the state is never read again.
+            unsafe { std::ptr::write_volatile(element, E::Fr::zero()) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+        self.pos = 1;
+        self.constants_offset = 0;
+        self.current_round = 0;
+    }
+}
+
+/// With the `zeroize` feature, dropping a hasher wipes its state.
+#[cfg(feature = "zeroize")]
+impl<'a, E, A> Drop for Poseidon<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    fn drop(&mut self) {
+        self.zeroize();
+    }
 }
 
 impl<'a, E, A> Poseidon<'a, E, A>
@@ -212,7 +462,7 @@ where
     pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
         let elements = GenericArray::generate(|i| {
             if i == 0 {
-                constants.arity_tag
+                constants.domain_tag
             } else {
                 E::Fr::zero()
             }
@@ -226,12 +476,55 @@ where
             _e: PhantomData::<E>,
         }
     }
+
+    /// Create a hasher whose state is the domain tag followed by `preimage`, ready to hash.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `preimage` does not hold exactly `arity` elements. See `try_new_with_preimage`.
     pub fn new_with_preimage(preimage: &[E::Fr], constants: &'a PoseidonConstants<E, A>) -> Self {
-        assert_eq!(preimage.len(), A::to_usize(), "Invalid preimage size");
+        Self::try_new_with_preimage(preimage, constants).expect("Invalid preimage size")
+    }
+
+    /// As `new_with_preimage`, for a preimage whose length is checked at compile time.
+    pub fn new_with_array(
+        preimage: &GenericArray<E::Fr, A>,
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Self {
+        Self::new_with_preimage(preimage, constants)
+    }
+
+    /// As `new_with_preimage`, for constants shared through an `Arc`, such as one set built at startup and cloned into
+    /// each thread, in place of the default statics. The hasher borrows the shared constants, so it cannot outlive the
+    /// `Arc` it is given; the digest is the one `new_with_preimage` gives over the same constants.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `preimage` does not hold exactly `arity` elements.
+    pub fn new_with_constants(
+        preimage: &[E::Fr],
+        constants: &'a Arc<PoseidonConstants<E, A>>,
+    ) -> Self {
+        Self::new_with_preimage(preimage, constants)
+    }
+
+    /// Create a hasher from a preimage of any slice type, such as a window of a tree layer, copying it into the state.
+    /// Returns `Error::Other` unless `preimage` holds exactly `arity` elements.
+    pub fn try_new_with_preimage(
+        preimage: &[E::Fr],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, Error> {
+        if preimage.len() != A::to_usize() {
+            return Err(Error::Other(format!(
+                "expected a preimage of {} elements, got {}",
+                A::to_usize(),
+                preimage.len()
+            )));
+        }
 
         let elements = GenericArray::generate(|i| {
             if i == 0 {
-                constants.arity_tag
+                constants.domain_tag
             } else {
                 preimage[i - 1]
             }
@@ -239,14 +532,60 @@ where
 
         let width = elements.len();
 
-        Poseidon {
+        Ok(Poseidon {
             constants_offset: 0,
             current_round: 0,
             elements,
             pos: width,
             constants,
             _e: PhantomData::<E>,
+        })
+    }
+
+    /// Create a hasher and input the elements `iter` yields, without collecting them first. Returns
+    /// `Error::FullBuffer` if it yields more than `arity` elements. Fewer are accepted: `hash` zero-pads them, and
+    /// `try_hash` rejects them.
+    pub fn try_from_iter<I>(constants: &'a PoseidonConstants<E, A>, iter: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = E::Fr>,
+    {
+        let mut p = Poseidon::new(constants);
+        for element in iter {
+            p.input(element)?;
         }
+
+        Ok(p)
+    }
+
+    /// The state: the domain tag (or, after hashing, the capacity element) followed by the rate elements.
+    pub fn elements(&self) -> &[E::Fr] {
+        &self.elements
+    }
+
+    /// The index in `elements` the next `input` will write to.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Number of compressed round constants consumed so far.
+    pub fn constants_offset(&self) -> usize {
+        self.constants_offset
+    }
+
+    /// Overwrite the `i`th element of the state, to inject faults in tests.
+    #[cfg(feature = "test-utils")]
+    pub fn set_element(&mut self, i: usize, element: E::Fr) {
+        self.elements[i] = element;
+    }
+
+    /// Hash as `hash` does, then wipe the state, so that neither the preimage nor any intermediate value of the
+    /// permutation remains in the hasher. The hasher must be `reset` before it is used again.
+    #[cfg(feature = "zeroize")]
+    pub fn hash_then_zeroize(&mut self) -> E::Fr {
+        let digest = self.hash();
+        self.zeroize();
+
+        digest
     }
 
     /// Replace the elements with the provided optional items.
@@ -267,15 +606,16 @@ where
         self.elements[1..]
             .iter_mut()
             .for_each(|l| *l = scalar_from_u64::<E::Fr>(0u64));
-        self.elements[0] = self.constants.arity_tag;
+        self.elements[0] = self.constants.domain_tag;
         self.pos = 1;
     }
 
-    /// The returned `usize` represents the element position (within arity) for the input operation
+    /// The returned `usize` represents the element position (within arity) for the input operation. Returns
+    /// `Error::FullBuffer` with the arity, leaving the state untouched, if `arity` elements have already been input.
     pub fn input(&mut self, element: E::Fr) -> Result<usize, Error> {
         // Cannot input more elements than the defined arity
         if self.pos >= self.constants.width() {
-            return Err(Error::FullBuffer);
+            return Err(Error::FullBuffer(A::to_usize()));
         }
 
         // Set current element, and increase the pointer
@@ -287,33 +627,166 @@ where
 
     pub fn hash_in_mode(&mut self, mode: HashMode) -> E::Fr {
         match mode {
-            Correct => hash_correct(self),
-            OptimizedDynamic => hash_optimized_dynamic(self),
+            Correct => self.hash_correct(),
+            OptimizedDynamic => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_hashes(1, self.pos - 1);
+
+                hash_optimized_dynamic(self)
+            }
             OptimizedStatic => self.hash_optimized_static(),
         }
     }
 
+    /// Hash the elements input so far. Any of the `arity` elements not yet input are zero, so a short preimage is
+    /// zero-padded; use `try_hash` to reject one instead. The digest is computed in `DEFAULT_HASH_MODE`,
+    /// `OptimizedStatic`, which uses round constants folded through the MDS matrix and sparse matrices for the partial
+    /// rounds; every mode gives the same digest.
     pub fn hash(&mut self) -> E::Fr {
         self.hash_in_mode(DEFAULT_HASH_MODE)
     }
 
+    /// Hash as the specification describes it: each round adds the full width of unmodified round constants, applies
+    /// the S-box, and multiplies by the dense MDS matrix. This is `HashMode::Correct`, kept deliberately naive as the
+    /// reference every optimized mode is tested against, and is much slower than `hash`.
+    pub fn hash_correct(&mut self) -> E::Fr {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hashes(1, self.pos - 1);
+
+        hash_correct(self)
+    }
+
+    /// As `hash`, writing the digest to `out`, such as an element of a preallocated layer.
+    pub fn hash_into(&mut self, out: &mut E::Fr) {
+        *out = self.hash();
+    }
+
+    /// As `hash`, but returns `Error::Other` without hashing unless all `arity` elements have been provided.
+    pub fn try_hash(&mut self) -> Result<E::Fr, Error> {
+        if self.pos != self.constants.width() {
+            return Err(Error::Other(format!(
+                "expected a preimage of {} elements, got {}",
+                A::to_usize(),
+                self.pos - 1
+            )));
+        }
+
+        Ok(self.hash())
+    }
+
+    /// The digest `hash` would return, computed on a copy of the state so that `self` is left as it was. The
+    /// preimage stays readable in `elements`, and hashing again (in any mode) gives the same digest.
+    pub fn hash_copy(&self) -> E::Fr {
+        self.clone().hash()
+    }
+
+    /// As `hash_copy`, in `mode`.
+    pub fn hash_copy_in_mode(&self, mode: HashMode) -> E::Fr {
+        self.clone().hash_in_mode(mode)
+    }
+
+    /// Hash `preimage`, such as a chunk of a tree layer, with this hasher's constants, reading it straight from the
+    /// caller's slice. The working state is first written by adding the initial round constants to the domain tag and
+    /// `preimage`, so the preimage is never copied in on its own. This hasher's own state is neither read nor changed,
+    /// so it need not be cloned or reset between hashes.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `preimage` holds exactly `arity` elements.
+    pub fn hash_slice(&self, preimage: &[E::Fr]) -> E::Fr {
+        assert_eq!(
+            A::to_usize(),
+            preimage.len(),
+            "expected a preimage of {} elements",
+            A::to_usize()
+        );
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hashes(1, A::to_usize());
+
+        // The first addition of round constants, fused with reading the slice.
+        let round_constants = &self.constants.compressed_round_constants;
+        let mut state = Poseidon {
+            constants_offset: self.constants.width(),
+            current_round: 0,
+            elements: GenericArray::generate(|i| {
+                let mut element = if i == 0 {
+                    self.constants.domain_tag
+                } else {
+                    preimage[i - 1]
+                };
+                element.add_assign(&round_constants[i]);
+                element
+            }),
+            pos: self.constants.width(),
+            constants: self.constants,
+            _e: PhantomData::<E>,
+        };
+
+        state.hash_rounds_traced(None)
+    }
+
+    /// Hash the elements input so far, zero-padded as by `hash`, to `n` outputs. The first `arity` outputs are the
+    /// rate elements of the permuted state, and each further `arity` are read after permuting again. The state's
+    /// first element is replaced by the `HashType::Squeeze` tag for `n`, so the outputs differ from the digest `hash`
+    /// returns and from those of any other output count.
+    pub fn hash_squeeze(&mut self, n: usize) -> Vec<E::Fr> {
+        // Each permutation counts as a hash, and the preimage is counted once.
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hashes(0, self.pos - 1);
+
+        self.elements[0] = HashType::Squeeze(n).domain_tag::<E::Fr, A>();
+
+        let mut outputs = Vec::with_capacity(n);
+        while outputs.len() < n {
+            self.permute();
+            let take = std::cmp::min(A::to_usize(), n - outputs.len());
+            outputs.extend_from_slice(&self.elements[1..=take]);
+        }
+
+        outputs
+    }
+
     pub fn hash_optimized_static(&mut self) -> E::Fr {
+        self.hash_optimized_static_traced(None)
+    }
+
+    /// Hash as `hash_optimized_static` does, appending the state after each round to `trace` if given.
+    fn hash_optimized_static_traced(&mut self, trace: Option<&mut RoundTrace<E::Fr>>) -> E::Fr {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hashes(1, self.pos - 1);
+
         // The first full round should use the initial constants.
         self.add_round_constants();
 
+        self.hash_rounds_traced(trace)
+    }
+
+    /// The rounds of `hash_optimized_static`, once the initial round constants have been added to the state.
+    fn hash_rounds_traced(&mut self, mut trace: Option<&mut RoundTrace<E::Fr>>) -> E::Fr {
+        let mut record = |elements: &[E::Fr]| {
+            if let Some(trace) = trace.as_mut() {
+                trace.push(elements.iter().cloned().map(Some).collect());
+            }
+        };
+
         for _ in 0..self.constants.half_full_rounds {
             self.full_round(false);
+            record(&self.elements);
         }
 
         for _ in 0..self.constants.partial_rounds {
             self.partial_round();
+            record(&self.elements);
         }
 
         // All but last full round.
         for _ in 1..self.constants.half_full_rounds {
             self.full_round(false);
+            record(&self.elements);
         }
         self.full_round(true);
+        record(&self.elements);
 
         assert_eq!(
             self.constants_offset,
@@ -326,7 +799,32 @@ where
         self.elements[1]
     }
 
+    /// Hash, returning the state after each round rather than the digest, which is the second element of the last
+    /// state. Compare with `circuit::poseidon_hash_trace` to find the round at which a circuit stops matching.
+    #[cfg(feature = "trace")]
+    pub fn trace(&mut self) -> RoundTrace<E::Fr> {
+        let mut trace =
+            Vec::with_capacity(self.constants.full_rounds + self.constants.partial_rounds);
+        self.hash_optimized_static_traced(Some(&mut trace));
+
+        trace
+    }
+
+    /// Apply the permutation to the current elements in place, starting from the first round. Unlike `reset`, the
+    /// elements themselves are kept, so this can be called repeatedly on evolving state (as a sponge does).
+    pub(crate) fn permute(&mut self) {
+        // A permutation counts as a hash, but inputs no preimage: callers such as a sponge count what they absorb.
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hashes(1, 0);
+
+        self.constants_offset = 0;
+        self.current_round = 0;
+        self.add_round_constants();
+        self.hash_rounds_traced(None);
+    }
+
     fn full_round(&mut self, last_round: bool) {
+        let alpha = self.constants.alpha;
         let to_take = self.elements.len();
         let post_round_keys = self
             .constants
@@ -354,13 +852,13 @@ where
                 } else {
                     Some(post)
                 };
-                quintic_s_box::<E>(l, None, post_key);
+                alpha_s_box::<E>(l, None, post_key, alpha);
             });
         // We need this because post_round_keys will have been empty, so it didn't happen in the for_each. :(
         if last_round {
             self.elements
                 .iter_mut()
-                .for_each(|l| quintic_s_box::<E>(l, None, None));
+                .for_each(|l| alpha_s_box::<E>(l, None, None, alpha));
         } else {
             self.constants_offset += self.elements.len();
         }
@@ -371,8 +869,13 @@ where
     fn partial_round(&mut self) {
         let post_round_key = self.constants.compressed_round_constants[self.constants_offset];
 
-        // Apply the quintic S-Box to the first element
-        quintic_s_box::<E>(&mut self.elements[0], None, Some(&post_round_key));
+        // Apply the S-Box to the first element
+        alpha_s_box::<E>(
+            &mut self.elements[0],
+            None,
+            Some(&post_round_key),
+            self.constants.alpha,
+        );
         self.constants_offset += 1;
 
         self.round_product_mds();
@@ -423,126 +926,1223 @@ where
     pub(crate) fn product_mds_with_matrix(&mut self, matrix: &Matrix<E::Fr>) {
         let mut result = GenericArray::<E::Fr, A::ConstantsSize>::generate(|_| E::Fr::zero());
 
-        for (j, val) in result.iter_mut().enumerate() {
-            for (i, row) in matrix.iter().enumerate() {
-                let mut tmp = row[j];
-                tmp.mul_assign(&self.elements[i]);
+        // Row by row, so that each row is read contiguously. Zipping, rather than indexing, lets the compiler drop the
+        // bounds checks.
+        for (row, element) in matrix.iter().zip(self.elements.iter()) {
+            for (val, m) in result.iter_mut().zip(row.iter()) {
+                let mut tmp = *m;
+                tmp.mul_assign(element);
                 val.add_assign(&tmp);
             }
         }
 
-        let _ = std::mem::replace(&mut self.elements, result);
+        self.elements = result;
     }
 
     // Sparse matrix in this context means one of the form, M''.
     fn product_mds_with_sparse_matrix(&mut self, sparse_matrix: &SparseMatrix<E>) {
         let mut result = GenericArray::<E::Fr, A::ConstantsSize>::generate(|_| E::Fr::zero());
+        let first = self.elements[0];
 
         // First column is dense.
-        for (i, val) in sparse_matrix.w_hat.iter().enumerate() {
-            let mut tmp = *val;
-            tmp.mul_assign(&self.elements[i]);
+        for (w, element) in sparse_matrix.w_hat.iter().zip(self.elements.iter()) {
+            let mut tmp = *w;
+            tmp.mul_assign(element);
             result[0].add_assign(&tmp);
         }
 
-        for (j, val) in result.iter_mut().enumerate().skip(1) {
+        for ((val, element), v) in result
+            .iter_mut()
+            .zip(self.elements.iter())
+            .skip(1)
+            .zip(sparse_matrix.v_rest.iter())
+        {
             // Except for first row/column, diagonals are one.
-            val.add_assign(&self.elements[j]);
+            *val = *element;
 
             // First row is dense.
-            let mut tmp = sparse_matrix.v_rest[j - 1];
-            tmp.mul_assign(&self.elements[0]);
+            let mut tmp = *v;
+            tmp.mul_assign(&first);
             val.add_assign(&tmp);
         }
 
-        let _ = std::mem::replace(&mut self.elements, result);
-    }
-
-    fn debug(&self, msg: &str) {
-        dbg!(msg, &self.constants_offset, &self.elements);
+        self.elements = result;
     }
 }
 
-#[derive(Debug)]
-pub struct SimplePoseidonBatchHasher<A>
+/// Input each element in turn, as `input` does.
+///
+/// # Panics
+///
+/// Panics if more than `arity` elements are input in all. Use `Poseidon::try_from_iter` to get an error instead.
+impl<'a, E, A> Extend<E::Fr> for Poseidon<'a, E, A>
 where
-    A: Arity<bls12_381::Fr>,
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
 {
-    constants: PoseidonConstants<Bls12, A>,
-    max_batch_size: usize,
+    fn extend<I: IntoIterator<Item = E::Fr>>(&mut self, iter: I) {
+        for element in iter {
+            if let Err(e) = self.input(element) {
+                panic!("{}", e);
+            }
+        }
+    }
 }
 
-impl<A> SimplePoseidonBatchHasher<A>
-where
-    A: Arity<bls12_381::Fr>,
+fn generate_default<A: Arity<bls12_381::Fr>>() -> Result<PoseidonConstants<Bls12, A>, ParameterError>
 {
-    pub(crate) fn new(max_batch_size: usize) -> Result<Self, Error> {
-        Self::new_with_strength(DEFAULT_STRENGTH, max_batch_size)
-    }
+    let (full_rounds, partial_rounds) = round_numbers(A::to_usize(), &DEFAULT_STRENGTH);
 
-    pub(crate) fn new_with_strength(
-        strength: Strength,
-        max_batch_size: usize,
-    ) -> Result<Self, Error> {
-        Ok(Self {
-            constants: PoseidonConstants::<Bls12, A>::new_with_strength(strength),
-            max_batch_size,
-        })
-    }
+    PoseidonConstants::try_new_with_rounds_and_alpha(full_rounds, partial_rounds, DEFAULT_ALPHA)
 }
-impl<A> BatchHasher<A> for SimplePoseidonBatchHasher<A>
-where
-    A: Arity<bls12_381::Fr>,
-{
-    fn hash(
-        &mut self,
-        preimages: &[GenericArray<bls12_381::Fr, A>],
-    ) -> Result<Vec<bls12_381::Fr>, Error> {
-        Ok(preimages
-            .iter()
-            .map(|preimage| Poseidon::new_with_preimage(&preimage, &self.constants).hash())
-            .collect())
-    }
 
-    fn max_batch_size(&self) -> usize {
-        self.max_batch_size
-    }
+fn unwrap_default<A: Arity<bls12_381::Fr>>(
+    generated: &Result<PoseidonConstants<Bls12, A>, ParameterError>,
+) -> PoseidonConstants<Bls12, A> {
+    generated
+        .as_ref()
+        .map(Clone::clone)
+        .unwrap_or_else(|err| panic!("{}", err))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::*;
-    use ff::Field;
-    use generic_array::typenum;
-    use paired::bls12_381::{Bls12, Fr};
+lazy_static! {
+    // Each default set is generated and validated once, here; the public statics below are built from the result,
+    // and the `try_poseidon_constants_*` accessors return its error.
+    static ref GENERATED_2: Result<PoseidonConstants<Bls12, U2>, ParameterError> = generate_default();
+    static ref GENERATED_4: Result<PoseidonConstants<Bls12, U4>, ParameterError> = generate_default();
+    static ref GENERATED_8: Result<PoseidonConstants<Bls12, U8>, ParameterError> = generate_default();
+    static ref GENERATED_11: Result<PoseidonConstants<Bls12, U11>, ParameterError> = generate_default();
+    static ref GENERATED_16: Result<PoseidonConstants<Bls12, U16>, ParameterError> = generate_default();
+    static ref GENERATED_24: Result<PoseidonConstants<Bls12, U24>, ParameterError> = generate_default();
+    static ref GENERATED_36: Result<PoseidonConstants<Bls12, U36>, ParameterError> = generate_default();
+}
 
-    #[test]
-    fn reset() {
-        let test_arity = 2;
-        let preimage = vec![Scalar::one(); test_arity];
-        let constants = PoseidonConstants::new();
-        let mut h = Poseidon::<Bls12, U2>::new_with_preimage(&preimage, &constants);
-        h.hash();
-        h.reset();
+lazy_static! {
+    /// Default constants for arity 2, shared by `hash2` and `circuit::poseidon_hash2`.
+    pub static ref POSEIDON_CONSTANTS_2: PoseidonConstants<Bls12, U2> = unwrap_default(&GENERATED_2);
+    /// Default constants for arity 4, shared by `hash4` and `circuit::poseidon_hash4`.
+    pub static ref POSEIDON_CONSTANTS_4: PoseidonConstants<Bls12, U4> = unwrap_default(&GENERATED_4);
+    /// Default constants for arity 8, shared by `hash8` and `circuit::poseidon_hash8`.
+    pub static ref POSEIDON_CONSTANTS_8: PoseidonConstants<Bls12, U8> = unwrap_default(&GENERATED_8);
+    /// Default constants for arity 11, the column hash of Filecoin's sealed sectors.
+    pub static ref POSEIDON_CONSTANTS_11: PoseidonConstants<Bls12, U11> = unwrap_default(&GENERATED_11);
+    /// Default constants for arity 16.
+    pub static ref POSEIDON_CONSTANTS_16: PoseidonConstants<Bls12, U16> = unwrap_default(&GENERATED_16);
+    /// Default constants for arity 24.
+    pub static ref POSEIDON_CONSTANTS_24: PoseidonConstants<Bls12, U24> = unwrap_default(&GENERATED_24);
+    /// Default constants for arity 36.
+    pub static ref POSEIDON_CONSTANTS_36: PoseidonConstants<Bls12, U36> = unwrap_default(&GENERATED_36);
+}
 
-        let default = Poseidon::<Bls12, U2>::new(&constants);
-        assert_eq!(default.pos, h.pos);
-        assert_eq!(default.elements, h.elements);
-        assert_eq!(default.constants_offset, h.constants_offset);
-    }
+/// `POSEIDON_CONSTANTS_2`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_2() -> Result<&'static PoseidonConstants<Bls12, U2>, ParameterError> {
+    GENERATED_2.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_2)
+}
 
-    #[test]
-    fn hash_det() {
-        let test_arity = 2;
-        let mut preimage = vec![Scalar::zero(); test_arity];
-        let constants = PoseidonConstants::new();
-        preimage[0] = Scalar::one();
+/// `POSEIDON_CONSTANTS_4`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_4() -> Result<&'static PoseidonConstants<Bls12, U4>, ParameterError> {
+    GENERATED_4.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_4)
+}
 
-        let mut h = Poseidon::<Bls12, U2>::new_with_preimage(&preimage, &constants);
+/// `POSEIDON_CONSTANTS_8`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_8() -> Result<&'static PoseidonConstants<Bls12, U8>, ParameterError> {
+    GENERATED_8.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_8)
+}
 
-        let mut h2 = h.clone();
-        let result: <Bls12 as ScalarEngine>::Fr = h.hash();
+/// `POSEIDON_CONSTANTS_11`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_11() -> Result<&'static PoseidonConstants<Bls12, U11>, ParameterError>
+{
+    GENERATED_11.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_11)
+}
+
+/// `POSEIDON_CONSTANTS_16`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_16() -> Result<&'static PoseidonConstants<Bls12, U16>, ParameterError>
+{
+    GENERATED_16.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_16)
+}
+
+/// `POSEIDON_CONSTANTS_24`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_24() -> Result<&'static PoseidonConstants<Bls12, U24>, ParameterError>
+{
+    GENERATED_24.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_24)
+}
+
+/// `POSEIDON_CONSTANTS_36`, or the `ParameterError` generating it, which dereferencing the static panics with.
+pub fn try_poseidon_constants_36() -> Result<&'static PoseidonConstants<Bls12, U36>, ParameterError>
+{
+    GENERATED_36.as_ref().map_err(Clone::clone)?;
+    Ok(&*POSEIDON_CONSTANTS_36)
+}
+
+/// Build all the default constants now rather than on first use, so that the first `hash2`, `hash4` or `hash8` (or
+/// any hash through the statics above) does not pay for it. Call this once at startup from latency-sensitive
+/// services. Hashers over their own constants, such as one `PoseidonConstants` shared through an `Arc`, never touch
+/// the statics and need no warming.
+pub fn init_default_constants() {
+    lazy_static::initialize(&POSEIDON_CONSTANTS_2);
+    lazy_static::initialize(&POSEIDON_CONSTANTS_4);
+    lazy_static::initialize(&POSEIDON_CONSTANTS_8);
+    lazy_static::initialize(&POSEIDON_CONSTANTS_11);
+    lazy_static::initialize(&POSEIDON_CONSTANTS_16);
+    lazy_static::initialize(&POSEIDON_CONSTANTS_24);
+    lazy_static::initialize(&POSEIDON_CONSTANTS_36);
+}
+
+/// Build every default set of constants, as `init_default_constants` does, returning the first `ParameterError`, so
+/// that a service calling this at startup fails there rather than panicking wherever the constants are first used.
+/// Each set is generated and validated once, whether by this or on first use.
+pub fn init() -> Result<(), ParameterError> {
+    try_poseidon_constants_2()?;
+    try_poseidon_constants_4()?;
+    try_poseidon_constants_8()?;
+    try_poseidon_constants_11()?;
+    try_poseidon_constants_16()?;
+    try_poseidon_constants_24()?;
+    try_poseidon_constants_36()?;
+
+    Ok(())
+}
+
+/// Hash two elements with the default arity 2 constants.
+pub fn hash2(a: bls12_381::Fr, b: bls12_381::Fr) -> bls12_381::Fr {
+    Poseidon::new_with_preimage(&[a, b], &*POSEIDON_CONSTANTS_2).hash()
+}
+
+/// Hash four elements with the default arity 4 constants.
+pub fn hash4(preimage: [bls12_381::Fr; 4]) -> bls12_381::Fr {
+    Poseidon::new_with_preimage(&preimage, &*POSEIDON_CONSTANTS_4).hash()
+}
+
+/// Hash eight elements with the default arity 8 constants.
+pub fn hash8(preimage: [bls12_381::Fr; 8]) -> bls12_381::Fr {
+    Poseidon::new_with_preimage(&preimage, &*POSEIDON_CONSTANTS_8).hash()
+}
+
+/// Hash a preimage of at most `arity` elements. The preimage is zero-padded to the arity, and the capacity element holds
+/// the `HashType::ConstantLength` tag for its length, so the digest differs from that of the padded preimage (and from
+/// that of any other length). Returns `Error::FullBuffer` if the preimage is longer than the arity. An empty preimage
+/// is not an error: its digest is `hash_empty`.
+pub fn hash_variable<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    preimage: &[E::Fr],
+) -> Result<E::Fr, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = Poseidon::new(constants);
+    p.elements[0] = HashType::ConstantLength(preimage.len()).domain_tag::<E::Fr, A>();
+
+    for element in preimage {
+        p.input(*element)?;
+    }
+
+    Ok(p.hash())
+}
+
+/// The digest of the empty preimage, for roots of empty trees and the like: the permutation of the tag-only state,
+/// holding the `HashType::ConstantLength` tag for zero elements with every other element zero. `hash_variable` and
+/// `hash_u64s` both return it for an empty input. `hash_multi` hashes the empty preimage as the tag-only state under
+/// its own `HashType::Chained` tag, so it gives a different digest, and `hash_bytes` never returns it, since even an
+/// empty byte string packs to one element.
+pub fn hash_empty<E, A>(constants: &PoseidonConstants<E, A>) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = Poseidon::new(constants);
+    p.elements[0] = HashType::ConstantLength(0).domain_tag::<E::Fr, A>();
+
+    p.hash()
+}
+
+/// Hash a preimage of any length by chaining permutations.
+///
+/// Every block's capacity element holds the `HashType::Chained` tag for the length of the whole preimage. The first
+/// block takes up to `arity` elements. Each later block holds the previous block's digest in its first preimage slot,
+/// followed by up to `arity - 1` more elements. The final block is zero-padded, and the empty preimage is a single
+/// block of the tag alone. Since the length is in every tag, preimages differing only in trailing zeros hash
+/// differently, and since the tag is used by no other kind of hashing, no digest coincides with one of
+/// `hash_variable`, even for a preimage of a single block. `circuit::poseidon_hash_multi` computes the same digest in
+/// a circuit.
+pub fn hash_multi<E, A>(constants: &PoseidonConstants<E, A>, preimage: &[E::Fr]) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let tag = HashType::Chained(preimage.len()).domain_tag::<E::Fr, A>();
+
+    hash_chained(constants, tag, preimage)
+}
+
+/// The chaining of `hash_multi`, with `tag` in every block.
+fn hash_chained<E, A>(constants: &PoseidonConstants<E, A>, tag: E::Fr, preimage: &[E::Fr]) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let arity = A::to_usize();
+    let (first, rest) = preimage.split_at(std::cmp::min(arity, preimage.len()));
+
+    let hash_block = |elements: &[E::Fr]| {
+        let mut p = Poseidon::new(constants);
+        p.elements[0] = tag;
+        p.elements[1..=elements.len()].copy_from_slice(elements);
+        p.hash()
+    };
+
+    let mut digest = hash_block(first);
+    for chunk in rest.chunks(arity - 1) {
+        let mut block = Vec::with_capacity(arity);
+        block.push(digest);
+        block.extend_from_slice(chunk);
+        digest = hash_block(&block);
+    }
+
+    digest
+}
+
+/// Bytes packed into each element by `hash_bytes`. 31 bytes stay below the modulus of any field of at least 248 bits.
+pub const BYTES_PER_ELEMENT: usize = 31;
+
+/// Hash a byte string of any length.
+///
+/// A single 0x01 byte is appended, then the bytes are split into chunks of `BYTES_PER_ELEMENT` (the last may be
+/// shorter), and each chunk is read as a little-endian integer: its first byte is the least significant. The elements
+/// are chained as by `hash_multi`, but under the `HashType::Bytes` tag for their number, so no digest coincides with
+/// one of `hash_multi`, `hash_variable` or `hash_u64s` over the same elements.
+/// The padding byte marks the end of the data, so byte strings which differ only in trailing zeros hash differently,
+/// and the empty byte string hashes the single element one rather than the empty preimage of `hash_empty`.
+///
+/// # Panics
+///
+/// Panics if the field has a capacity below 248 bits, when chunks could exceed the modulus.
+pub fn hash_bytes<E, A>(constants: &PoseidonConstants<E, A>, bytes: &[u8]) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let elements = bytes_to_elements::<E>(bytes);
+    let tag = HashType::Bytes(elements.len()).domain_tag::<E::Fr, A>();
+
+    hash_chained(constants, tag, &elements)
+}
+
+/// Hash `data` personalized by `domain`, such as `b"merkle-leaf-v1"`, so that the same bytes hashed for two purposes
+/// give unrelated digests.
+///
+/// The domain, of any length, is hashed alone with `hash_bytes`. Its digest is followed by the elements `hash_bytes`
+/// packs `data` into, and these are chained as by `hash_multi`, but under the `HashType::Personalized` tag for their
+/// number rather than `HashType::Chained`. The digest therefore never coincides with a `hash_bytes` or
+/// `hash_multi` digest, even for an empty domain.
+///
+/// # Panics
+///
+/// Panics as `hash_bytes` does.
+pub fn hash_bytes_with_domain<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    domain: &[u8],
+    data: &[u8],
+) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut elements = vec![hash_bytes(constants, domain)];
+    elements.extend(bytes_to_elements::<E>(data));
+    let tag = HashType::Personalized(elements.len()).domain_tag::<E::Fr, A>();
+
+    hash_chained(constants, tag, &elements)
+}
+
+/// The elements `hash_bytes` hashes: `bytes`, then 0x01, in little-endian chunks of `BYTES_PER_ELEMENT`.
+fn bytes_to_elements<E: ScalarEngine>(bytes: &[u8]) -> Vec<E::Fr> {
+    assert!(
+        E::Fr::CAPACITY as usize >= 8 * BYTES_PER_ELEMENT,
+        "field too small for hash_bytes"
+    );
+
+    let mut padded = bytes.to_vec();
+    padded.push(1);
+
+    padded
+        .chunks(BYTES_PER_ELEMENT)
+        .map(|chunk| {
+            let mut repr = <E::Fr as PrimeField>::Repr::default();
+            let mut le_bytes = vec![0u8; 8 * repr.as_ref().len()];
+            le_bytes[..chunk.len()].copy_from_slice(chunk);
+            repr.read_le(&le_bytes[..])
+                .expect("reading a repr from a buffer of its size cannot fail");
+            E::Fr::from_repr(repr).expect("chunk is below the modulus")
+        })
+        .collect()
+}
+
+/// Hash integers such as counters, indices or timestamps. Each value becomes the field element equal to it, as
+/// `scalar_from_u64` converts it. The elements are chained as by `hash_multi`, zero-padding the last block, but under the
+/// `HashType::U64s` tag for their number, so no digest coincides with one of `hash_multi`, `hash_variable` or
+/// `hash_bytes`.
+pub fn hash_u64s<E, A>(constants: &PoseidonConstants<E, A>, values: &[u64]) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let elements = values
+        .iter()
+        .map(|value| scalar_from_u64::<E::Fr>(*value))
+        .collect::<Vec<_>>();
+    let tag = HashType::U64s(elements.len()).domain_tag::<E::Fr, A>();
+
+    hash_chained(constants, tag, &elements)
+}
+
+/// Hash each `arity` consecutive elements of `leaves`, returning the parent layer of digests in order. Returns
+/// `Error::Other` unless the number of leaves is a multiple of the arity; no leaves give an empty layer.
+/// `parallel::hash_layer` spreads the same work over threads.
+pub fn hash_layer<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    leaves: &[E::Fr],
+) -> Result<Vec<E::Fr>, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    check_layer_len::<E, A>(leaves.len())?;
+
+    let mut out = vec![E::Fr::zero(); leaves.len() / A::to_usize()];
+    hash_layer_into(constants, leaves, &mut out)?;
+
+    Ok(out)
+}
+
+pub(crate) fn check_layer_len<E, A>(len: usize) -> Result<(), Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    if len % A::to_usize() != 0 {
+        return Err(Error::Other(format!(
+            "expected a multiple of {} leaves, got {}",
+            A::to_usize(),
+            len
+        )));
+    }
+
+    Ok(())
+}
+
+/// Hash each `arity` consecutive elements of `leaves` into the corresponding element of `out`, such as a preallocated
+/// parent layer of a tree. Each chunk is hashed with `Poseidon::hash_slice`, which reads it straight from `leaves`,
+/// so nothing is copied or allocated per hash. Returns `Error::Other`, writing nothing, unless `leaves` holds exactly
+/// `arity` elements for each element of `out`.
+pub fn hash_layer_into<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    leaves: &[E::Fr],
+    out: &mut [E::Fr],
+) -> Result<(), Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    if out.len() * A::to_usize() != leaves.len() {
+        return Err(Error::Other(format!(
+            "expected {} leaves for {} digests at arity {}, got {}",
+            out.len() * A::to_usize(),
+            out.len(),
+            A::to_usize(),
+            leaves.len()
+        )));
+    }
+
+    let p = Poseidon::new(constants);
+    for (preimage, digest) in leaves.chunks(A::to_usize()).zip(out.iter_mut()) {
+        *digest = p.hash_slice(preimage);
+    }
+
+    Ok(())
+}
+
+/// Keyed pseudorandom function: hash `key` followed by `inputs` under the `HashType::Prf` domain tag, so the output
+/// never coincides with the plain hash of the same elements. `inputs` must hold exactly `arity - 1` elements;
+/// otherwise returns `Error::Other`.
+pub fn prf<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    key: E::Fr,
+    inputs: &[E::Fr],
+) -> Result<E::Fr, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    if inputs.len() + 1 != A::to_usize() {
+        return Err(Error::Other(format!(
+            "PRF takes {} inputs, got {}",
+            A::to_usize() - 1,
+            inputs.len()
+        )));
+    }
+
+    let mut p = Poseidon::new(constants);
+    p.elements[0] = HashType::Prf.domain_tag::<E::Fr, A>();
+    p.input(key)?;
+    for input in inputs {
+        p.input(*input)?;
+    }
+
+    Ok(p.hash())
+}
+
+/// Salted hash for commitments: hash `preimage` with a capacity element derived from `salt`, so the salt takes no rate
+/// slot and an arity 2 instance still commits to two elements.
+///
+/// The capacity element is the digest of `salt` alone: the state `[Salt tag, salt, 0, ..., 0]` under the
+/// `HashType::Salt` domain tag, permuted, second element. The salted digest is then the second element of the permuted
+/// state `[capacity, preimage...]`. Since the capacity is itself a digest, no salt yields another kind's domain tag.
+/// `preimage` must hold exactly `arity` elements; otherwise returns `Error::Other`. `circuit::poseidon_hash_with_salt`
+/// computes the same digest in a circuit.
+pub fn hash_with_salt<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    salt: E::Fr,
+    preimage: &[E::Fr],
+) -> Result<E::Fr, Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = Poseidon::try_new_with_preimage(preimage, constants)?;
+
+    let mut salt_hasher = Poseidon::new(constants);
+    salt_hasher.elements[0] = HashType::Salt.domain_tag::<E::Fr, A>();
+    salt_hasher.input(salt)?;
+    p.elements[0] = salt_hasher.hash();
+
+    Ok(p.hash())
+}
+
+/// The low `n_bits` bits of the canonical representation of `digest`, least significant first, for deriving a
+/// challenge from a digest. `circuit::digest_to_bits` computes the same bits in a circuit. Returns `Error::Other` if
+/// `n_bits` exceeds the field's bit length.
+pub fn digest_to_bits<Fr: PrimeField>(digest: &Fr, n_bits: usize) -> Result<Vec<bool>, Error> {
+    if n_bits > Fr::NUM_BITS as usize {
+        return Err(Error::Other(format!(
+            "cannot take {} bits of a {}-bit field element",
+            n_bits,
+            Fr::NUM_BITS
+        )));
+    }
+
+    let repr = digest.into_repr();
+    let limbs = repr.as_ref();
+
+    Ok((0..n_bits)
+        .map(|i| (limbs[i / 64] >> (i % 64)) & 1 == 1)
+        .collect())
+}
+
+/// The limbs of the canonical representation of `digest`, least significant first, as `scalar_from_u64s` and
+/// `fr_from_u64s` take them. Bit `i` of the digest, as `digest_to_bits` numbers it, is bit `i % 64` of limb `i / 64`.
+pub fn digest_to_u64s(digest: &bls12_381::Fr) -> [u64; 4] {
+    digest.into_repr().0
+}
+
+/// The low 128 bits of the canonical representation of `digest`: the first 128 bits `digest_to_bits` returns, with
+/// bit `i` as bit `i` of the result.
+pub fn digest_low_128(digest: &bls12_381::Fr) -> u128 {
+    let limbs = digest_to_u64s(digest);
+
+    u128::from(limbs[0]) | (u128::from(limbs[1]) << 64)
+}
+
+/// The field element with canonical representation `limbs`, least significant first, the inverse of
+/// `digest_to_u64s`. Returns `Error::DecodingError` if the limbs encode a value not below the modulus.
+pub fn fr_from_u64s(limbs: [u64; 4]) -> Result<bls12_381::Fr, Error> {
+    bls12_381::Fr::from_repr(bls12_381::FrRepr(limbs)).map_err(|_| Error::DecodingError)
+}
+
+/// Apply the Poseidon permutation to `state` in place. The whole state is permuted, including the first (capacity)
+/// element, and no domain tag is set: this is the building block for custom sponge or duplex modes.
+/// `circuit::poseidon_permutation` computes the same permutation in a circuit.
+pub fn poseidon_permute<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    state: &mut GenericArray<E::Fr, A::ConstantsSize>,
+) where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut p = Poseidon::new(constants);
+    p.elements = state.clone();
+    p.permute();
+
+    state.copy_from_slice(&p.elements);
+}
+
+/// As `poseidon_permute`, for a state held in a slice, such as part of a larger buffer. Returns `Error::Other`, leaving
+/// the state untouched, unless `state` holds exactly `width` elements.
+pub fn poseidon_permute_in_place<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    state: &mut [E::Fr],
+) -> Result<(), Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    if state.len() != constants.width() {
+        return Err(Error::Other(format!(
+            "expected a state of {} elements, got {}",
+            constants.width(),
+            state.len()
+        )));
+    }
+
+    let mut p = Poseidon::new(constants);
+    p.elements.copy_from_slice(state);
+    p.permute();
+    state.copy_from_slice(&p.elements);
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct SimplePoseidonBatchHasher<A>
+where
+    A: Arity<bls12_381::Fr>,
+{
+    constants: PoseidonConstants<Bls12, A>,
+    max_batch_size: usize,
+}
+
+impl<A> SimplePoseidonBatchHasher<A>
+where
+    A: Arity<bls12_381::Fr>,
+{
+    pub(crate) fn new(max_batch_size: usize) -> Result<Self, Error> {
+        Self::new_with_strength(DEFAULT_STRENGTH, max_batch_size)
+    }
+
+    pub(crate) fn new_with_strength(
+        strength: Strength,
+        max_batch_size: usize,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            constants: PoseidonConstants::<Bls12, A>::new_with_strength(strength),
+            max_batch_size,
+        })
+    }
+}
+impl<A> BatchHasher<A> for SimplePoseidonBatchHasher<A>
+where
+    A: Arity<bls12_381::Fr>,
+{
+    fn hash(
+        &mut self,
+        preimages: &[GenericArray<bls12_381::Fr, A>],
+    ) -> Result<Vec<bls12_381::Fr>, Error> {
+        Ok(preimages
+            .iter()
+            .map(|preimage| Poseidon::new_with_preimage(&preimage, &self.constants).hash())
+            .collect())
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use ff::Field;
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+    use rand::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn reset() {
+        let test_arity = 2;
+        let preimage = vec![Scalar::one(); test_arity];
+        let constants = PoseidonConstants::new();
+        let mut h = Poseidon::<Bls12, U2>::new_with_preimage(&preimage, &constants);
+        h.hash();
+        h.reset();
+
+        let default = Poseidon::<Bls12, U2>::new(&constants);
+        assert_eq!(default.pos, h.pos);
+        assert_eq!(default.elements, h.elements);
+        assert_eq!(default.constants_offset, h.constants_offset);
+    }
+
+    #[test]
+    fn reset_reuses_hasher() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let layer = (0..12).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        let mut h = Poseidon::new(&constants);
+        for preimage in layer.chunks(4) {
+            h.reset();
+            for x in preimage {
+                h.input(*x).unwrap();
+            }
+
+            assert_eq!(
+                Poseidon::new_with_preimage(preimage, &constants).hash(),
+                h.hash()
+            );
+        }
+    }
+
+    #[test]
+    fn try_new_with_preimage() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let layer = (0..6).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        for pair in layer.chunks(2) {
+            let mut h = Poseidon::try_new_with_preimage(pair, &constants).unwrap();
+            assert_eq!(
+                Poseidon::new_with_preimage(pair, &constants).hash(),
+                h.hash()
+            );
+        }
+
+        assert!(Poseidon::try_new_with_preimage(&layer[..1], &constants).is_err());
+        assert!(Poseidon::try_new_with_preimage(&layer[..3], &constants).is_err());
+    }
+
+    #[test]
+    fn input_fills_buffer() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let data = (1..=5).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        let mut h = Poseidon::new(&constants);
+        for (i, x) in data[..4].iter().enumerate() {
+            assert_eq!(i + 1, h.input(*x).unwrap());
+        }
+        let full = h.clone();
+
+        // Overflowing by one reports the arity and changes nothing.
+        match h.input(data[4]) {
+            Err(Error::FullBuffer(arity)) => assert_eq!(4, arity),
+            _ => panic!("expected Error::FullBuffer"),
+        }
+        assert_eq!(full.elements, h.elements);
+        assert_eq!(full.pos, h.pos);
+        assert_eq!(
+            Poseidon::new_with_preimage(&data[..4], &constants).hash(),
+            h.hash()
+        );
+
+        // After a reset, inputs interleaved with hashes start a fresh preimage each time.
+        for preimage in data.chunks(2).take(2) {
+            h.reset();
+            for x in preimage {
+                h.input(*x).unwrap();
+            }
+            let mut padded = preimage.to_vec();
+            padded.resize(4, Fr::zero());
+            assert_eq!(
+                Poseidon::new_with_preimage(&padded, &constants).hash(),
+                h.hash()
+            );
+        }
+    }
+
+    #[test]
+    fn hash_copy_keeps_state() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let preimage = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+        let h = Poseidon::new_with_preimage(&preimage, &constants);
+
+        let digest = h.hash_copy();
+        assert_eq!(digest, h.hash_copy());
+        assert_eq!(digest, h.hash_copy_in_mode(Correct));
+        assert_eq!(preimage[..], h.elements[1..]);
+        assert_eq!(digest, h.clone().hash());
+    }
+
+    #[test]
+    fn hash_slice() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        let leaves = (0..8 * 50)
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        let mut h = Poseidon::new(&constants);
+        h.input(Fr::one()).unwrap();
+        let before = h.clone();
+
+        for preimage in leaves.chunks(8) {
+            assert_eq!(
+                Poseidon::new_with_preimage(preimage, &constants).hash(),
+                h.hash_slice(preimage)
+            );
+        }
+        assert_eq!(before, h);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a preimage of 8 elements")]
+    fn hash_slice_wrong_length() {
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        Poseidon::new(&constants).hash_slice(&[Fr::one(); 7]);
+    }
+
+    #[test]
+    fn digest_decomposition() {
+        let digest = hash2(scalar_from_u64(1), scalar_from_u64(2));
+        let limbs = [
+            0x9317d9833e5fa9be,
+            0x69c04e726e5896d8,
+            0x7babcbaf436a9d76,
+            0x6d6f8106657f1f4d,
+        ];
+
+        assert_eq!(limbs, digest_to_u64s(&digest));
+        assert_eq!(0x69c04e726e5896d8_9317d9833e5fa9be, digest_low_128(&digest));
+        assert_eq!(digest, fr_from_u64s(limbs).unwrap());
+
+        // The low byte is 0xbe, least significant bit first.
+        let bits = digest_to_bits(&digest, 128).unwrap();
+        assert_eq!(
+            [false, true, true, true, true, true, false, true],
+            bits[..8]
+        );
+        let low_128 = digest_low_128(&digest);
+        for (i, bit) in bits.iter().enumerate() {
+            assert_eq!((low_128 >> i) & 1 == 1, *bit);
+        }
+
+        // The modulus itself, and anything above it, is not canonical.
+        let mut modulus = digest_to_u64s(&Fr::zero());
+        modulus.copy_from_slice(bls12_381::Fr::char().as_ref());
+        assert!(fr_from_u64s(modulus).is_err());
+        assert!(fr_from_u64s([u64::max_value(); 4]).is_err());
+        modulus[0] -= 1;
+        assert!(fr_from_u64s(modulus).is_ok());
+    }
+
+    #[test]
+    fn hash_layer() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        let leaves = (0..8 * 20)
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        let mut expected = Vec::new();
+        for preimage in leaves.chunks(8) {
+            expected.push(Poseidon::new_with_preimage(preimage, &constants).hash());
+        }
+
+        assert_eq!(expected, super::hash_layer(&constants, &leaves).unwrap());
+        assert_eq!(
+            expected[..1],
+            super::hash_layer(&constants, &leaves[..8]).unwrap()[..]
+        );
+        assert!(super::hash_layer(&constants, &[]).unwrap().is_empty());
+        assert!(super::hash_layer(&constants, &leaves[..12]).is_err());
+        assert!(super::hash_layer(&constants, &leaves[..7]).is_err());
+    }
+
+    #[test]
+    fn hash_layer_into() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let leaves = (0..64).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let expected = leaves
+            .chunks(4)
+            .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+            .collect::<Vec<_>>();
+
+        let mut layer = vec![Fr::zero(); 16];
+        super::hash_layer_into(&constants, &leaves, &mut layer).unwrap();
+        assert_eq!(expected, layer);
+
+        let mut h = Poseidon::new_with_preimage(&leaves[..4], &constants);
+        let mut digest = Fr::zero();
+        h.hash_into(&mut digest);
+        assert_eq!(expected[0], digest);
+
+        // Mismatched lengths are rejected, leaving the output untouched.
+        let mut short = vec![Fr::zero(); 15];
+        assert!(super::hash_layer_into(&constants, &leaves, &mut short).is_err());
+        assert!(super::hash_layer_into(&constants, &leaves[..63], &mut layer[..]).is_err());
+        assert!(super::hash_layer_into(&constants, &leaves[..60], &mut layer[..]).is_err());
+        assert!(short.iter().all(|x| *x == Fr::zero()));
+        assert_eq!(expected, layer);
+    }
+
+    #[test]
+    fn hash_squeeze() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let preimage = [scalar_from_u64::<Fr>(1), scalar_from_u64(2)];
+        let h = Poseidon::new_with_preimage(&preimage, &constants);
+
+        let outputs = h.clone().hash_squeeze(3);
+        assert_eq!(
+            vec![
+                scalar_from_u64s([
+                    0xbb649961d1aec720,
+                    0xa6172de441c450a9,
+                    0x323276ddbb309904,
+                    0x0f0543d169a4ec27,
+                ]),
+                scalar_from_u64s([
+                    0x616bf1bf3ae73d81,
+                    0xa26a3d4d8afaca4e,
+                    0xc354f964d6383dcb,
+                    0x4441abc19e3bd5b4,
+                ]),
+                scalar_from_u64s([
+                    0xb3c08fc41cc519a1,
+                    0xa74a4ca0ed07e25e,
+                    0xb347be4aa0402d28,
+                    0x3efb0b7a6afee3ab,
+                ]),
+            ],
+            outputs
+        );
+        assert_eq!(outputs, h.clone().hash_squeeze(3));
+
+        for n in 1..10 {
+            let outputs = h.clone().hash_squeeze(n);
+            assert_eq!(n, outputs.len());
+            for (i, a) in outputs.iter().enumerate() {
+                assert!(outputs[i + 1..].iter().all(|b| a != b));
+            }
+
+            // The output count is part of the tag, so fewer outputs are not a prefix of more.
+            assert_ne!(outputs[0], h.clone().hash_squeeze(n + 1)[0]);
+        }
+
+        assert_ne!(h.clone().hash(), h.clone().hash_squeeze(1)[0]);
+        assert!(h.clone().hash_squeeze(0).is_empty());
+    }
+
+    /// The matrix products as first written, indexing column by column.
+    fn product_mds_reference(elements: &[Fr], matrix: &Matrix<Fr>) -> Vec<Fr> {
+        (0..elements.len())
+            .map(|j| {
+                let mut val = Fr::zero();
+                for (i, row) in matrix.iter().enumerate() {
+                    let mut tmp = row[j];
+                    tmp.mul_assign(&elements[i]);
+                    val.add_assign(&tmp);
+                }
+                val
+            })
+            .collect()
+    }
+
+    fn product_sparse_reference(elements: &[Fr], sparse_matrix: &SparseMatrix<Bls12>) -> Vec<Fr> {
+        let mut result = vec![Fr::zero(); elements.len()];
+        for (i, val) in sparse_matrix.w_hat.iter().enumerate() {
+            let mut tmp = *val;
+            tmp.mul_assign(&elements[i]);
+            result[0].add_assign(&tmp);
+        }
+        for (j, val) in result.iter_mut().enumerate().skip(1) {
+            val.add_assign(&elements[j]);
+            let mut tmp = sparse_matrix.v_rest[j - 1];
+            tmp.mul_assign(&elements[0]);
+            val.add_assign(&tmp);
+        }
+        result
+    }
+
+    #[test]
+    fn product_mds_matches_reference() {
+        product_mds_matches_reference_aux::<U2>();
+        product_mds_matches_reference_aux::<typenum::U4>();
+        product_mds_matches_reference_aux::<typenum::U8>();
+        product_mds_matches_reference_aux::<typenum::U11>();
+    }
+
+    fn product_mds_matches_reference_aux<A>()
+    where
+        A: Arity<Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let mut p = Poseidon::new(&constants);
+
+        for _ in 0..10 {
+            p.elements
+                .iter_mut()
+                .for_each(|x| *x = Fr::random(&mut rng));
+            let elements = p.elements.to_vec();
+
+            for matrix in [&constants.mds_matrices.m, &constants.pre_sparse_matrix].iter() {
+                let mut q = p.clone();
+                q.product_mds_with_matrix(matrix);
+                assert_eq!(
+                    product_mds_reference(&elements, matrix),
+                    q.elements.to_vec()
+                );
+            }
+            for sparse_matrix in constants.sparse_matrixes.iter() {
+                let mut q = p.clone();
+                q.product_mds_with_sparse_matrix(sparse_matrix);
+                assert_eq!(
+                    product_sparse_reference(&elements, sparse_matrix),
+                    q.elements.to_vec()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn from_iter() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let data = (1..=5).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+        let expected = Poseidon::new_with_preimage(&data[..4], &constants).hash();
+
+        // Exact.
+        let mut p = Poseidon::try_from_iter(&constants, data.iter().cloned().take(4)).unwrap();
+        assert_eq!(expected, p.try_hash().unwrap());
+
+        let mut p = Poseidon::new(&constants);
+        p.extend(data.iter().cloned().take(2));
+        p.extend(data[2..4].iter().cloned());
+        assert_eq!(expected, p.hash());
+
+        // Short: rejected by `try_hash`, zero-padded by `hash`.
+        let mut p = Poseidon::try_from_iter(&constants, data.iter().cloned().take(3)).unwrap();
+        assert!(p.try_hash().is_err());
+        let mut padded = data[..3].to_vec();
+        padded.push(Fr::zero());
+        assert_eq!(
+            Poseidon::new_with_preimage(&padded, &constants).hash(),
+            p.hash()
+        );
+
+        // Long.
+        assert!(Poseidon::try_from_iter(&constants, data.iter().cloned()).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "The size of the buffer cannot be greater than the hash arity (4).")]
+    fn extend_too_long() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let mut p = Poseidon::new(&constants);
+        p.extend((1..=5).map(scalar_from_u64::<Fr>));
+    }
+
+    #[test]
+    fn new_with_array() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let preimage = GenericArray::<Fr, U4>::generate(|i| scalar_from_u64(i as u64));
+
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage, &constants).hash(),
+            Poseidon::new_with_array(&preimage, &constants).hash()
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let preimage = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        let p = Poseidon::new(&constants);
+        assert_eq!(constants.domain_tag, p.elements()[0]);
+        assert_eq!(1, p.pos());
+        assert_eq!(0, p.constants_offset());
+
+        let mut p = Poseidon::new_with_preimage(&preimage, &constants);
+        assert_eq!(constants.domain_tag, p.elements()[0]);
+        assert_eq!(&preimage[..], &p.elements()[1..]);
+        assert_eq!(5, p.pos());
+
+        p.hash();
+        assert_eq!(
+            constants.compressed_round_constants.len(),
+            p.constants_offset()
+        );
+
+        let debug = format!("{:?}", p);
+        assert!(debug.starts_with("Poseidon { elements: "));
+        assert!(!debug.contains("mds_matrices"));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn set_element() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let preimage = [scalar_from_u64::<Fr>(1), scalar_from_u64::<Fr>(2)];
+        let mut p = Poseidon::new_with_preimage(&preimage, &constants);
+        let digest = p.hash_copy();
+
+        p.set_element(2, scalar_from_u64(3));
+        assert_ne!(digest, p.hash());
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let preimage = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+        let mut p = Poseidon::new_with_preimage(&preimage, &constants);
+        let expected = p.hash_copy();
+
+        p.zeroize();
+        assert!(p.elements().iter().all(|x| *x == Fr::zero()));
+
+        p.set_preimage(&preimage);
+        assert_eq!(expected, p.hash_then_zeroize());
+        assert!(p.elements().iter().all(|x| *x == Fr::zero()));
+        assert_eq!(1, p.pos());
+        assert_eq!(0, p.constants_offset());
+    }
+
+    #[test]
+    fn init_validates_parameters() {
+        init().unwrap();
+
+        // The statics hold the constants `init` validated, which are the default ones.
+        assert_eq!(
+            PoseidonConstants::<Bls12, U2>::new().compressed_round_constants,
+            POSEIDON_CONSTANTS_2.compressed_round_constants
+        );
+        assert!(std::ptr::eq(
+            try_poseidon_constants_2().unwrap(),
+            &*POSEIDON_CONSTANTS_2
+        ));
+
+        for (full_rounds, partial_rounds) in &[(0, 57), (7, 57), (8, 0)] {
+            assert_eq!(
+                Some(ParameterError::InvalidRounds {
+                    full_rounds: *full_rounds,
+                    partial_rounds: *partial_rounds,
+                }),
+                PoseidonConstants::<Bls12, U2>::try_new_with_rounds_and_alpha(
+                    *full_rounds,
+                    *partial_rounds,
+                    Alpha::Five
+                )
+                .err()
+            );
+        }
+
+        // Three divides p - 1, so x^3 is not a permutation of the BLS12-381 scalar field.
+        assert_eq!(
+            Some(ParameterError::InvalidAlpha(3)),
+            PoseidonConstants::<Bls12, U2>::try_new_with_rounds_and_alpha(8, 55, Alpha::Three)
+                .err()
+        );
+        assert_eq!(
+            PoseidonConstants::<Bls12, U2>::new_with_rounds(8, 55).round_constants,
+            PoseidonConstants::<Bls12, U2>::try_new_with_rounds_and_alpha(8, 55, Alpha::Seven)
+                .unwrap()
+                .round_constants
+        );
+
+        let constants =
+            PoseidonConstants::<Bls12, U2>::try_new_with_rounds_and_alpha(8, 55, Alpha::Five)
+                .unwrap();
+        assert_eq!(
+            PoseidonConstants::<Bls12, U2>::new_with_rounds(8, 55).compressed_round_constants,
+            constants.compressed_round_constants
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "full_rounds (7) must be positive and even")]
+    fn odd_full_rounds() {
+        PoseidonConstants::<Bls12, U2>::new_with_rounds(7, 57);
+    }
+
+    #[test]
+    fn shared_constants() {
+        init_default_constants();
+
+        // Constants held in an `Arc` lend hashers the same tables as the statics, with the same digests, alongside
+        // constants of another strength.
+        let constants = Arc::new(PoseidonConstants::<Bls12, U2>::new());
+        let strengthened = Arc::new(PoseidonConstants::<Bls12, U2>::new_with_strength(
+            Strength::Strengthened,
+        ));
+        let (a, b) = (scalar_from_u64::<Fr>(1), scalar_from_u64::<Fr>(2));
+
+        assert_eq!(
+            hash2(a, b),
+            Poseidon::new_with_preimage(&[a, b], &constants).hash()
+        );
+        assert_ne!(
+            hash2(a, b),
+            Poseidon::new_with_preimage(&[a, b], &strengthened).hash()
+        );
+
+        // Each thread hashes over its own clone of the `Arc`, with the digest of the shared constants.
+        let handle = {
+            let strengthened = Arc::clone(&strengthened);
+            std::thread::spawn(move || Poseidon::new_with_constants(&[a, b], &strengthened).hash())
+        };
+        assert_eq!(
+            Poseidon::new_with_preimage(&[a, b], &strengthened).hash(),
+            handle.join().unwrap()
+        );
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn send_sync() {
+        assert_send_sync::<Poseidon<'static, Bls12, U4>>();
+        assert_send_sync::<PoseidonConstants<Bls12, U4>>();
+    }
+
+    #[test]
+    fn clone_into_threads() {
+        let template = std::sync::Arc::new(Poseidon::<Bls12, U4>::new(&*POSEIDON_CONSTANTS_4));
+        let preimage = |t: u64| {
+            (0..4)
+                .map(|i| scalar_from_u64::<Fr>(4 * t + i))
+                .collect::<Vec<_>>()
+        };
+
+        let threads = (0..8)
+            .map(|t| {
+                let template = std::sync::Arc::clone(&template);
+                std::thread::spawn(move || {
+                    let mut h = (*template).clone();
+                    for x in preimage(t) {
+                        h.input(x).unwrap();
+                    }
+                    (t, h.hash())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in threads {
+            let (t, digest) = handle.join().unwrap();
+            assert_eq!(
+                Poseidon::new_with_preimage(&preimage(t), &*POSEIDON_CONSTANTS_4).hash(),
+                digest
+            );
+        }
+        assert_eq!(1, template.pos());
+    }
+
+    #[test]
+    fn hash_det() {
+        let test_arity = 2;
+        let mut preimage = vec![Scalar::zero(); test_arity];
+        let constants = PoseidonConstants::new();
+        preimage[0] = Scalar::one();
+
+        let mut h = Poseidon::<Bls12, U2>::new_with_preimage(&preimage, &constants);
+
+        let mut h2 = h.clone();
+        let result: <Bls12 as ScalarEngine>::Fr = h.hash();
 
         assert_eq!(result, h2.hash());
     }
@@ -740,6 +2340,704 @@ mod tests {
         assert_eq!(digest_correct, digest_optimized_static);
     }
 
+    /// Every mode. The match in `hash_modes_random_preimages` fails to compile when a mode is added, as a reminder to
+    /// list it here.
+    const HASH_MODES: [HashMode; 3] = [Correct, OptimizedDynamic, OptimizedStatic];
+
+    #[test]
+    fn hash_modes_random_preimages() {
+        for (i, mode) in HASH_MODES.iter().enumerate() {
+            let index = match mode {
+                Correct => 0,
+                OptimizedDynamic => 1,
+                OptimizedStatic => 2,
+            };
+            assert_eq!(i, index, "{:?} is missing from HASH_MODES", mode);
+        }
+
+        for strength in [Strength::Standard, Strength::Strengthened].iter() {
+            hash_modes_random_preimages_aux::<U2>(*strength);
+            hash_modes_random_preimages_aux::<typenum::U4>(*strength);
+            hash_modes_random_preimages_aux::<typenum::U8>(*strength);
+            hash_modes_random_preimages_aux::<typenum::U11>(*strength);
+        }
+    }
+
+    /// The optimized modes fold round constants through the MDS matrices; `Correct` mode adds them as the
+    /// specification does. All must agree on every preimage, not just the pinned vectors.
+    fn hash_modes_random_preimages_aux<A>(strength: Strength)
+    where
+        A: Arity<Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new_with_strength(strength);
+
+        for _ in 0..100 {
+            let preimage = (0..A::to_usize())
+                .map(|_| Fr::random(&mut rng))
+                .collect::<Vec<_>>();
+            let p = Poseidon::new_with_preimage(&preimage, &constants);
+            let digests = HASH_MODES
+                .iter()
+                .map(|mode| p.hash_copy_in_mode(*mode))
+                .collect::<Vec<_>>();
+
+            for (mode, digest) in HASH_MODES.iter().zip(digests.iter()).skip(1) {
+                for (other, other_digest) in HASH_MODES.iter().zip(digests.iter()) {
+                    assert_eq!(
+                        other_digest,
+                        digest,
+                        "{:?} and {:?} differ for arity {} and {:?}",
+                        other,
+                        mode,
+                        A::to_usize(),
+                        strength
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hash_correct_differential() {
+        hash_correct_differential_aux::<U2>();
+        hash_correct_differential_aux::<typenum::U4>();
+        hash_correct_differential_aux::<typenum::U8>();
+        hash_correct_differential_aux::<typenum::U11>();
+    }
+
+    /// `hash_correct` against every other mode, on preimages mixing random elements with zeros and the largest
+    /// element, so that structured inputs are covered as well as random ones.
+    fn hash_correct_differential_aux<A>()
+    where
+        A: Arity<Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let mut max = Fr::zero();
+        max.sub_assign(&Fr::one());
+
+        for i in 0..1000 {
+            let preimage = (0..A::to_usize())
+                .map(|_| match rng.next_u32() % 4 {
+                    0 => Fr::zero(),
+                    1 => max,
+                    _ => Fr::random(&mut rng),
+                })
+                .collect::<Vec<_>>();
+            let p = Poseidon::new_with_preimage(&preimage, &constants);
+            let expected = p.clone().hash_correct();
+
+            for mode in HASH_MODES.iter() {
+                assert_eq!(
+                    expected,
+                    p.hash_copy_in_mode(*mode),
+                    "{:?} differs from hash_correct for arity {} on preimage {}",
+                    mode,
+                    A::to_usize(),
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hash_variable_lengths() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let data = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        let full = hash_variable(&constants, &data).unwrap();
+        let short = hash_variable(&constants, &data[..3]).unwrap();
+        let empty = hash_variable(&constants, &[]).unwrap();
+
+        // Padding by hand changes the length, and so the tag: it must not reproduce the short digest.
+        let mut padded = data[..3].to_vec();
+        padded.push(Fr::zero());
+        let padded_hash = hash_variable(&constants, &padded).unwrap();
+        let plain = Poseidon::new_with_preimage(&data, &constants).hash();
+        let plain_padded = Poseidon::new_with_preimage(&padded, &constants).hash();
+
+        let digests = [full, short, empty, padded_hash, plain, plain_padded];
+        for (i, a) in digests.iter().enumerate() {
+            for b in digests.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+
+        let mut too_long = data.clone();
+        too_long.push(Fr::one());
+        assert!(hash_variable(&constants, &too_long).is_err());
+    }
+
+    #[test]
+    fn permute_whole_state() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let state = GenericArray::<Fr, <U4 as Arity<Fr>>::ConstantsSize>::generate(|_| {
+            Fr::random(&mut rng)
+        });
+
+        let mut permuted = state.clone();
+        poseidon_permute(&constants, &mut permuted);
+
+        let mut p = Poseidon::new(&constants);
+        p.elements = state.clone();
+        p.hash_in_mode(Correct);
+
+        // Every lane must match the reference permutation, not only the digest lane.
+        assert_eq!(p.elements, permuted);
+        assert_ne!(state, permuted);
+    }
+
+    #[test]
+    fn permute_in_place_matches_hash() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let preimage = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        // Hashing is permuting the domain tag followed by the preimage, and reading the second element.
+        let mut state = vec![constants.domain_tag];
+        state.extend_from_slice(&preimage);
+        poseidon_permute_in_place(&constants, &mut state).unwrap();
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage, &constants).hash(),
+            state[1]
+        );
+
+        let mut array = GenericArray::<Fr, <U4 as Arity<Fr>>::ConstantsSize>::generate(|i| {
+            if i == 0 {
+                constants.domain_tag
+            } else {
+                preimage[i - 1]
+            }
+        });
+        poseidon_permute(&constants, &mut array);
+        assert_eq!(&state[..], &array[..]);
+
+        let mut short = state[..4].to_vec();
+        assert!(poseidon_permute_in_place(&constants, &mut short).is_err());
+        assert_eq!(&state[..4], &short[..]);
+    }
+
+    #[test]
+    fn hash_with_salt_values() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let preimage = [scalar_from_u64::<Fr>(1), scalar_from_u64::<Fr>(2)];
+
+        let expected = [
+            (
+                7,
+                scalar_from_u64s([
+                    0x40239a9221f77b41,
+                    0x607b245bfd22c845,
+                    0x38ed333a4813c805,
+                    0x3f497c71636aacce,
+                ]),
+            ),
+            (
+                8,
+                scalar_from_u64s([
+                    0x9c105f45efe0fad6,
+                    0xc390ac5f7acb8ae3,
+                    0xfcb3a90222992011,
+                    0x4e5d4e852b116d87,
+                ]),
+            ),
+        ];
+        for (salt, digest) in expected.iter() {
+            assert_eq!(
+                *digest,
+                hash_with_salt(&constants, scalar_from_u64(*salt), &preimage).unwrap()
+            );
+        }
+
+        assert_ne!(
+            Poseidon::new_with_preimage(&preimage, &constants).hash(),
+            hash_with_salt(&constants, Fr::zero(), &preimage).unwrap()
+        );
+        assert!(hash_with_salt(&constants, Fr::one(), &preimage[..1]).is_err());
+    }
+
+    #[test]
+    fn digest_bits() {
+        let digest = scalar_from_u64::<Fr>(0b1011);
+        assert_eq!(
+            vec![true, true, false, true, false],
+            digest_to_bits(&digest, 5).unwrap()
+        );
+        assert!(digest_to_bits(&digest, 0).unwrap().is_empty());
+
+        // p - 1 is even and uses the field's full bit length, and there are no bits past that length.
+        let minus_one = {
+            let mut x = Fr::zero();
+            x.sub_assign(&Fr::one());
+            x
+        };
+        let bits = digest_to_bits(&minus_one, Fr::NUM_BITS as usize).unwrap();
+        assert!(!bits[0]);
+        assert!(bits[Fr::NUM_BITS as usize - 1]);
+        assert!(digest_to_bits(&minus_one, Fr::NUM_BITS as usize + 1).is_err());
+    }
+
+    #[test]
+    fn fixed_size_hashes() {
+        let data = (1..=8).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        assert_eq!(
+            Poseidon::new_with_preimage(&data[..2], &PoseidonConstants::<Bls12, U2>::new()).hash(),
+            hash2(data[0], data[1])
+        );
+        assert_eq!(
+            Poseidon::new_with_preimage(&data[..4], &PoseidonConstants::<Bls12, U4>::new()).hash(),
+            hash4([data[0], data[1], data[2], data[3]])
+        );
+
+        let mut preimage = [Fr::zero(); 8];
+        preimage.copy_from_slice(&data);
+        assert_eq!(
+            Poseidon::new_with_preimage(&data, &PoseidonConstants::<Bls12, U8>::new()).hash(),
+            hash8(preimage)
+        );
+    }
+
+    #[test]
+    fn hash_type_sets_domain_tag() {
+        let data = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+        let merkle = PoseidonConstants::<Bls12, U4>::new();
+        let constant_length =
+            PoseidonConstants::<Bls12, U4>::new_with_hash_type(HashType::ConstantLength(4));
+
+        assert_eq!(HashType::MerkleTree, merkle.hash_type);
+        assert_eq!(
+            merkle,
+            PoseidonConstants::<Bls12, U4>::new_with_hash_type(HashType::MerkleTree)
+        );
+        assert_eq!(merkle.domain_tag, merkle.arity_tag());
+        assert_eq!(merkle.arity_tag(), constant_length.arity_tag());
+        assert_ne!(constant_length.domain_tag, constant_length.arity_tag());
+
+        // A full-length preimage under the constant-length tag is exactly what `hash_variable` computes.
+        let tagged = Poseidon::new_with_preimage(&data, &constant_length).hash();
+        assert_eq!(hash_variable(&merkle, &data).unwrap(), tagged);
+        assert_ne!(Poseidon::new_with_preimage(&data, &merkle).hash(), tagged);
+    }
+
+    #[test]
+    fn hash_multi_lengths() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let data = (1..=12).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        // Short preimages are a single block under the chained tag, so differ from those of `hash_variable`.
+        for len in 0..=4 {
+            let mut p = Poseidon::new(&constants);
+            p.elements[0] = HashType::Chained(len).domain_tag::<Fr, U4>();
+            p.elements[1..=len].copy_from_slice(&data[..len]);
+            assert_eq!(p.hash(), hash_multi(&constants, &data[..len]));
+            assert_ne!(
+                hash_variable(&constants, &data[..len]).unwrap(),
+                hash_multi(&constants, &data[..len])
+            );
+        }
+
+        // Chaining by hand: the first block takes four elements, and later blocks three after the running digest.
+        let tag = HashType::Chained(7).domain_tag::<Fr, U4>();
+        let mut p = Poseidon::new(&constants);
+        p.elements[0] = tag;
+        p.elements[1..].copy_from_slice(&data[..4]);
+        let first = p.hash();
+        let mut p = Poseidon::new(&constants);
+        p.elements[0] = tag;
+        p.elements[1] = first;
+        p.elements[2..].copy_from_slice(&data[4..7]);
+        assert_eq!(p.hash(), hash_multi(&constants, &data[..7]));
+
+        let mut padded = data[..5].to_vec();
+        padded.push(Fr::zero());
+        assert_ne!(
+            hash_multi(&constants, &data[..5]),
+            hash_multi(&constants, &padded)
+        );
+    }
+
+    #[test]
+    fn empty_preimage() {
+        fn check<A: Arity<Fr>>() {
+            let constants = PoseidonConstants::<Bls12, A>::new();
+            let empty = hash_empty(&constants);
+
+            // The tag-only state, permuted.
+            let mut p = Poseidon::new(&constants);
+            p.elements[0] = HashType::ConstantLength(0).domain_tag::<Fr, A>();
+            assert_eq!(p.hash_correct(), empty);
+
+            assert_eq!(empty, hash_variable(&constants, &[]).unwrap());
+            assert_ne!(empty, hash_u64s(&constants, &[]));
+
+            // The chained hash of nothing is the tag-only state under its own tag.
+            let mut p = Poseidon::new(&constants);
+            p.elements[0] = HashType::Chained(0).domain_tag::<Fr, A>();
+            assert_eq!(p.hash_correct(), hash_multi(&constants, &[]));
+            assert_ne!(empty, hash_multi(&constants, &[]));
+            assert_ne!(empty, hash_bytes(&constants, b""));
+            assert_ne!(empty, hash_multi(&constants, &[Fr::zero()]));
+            assert_ne!(empty, Poseidon::new(&constants).hash());
+        }
+
+        check::<U2>();
+        check::<U4>();
+        check::<U8>();
+        check::<U11>();
+
+        assert_eq!(
+            scalar_from_u64s([
+                0x6c10b0ba469ccd52,
+                0x76fc161e570e21b3,
+                0xdd634483bd14566f,
+                0x0c099f6358322ad1,
+            ]),
+            hash_empty(&PoseidonConstants::<Bls12, U2>::new())
+        );
+    }
+
+    #[test]
+    fn hash_u64s_values() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+
+        // Three values take a second permutation at arity 2.
+        let expected: [(&[u64], Fr); 5] = [
+            (
+                &[],
+                scalar_from_u64s([
+                    0x4a1cea21d12a3ad7,
+                    0xd43803c7c3e80a3b,
+                    0xae85e660e304f672,
+                    0x177b42d9b7a8fe92,
+                ]),
+            ),
+            (
+                &[0],
+                scalar_from_u64s([
+                    0xce7e80a27d3c9870,
+                    0x52568afff42eaa37,
+                    0x37e9bbbbc4f2c3f5,
+                    0x3b538cd573f0c2e7,
+                ]),
+            ),
+            (
+                &[1, 2],
+                scalar_from_u64s([
+                    0x7a93159cc84cdf12,
+                    0x9277e1298dc523a6,
+                    0x3098e8944137998d,
+                    0x13a76188e18d3edd,
+                ]),
+            ),
+            (
+                &[1, 2, 3],
+                scalar_from_u64s([
+                    0x401bcb48041636f6,
+                    0x38832feac380f76d,
+                    0x28bb317d206d9a96,
+                    0x503fa1fe7c20ae5a,
+                ]),
+            ),
+            (
+                &[u64::max_value()],
+                scalar_from_u64s([
+                    0xb2720d70080c2bb6,
+                    0xbee32790daec97c8,
+                    0x092c62cb863b155c,
+                    0x398030744a70465f,
+                ]),
+            ),
+        ];
+
+        for (values, digest) in expected.iter() {
+            assert_eq!(*digest, hash_u64s(&constants, values), "{:?}", values);
+        }
+
+        // The values hash as their elements do when chained under their own tag, which no other kind of hashing uses.
+        let elements = [scalar_from_u64::<Fr>(1), scalar_from_u64::<Fr>(2)];
+        let digest = hash_u64s(&constants, &[1, 2]);
+        assert_eq!(
+            hash_chained(
+                &constants,
+                HashType::U64s(2).domain_tag::<Fr, U2>(),
+                &elements
+            ),
+            digest
+        );
+        assert_ne!(hash_variable(&constants, &elements).unwrap(), digest);
+        assert_ne!(hash_multi(&constants, &elements), digest);
+        assert_ne!(
+            Poseidon::new_with_preimage(&elements, &constants).hash(),
+            digest
+        );
+    }
+
+    #[test]
+    fn hash_bytes_values() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let bytes = (0..100).collect::<Vec<u8>>();
+
+        // Empty, one full chunk, one byte more, and enough chunks to chain several permutations at arity 2.
+        let expected = [
+            (
+                0,
+                scalar_from_u64s([
+                    0x45d3c57ed7e07fbe,
+                    0xdefcddb9538ef3ea,
+                    0x5de6d09ca07a80c8,
+                    0x6df330c474809c33,
+                ]),
+            ),
+            (
+                31,
+                scalar_from_u64s([
+                    0x1d62935a429dcaa9,
+                    0x7a99251d82afbfad,
+                    0xaa4d510c1344b916,
+                    0x429e5bd780f8b7a7,
+                ]),
+            ),
+            (
+                32,
+                scalar_from_u64s([
+                    0xb15bc32c74e8b569,
+                    0x25bf6f9304a253b0,
+                    0xbd300e57a13e306a,
+                    0x71a96703322d7f12,
+                ]),
+            ),
+            (
+                100,
+                scalar_from_u64s([
+                    0x7883d02895f163e3,
+                    0xfdc7cc44043c5d7e,
+                    0x05e8a78d572482c0,
+                    0x2b53e46842421c12,
+                ]),
+            ),
+        ];
+
+        for (len, digest) in expected.iter() {
+            assert_eq!(
+                *digest,
+                hash_bytes(&constants, &bytes[..*len]),
+                "length {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn hash_bytes_with_domain_values() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let long_domain = b"a domain string longer than thirty-one bytes";
+        let data = (0..100).collect::<Vec<u8>>();
+
+        let cases: [(&[u8], &[u8], [u64; 4]); 4] = [
+            (
+                b"merkle-leaf-v1",
+                b"abc",
+                [
+                    0x9f46d97c16401b8b,
+                    0xc4c1a9eb8cd09546,
+                    0x8051aacb2e5d9d1b,
+                    0x62b036f1170eaf8c,
+                ],
+            ),
+            (
+                b"",
+                b"abc",
+                [
+                    0xe494ae2640a9ab9f,
+                    0x8b85a39140c0a8cb,
+                    0xad67056b6478cd0a,
+                    0x53f76ef82bb150ab,
+                ],
+            ),
+            (
+                b"merkle-leaf-v1",
+                b"",
+                [
+                    0xd56a35e8e5dbb441,
+                    0x1ce1dd992e880636,
+                    0xea7532616237705e,
+                    0x416b2c30d89f67b6,
+                ],
+            ),
+            (
+                long_domain,
+                &data,
+                [
+                    0xd20b95859e73bb81,
+                    0xa7be5c301011fd59,
+                    0xe4cf624e75c93cfc,
+                    0x07b1be1117a01a77,
+                ],
+            ),
+        ];
+        for (domain, data, expected) in cases.iter() {
+            assert_eq!(
+                scalar_from_u64s(*expected),
+                hash_bytes_with_domain(&constants, domain, data)
+            );
+        }
+
+        // The same data under two domains, or under none, differs.
+        let leaf = hash_bytes_with_domain(&constants, b"merkle-leaf-v1", b"abc");
+        assert_ne!(
+            leaf,
+            hash_bytes_with_domain(&constants, b"merkle-node-v1", b"abc")
+        );
+        assert_ne!(leaf, hash_bytes(&constants, b"abc"));
+        assert_ne!(
+            hash_bytes_with_domain(&constants, b"", b"abc"),
+            hash_bytes(&constants, b"abc")
+        );
+
+        // Moving bytes between the domain and the data changes the digest.
+        assert_ne!(
+            hash_bytes_with_domain(&constants, b"ab", b"c"),
+            hash_bytes_with_domain(&constants, b"a", b"bc")
+        );
+    }
+
+    #[test]
+    fn hash_bytes_packing() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let chained = |elements: &[Fr]| {
+            hash_chained(
+                &constants,
+                HashType::Bytes(elements.len()).domain_tag::<Fr, U4>(),
+                elements,
+            )
+        };
+
+        // Thirty-one bytes fill the first element; the padding byte starts the second.
+        let bytes = [0xffu8; 31];
+        let max = u64::max_value();
+        let elements = [
+            scalar_from_u64s([max, max, max, 0x00ff_ffff_ffff_ffff]),
+            Fr::one(),
+        ];
+        assert_eq!(chained(&elements), hash_bytes(&constants, &bytes));
+        assert_ne!(
+            hash_variable(&constants, &elements).unwrap(),
+            hash_bytes(&constants, &bytes)
+        );
+
+        // Little-endian, padded in the next byte up.
+        assert_eq!(
+            chained(&[scalar_from_u64(0x01_0201)]),
+            hash_bytes(&constants, &[1, 2])
+        );
+        // The collision of a one-element byte string with the integer it packs into is gone.
+        assert_ne!(
+            hash_u64s(&constants, &[0x01_0201]),
+            hash_bytes(&constants, &[1, 2])
+        );
+
+        assert_ne!(hash_bytes(&constants, &[]), hash_bytes(&constants, &[0]));
+        assert_ne!(
+            hash_bytes(&constants, &[1, 2]),
+            hash_bytes(&constants, &[1, 2, 0])
+        );
+    }
+
+    #[test]
+    fn hash_multi_values() {
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        let data = (1..=20).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        // A single element, a partial first block, exactly one block, exactly one chained block, and a partial chained
+        // block. Changing any of these changes the chaining scheme.
+        let expected = [
+            (
+                1,
+                scalar_from_u64s([
+                    0x736a85c9c880ebd7,
+                    0xb65ff14e19902c45,
+                    0x84674e66d70b4faf,
+                    0x2d4191b580c25acd,
+                ]),
+            ),
+            (
+                5,
+                scalar_from_u64s([
+                    0xb94a0ed2e99c3241,
+                    0xb2e823e52e6452d5,
+                    0x195e206c084eda94,
+                    0x07e79c0659a5c142,
+                ]),
+            ),
+            (
+                8,
+                scalar_from_u64s([
+                    0xf4bb0a94a6e7ce6c,
+                    0x421135197631c339,
+                    0xe80f1eb8080cf7a5,
+                    0x5b42c659675397f4,
+                ]),
+            ),
+            (
+                15,
+                scalar_from_u64s([
+                    0x0c64d354b25c049f,
+                    0x562c7809d263e952,
+                    0x793201eb56aa1803,
+                    0x105cca64120973be,
+                ]),
+            ),
+            (
+                20,
+                scalar_from_u64s([
+                    0x16375312f9dbd8c4,
+                    0xf0691a6c26fba279,
+                    0x6ad7a3caf5668ed3,
+                    0x3a7cd6148598eb35,
+                ]),
+            ),
+        ];
+
+        for (len, digest) in expected.iter() {
+            assert_eq!(
+                *digest,
+                hash_multi(&constants, &data[..*len]),
+                "length {}",
+                len
+            );
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn trace_diverges_at_corrupted_round() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let width = constants.width();
+        let data = (1..=4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+
+        let trace = Poseidon::new_with_preimage(&data, &constants).trace();
+        assert_eq!(
+            constants.full_rounds + constants.partial_rounds,
+            trace.len()
+        );
+        assert_eq!(
+            Some(Poseidon::new_with_preimage(&data, &constants).hash()),
+            trace.last().unwrap()[1]
+        );
+
+        // The first round uses the pre-round and its own keys, every other full round before the partial rounds
+        // one set, and each partial round a single key. This is the key of the fourth partial round.
+        let half = constants.half_full_rounds;
+        let mut corrupted = constants.clone();
+        corrupted.compressed_round_constants[(half + 1) * width + 3].add_assign(&Fr::one());
+        let corrupted_trace = Poseidon::new_with_preimage(&data, &corrupted).trace();
+
+        assert_eq!(Some(half + 3), trace_divergence(&trace, &corrupted_trace));
+        assert_eq!(None, trace_divergence(&trace, &trace));
+    }
+
     #[test]
     fn default_is_standard() {
         let default_constants = PoseidonConstants::<Bls12, U8>::new();
@@ -2,7 +2,7 @@
 //! These are tested (in `poseidon::test`) to be equivalent to the 'static optimized' version
 //! used for actual hashing by the neptune library.
 use crate::poseidon::{Arity, Poseidon};
-use crate::{matrix, quintic_s_box};
+use crate::{alpha_s_box, matrix};
 use ff::{Field, ScalarEngine};
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -42,9 +42,10 @@ where
     E: ScalarEngine,
     A: Arity<E::Fr>,
 {
-    // Apply the quintic S-Box to all elements, after adding the round key.
+    // Apply the S-Box to all elements, after adding the round key.
     // Round keys are added in the S-box to match circuits (where the addition is free)
     // and in preparation for the shift to adding round keys after (rather than before) applying the S-box.
+    let alpha = p.constants.alpha;
 
     let pre_round_keys = p
         .constants
@@ -57,7 +58,7 @@ where
         .iter_mut()
         .zip(pre_round_keys)
         .for_each(|(l, pre)| {
-            quintic_s_box::<E>(l, pre, None);
+            alpha_s_box::<E>(l, pre, None, alpha);
         });
 
     p.constants_offset += p.elements.len();
@@ -76,8 +77,8 @@ where
     // Every element of the hash buffer is incremented by the round constants
     add_round_constants(p);
 
-    // Apply the quintic S-Box to the first element
-    quintic_s_box::<E>(&mut p.elements[0], None, None);
+    // Apply the S-Box to the first element
+    alpha_s_box::<E>(&mut p.elements[0], None, None, p.constants.alpha);
 
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
@@ -127,6 +128,8 @@ pub fn full_round_dynamic<'a, E, A>(
     // NOTE: decrease in performance is expected when using this pathway.
     // We seek to preserve correctness while transforming the algorithm to an eventually more performant one.
 
+    let alpha = p.constants.alpha;
+
     // Round keys are added in the S-box to match circuits (where the addition is free).
     // If requested, add round keys synthesized from following round after (rather than before) applying the S-box.
     let pre_round_keys = p
@@ -181,14 +184,14 @@ pub fn full_round_dynamic<'a, E, A>(
             .iter_mut()
             .zip(pre_round_keys.zip(post_round_keys))
             .for_each(|(l, (pre, post))| {
-                quintic_s_box::<E>(l, pre, Some(post));
+                alpha_s_box::<E>(l, pre, Some(post), alpha);
             });
     } else {
         p.elements
             .iter_mut()
             .zip(pre_round_keys)
             .for_each(|(l, pre)| {
-                quintic_s_box::<E>(l, pre, None);
+                alpha_s_box::<E>(l, pre, None, alpha);
             });
     }
     let mut consumed = 0;
@@ -213,8 +216,8 @@ where
     E: ScalarEngine,
     A: Arity<E::Fr>,
 {
-    // Apply the quintic S-Box to the first element
-    quintic_s_box::<E>(&mut p.elements[0], None, None);
+    // Apply the S-Box to the first element
+    alpha_s_box::<E>(&mut p.elements[0], None, None, p.constants.alpha);
 
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
@@ -0,0 +1,90 @@
+use crate::poseidon::{hash_bytes, POSEIDON_CONSTANTS_8};
+use ::digest::consts::U32;
+use ::digest::generic_array::GenericArray;
+use ::digest::{FixedOutput, Reset, Update};
+use ff::{PrimeField, PrimeFieldRepr};
+
+/// Adapter exposing `poseidon::hash_bytes` through the RustCrypto `digest` traits, so that code generic over
+/// `digest::Digest` can hash with Poseidon. Input is buffered until finalization, which hashes it with the default
+/// arity 8 constants and outputs the canonical little-endian encoding of the digest.
+#[derive(Clone, Debug, Default)]
+pub struct PoseidonDigest256 {
+    buffer: Vec<u8>,
+}
+
+impl Update for PoseidonDigest256 {
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.buffer.extend_from_slice(data.as_ref());
+    }
+}
+
+impl FixedOutput for PoseidonDigest256 {
+    type OutputSize = U32;
+
+    fn finalize_into(self, out: &mut GenericArray<u8, Self::OutputSize>) {
+        hash_bytes(&*POSEIDON_CONSTANTS_8, &self.buffer)
+            .into_repr()
+            .write_le(&mut out[..])
+            .expect("a digest encodes in 32 bytes");
+    }
+
+    fn finalize_into_reset(&mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
+        std::mem::take(self).finalize_into(out);
+    }
+}
+
+impl Reset for PoseidonDigest256 {
+    fn reset(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::digest::Digest;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Hash through nothing but the `Digest` bound.
+    fn hash_with<D: Digest>(chunks: &[&[u8]]) -> String {
+        let mut hasher = D::new();
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+
+        hex(&hasher.finalize())
+    }
+
+    #[test]
+    fn test_poseidon_digest() {
+        let bytes = (0..100).collect::<Vec<u8>>();
+
+        assert_eq!(
+            "55c4a8924967aad69fc68396742b853da9d1d97183fdb7ab9179b7726ff0324e",
+            hash_with::<PoseidonDigest256>(&[])
+        );
+        assert_eq!(
+            "2b2efd1da2e05ad01f3d3096c45e72040f41d14a4642e460c89ca9d09dc0e31a",
+            hash_with::<PoseidonDigest256>(&[b"abc"])
+        );
+        let expected = "346318fa4e3e7819fc9da6558d33b5e1fd61610c1966db3cfaa2700bc57a4c6b";
+        assert_eq!(expected, hash_with::<PoseidonDigest256>(&[&bytes]));
+
+        // Splitting the input across updates does not change the digest.
+        assert_eq!(
+            expected,
+            hash_with::<PoseidonDigest256>(&[&bytes[..31], &bytes[31..40], &bytes[40..]])
+        );
+
+        // Resetting discards buffered input.
+        let mut hasher = PoseidonDigest256::new();
+        Digest::update(&mut hasher, b"discarded");
+        Digest::reset(&mut hasher);
+        Digest::update(&mut hasher, &bytes);
+        assert_eq!(expected, hex(&hasher.finalize_reset()));
+        assert_eq!(hash_with::<PoseidonDigest256>(&[]), hex(&hasher.finalize()));
+    }
+}
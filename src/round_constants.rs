@@ -25,6 +25,29 @@ pub use paired::bls12_381::Fr as Scalar;
 /// next one. Note that cryptographically strong randomness is not needed for the
 /// round constants, and other methods can also be used.
 
+/// The round constants for a prime field of `field_bits` bits and the x^5 S-box, at `width` with `r_f` full and `r_p`
+/// partial rounds, as the reference script generates them: `width * (r_f + r_p)` elements, in the order the rounds
+/// consume them. Every `PoseidonConstants` takes its round constants from here. The seed has no bits for the S-box
+/// exponent, so constants for another `Alpha` are these same constants.
+pub fn round_constants<E: ScalarEngine>(
+    field_bits: u16,
+    width: usize,
+    r_f: usize,
+    r_p: usize,
+) -> Vec<E::Fr> {
+    const FIELD: u8 = 1; // Gf(p)
+    const SBOX: u8 = 1; // x^5
+
+    generate_constants::<E>(
+        FIELD,
+        SBOX,
+        field_bits,
+        width as u16,
+        r_f as u16,
+        r_p as u16,
+    )
+}
+
 /// Following https://extgit.iaik.tugraz.at/krypto/hadeshash/blob/master/code/scripts/create_rcs_grain.sage
 pub fn generate_constants<E: ScalarEngine>(
     field: u8,
@@ -246,4 +269,40 @@ mod tests {
             .zip(expected)
             .for_each(|(generated, expected)| assert_eq!(generated, expected));
     }
+
+    fn to_hex(x: &Scalar) -> String {
+        let s = x.to_string();
+        s[3..s.len() - 1].to_string()
+    }
+
+    #[test]
+    fn test_round_constants_width_3() {
+        // From the reference script for BLS12-381, width 3, 8 full and 57 partial rounds.
+        let generated = round_constants::<Bls12>(255, 3, 8, 57);
+        assert_eq!(3 * (8 + 57), generated.len());
+
+        let expected = [
+            "0x46458ff603a3ceca58a5b0f51ebf2af001145e02dadf8c016b05afd6f0ae0af4",
+            "0x2630c94c7a0d2dded30072e816cc9001945f799ac697e8d5f2fde213f2acf4cc",
+            "0x327bde9325365c26ffb001163a721e577084d28ac6c196694c73aa98cbf0241d",
+            "0x72878022e5ee9a13d0fcbe1f825e536ed9f3bd959e9081d801e9d2861b19303e",
+        ];
+        for (generated, expected) in generated.iter().zip(expected.iter()) {
+            assert_eq!(*expected, to_hex(generated));
+        }
+        assert_eq!(
+            "0x36ea6937627f9d75e8e5759a8daea08ffb1ba59b581e5d113518d68a74b65136",
+            to_hex(generated.last().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_poseidon_constants_use_grain() {
+        let constants =
+            crate::poseidon::PoseidonConstants::<Bls12, generic_array::typenum::U2>::new();
+        assert_eq!(
+            round_constants::<Bls12>(255, 3, constants.full_rounds, constants.partial_rounds),
+            constants.round_constants
+        );
+    }
 }
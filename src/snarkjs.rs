@@ -0,0 +1,454 @@
+use crate::circuit::poseidon_hash;
+use crate::poseidon::{Arity, PoseidonConstants};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+const R1CS_MAGIC: &[u8; 4] = b"r1cs";
+const R1CS_VERSION: u32 = 1;
+const R1CS_HEADER: u32 = 1;
+const R1CS_CONSTRAINTS: u32 = 2;
+const R1CS_WIRE_TO_LABEL: u32 = 3;
+
+const WTNS_MAGIC: &[u8; 4] = b"wtns";
+const WTNS_VERSION: u32 = 2;
+const WTNS_HEADER: u32 = 1;
+const WTNS_VALUES: u32 = 2;
+
+/// A linear combination over wires, as a wire index and coefficient for each term.
+pub type WireCombination<Fr> = Vec<(u32, Fr)>;
+
+/// Constraint system which records constraints and, where known, the witness, so that they can be written in the
+/// `.r1cs` and `.wtns` formats read by snarkjs.
+///
+/// Wires are numbered as the formats require: wire 0 is the constant one, followed by the public inputs, then the
+/// auxiliary variables, each in allocation order. All public inputs are written as public inputs rather than
+/// outputs, and there are no private inputs in the snarkjs sense, since a bellperson circuit does not distinguish
+/// them from other auxiliary variables.
+pub struct R1csCollector<E: ScalarEngine> {
+    inputs: Vec<Option<E::Fr>>,
+    aux: Vec<Option<E::Fr>>,
+    constraints: Vec<(
+        LinearCombination<E>,
+        LinearCombination<E>,
+        LinearCombination<E>,
+    )>,
+}
+
+impl<E: ScalarEngine> Default for R1csCollector<E> {
+    fn default() -> Self {
+        R1csCollector {
+            inputs: vec![Some(E::Fr::one())],
+            aux: vec![],
+            constraints: vec![],
+        }
+    }
+}
+
+impl<E: ScalarEngine> R1csCollector<E> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of wires, including the constant one wire.
+    pub fn num_wires(&self) -> usize {
+        self.inputs.len() + self.aux.len()
+    }
+
+    pub fn num_constraints(&self) -> usize {
+        self.constraints.len()
+    }
+
+    /// Write the constraints in the `.r1cs` format.
+    pub fn write_r1cs<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let n8 = field_size::<E::Fr>();
+
+        let mut header = Vec::new();
+        header.write_u32::<LittleEndian>(n8)?;
+        E::Fr::char().write_le(&mut header)?;
+        header.write_u32::<LittleEndian>(self.num_wires() as u32)?;
+        // Outputs, public inputs and private inputs.
+        header.write_u32::<LittleEndian>(0)?;
+        header.write_u32::<LittleEndian>(self.inputs.len() as u32 - 1)?;
+        header.write_u32::<LittleEndian>(0)?;
+        header.write_u64::<LittleEndian>(self.num_wires() as u64)?;
+        header.write_u32::<LittleEndian>(self.constraints.len() as u32)?;
+
+        let mut constraints = Vec::new();
+        for (a, b, c) in self.constraints.iter() {
+            for lc in [a, b, c].iter() {
+                let terms = self.wire_combination(lc);
+                constraints.write_u32::<LittleEndian>(terms.len() as u32)?;
+                for (wire, coeff) in terms {
+                    constraints.write_u32::<LittleEndian>(wire)?;
+                    coeff.into_repr().write_le(&mut constraints)?;
+                }
+            }
+        }
+
+        // Each wire is its own label.
+        let mut labels = Vec::new();
+        for wire in 0..self.num_wires() {
+            labels.write_u64::<LittleEndian>(wire as u64)?;
+        }
+
+        writer.write_all(R1CS_MAGIC)?;
+        writer.write_u32::<LittleEndian>(R1CS_VERSION)?;
+        writer.write_u32::<LittleEndian>(3)?;
+        write_section(&mut writer, R1CS_HEADER, &header)?;
+        write_section(&mut writer, R1CS_CONSTRAINTS, &constraints)?;
+        write_section(&mut writer, R1CS_WIRE_TO_LABEL, &labels)
+    }
+
+    /// Write the witness in the `.wtns` format. Fails if any value was not assigned during synthesis.
+    pub fn write_wtns<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let n8 = field_size::<E::Fr>();
+
+        let mut header = Vec::new();
+        header.write_u32::<LittleEndian>(n8)?;
+        E::Fr::char().write_le(&mut header)?;
+        header.write_u32::<LittleEndian>(self.num_wires() as u32)?;
+
+        let mut values = Vec::new();
+        for value in self.inputs.iter().chain(self.aux.iter()) {
+            let value = value.ok_or_else(|| invalid_data("witness is missing a value"))?;
+            value.into_repr().write_le(&mut values)?;
+        }
+
+        writer.write_all(WTNS_MAGIC)?;
+        writer.write_u32::<LittleEndian>(WTNS_VERSION)?;
+        writer.write_u32::<LittleEndian>(2)?;
+        write_section(&mut writer, WTNS_HEADER, &header)?;
+        write_section(&mut writer, WTNS_VALUES, &values)
+    }
+
+    /// Merge repeated variables and drop zero terms, ordering by wire.
+    fn wire_combination(&self, lc: &LinearCombination<E>) -> WireCombination<E::Fr> {
+        let mut terms = BTreeMap::<u32, E::Fr>::new();
+        for (var, coeff) in lc.iter() {
+            let wire = match var.get_unchecked() {
+                Index::Input(index) => index,
+                Index::Aux(index) => self.inputs.len() + index,
+            };
+            terms
+                .entry(wire as u32)
+                .or_insert_with(E::Fr::zero)
+                .add_assign(coeff);
+        }
+
+        terms
+            .into_iter()
+            .filter(|(_, coeff)| !coeff.is_zero())
+            .collect()
+    }
+}
+
+impl<E: ScalarEngine> ConstraintSystem<E> for R1csCollector<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux.push(f().ok());
+
+        Ok(Variable::new_unchecked(Index::Aux(self.aux.len() - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.inputs.push(f().ok());
+
+        Ok(Variable::new_unchecked(Index::Input(self.inputs.len() - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _annotation: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        self.constraints.push((
+            a(LinearCombination::zero()),
+            b(LinearCombination::zero()),
+            c(LinearCombination::zero()),
+        ));
+    }
+
+    fn push_namespace<NR, N>(&mut self, _name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// Synthesize `poseidon_hash` of `preimage`, with the preimage private and the digest the only public input, and
+/// write the constraints to `r1cs` and the witness to `wtns`.
+pub fn export_poseidon<E, A, R, W>(
+    preimage: &[E::Fr],
+    constants: &PoseidonConstants<E, A>,
+    r1cs: R,
+    wtns: W,
+) -> io::Result<()>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+    R: Write,
+    W: Write,
+{
+    let mut cs = R1csCollector::<E>::new();
+    synthesize_poseidon(&mut cs, preimage, constants)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    cs.write_r1cs(r1cs)?;
+    cs.write_wtns(wtns)
+}
+
+fn synthesize_poseidon<CS, E, A>(
+    cs: &mut CS,
+    preimage: &[E::Fr],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<(), SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let preimage = preimage
+        .iter()
+        .enumerate()
+        .map(|(i, x)| AllocatedNum::alloc(cs.namespace(|| format!("preimage {}", i)), || Ok(*x)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let digest = poseidon_hash(cs.namespace(|| "poseidon hash"), &preimage, constants)?;
+
+    digest.inputize(cs.namespace(|| "digest"))
+}
+
+/// Constraints read from a `.r1cs` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct R1cs<Fr> {
+    pub num_wires: u32,
+    pub num_public_outputs: u32,
+    pub num_public_inputs: u32,
+    pub num_private_inputs: u32,
+    pub constraints: Vec<(
+        WireCombination<Fr>,
+        WireCombination<Fr>,
+        WireCombination<Fr>,
+    )>,
+}
+
+impl<Fr: PrimeField> R1cs<Fr> {
+    /// Whether `witness`, indexed by wire, satisfies every constraint.
+    pub fn is_satisfied(&self, witness: &[Fr]) -> bool {
+        let eval = |lc: &WireCombination<Fr>| {
+            lc.iter().fold(Fr::zero(), |mut acc, (wire, coeff)| {
+                let mut term = witness[*wire as usize];
+                term.mul_assign(coeff);
+                acc.add_assign(&term);
+                acc
+            })
+        };
+
+        witness.len() == self.num_wires as usize
+            && witness.first() == Some(&Fr::one())
+            && self.constraints.iter().all(|(a, b, c)| {
+                let mut ab = eval(a);
+                ab.mul_assign(&eval(b));
+                ab == eval(c)
+            })
+    }
+}
+
+/// Read constraints written in the `.r1cs` format over `Fr`.
+pub fn read_r1cs<Fr: PrimeField, R: Read>(mut reader: R) -> io::Result<R1cs<Fr>> {
+    let sections = read_sections(&mut reader, R1CS_MAGIC, R1CS_VERSION)?;
+
+    let mut header = section(&sections, R1CS_HEADER)?;
+    read_field_header::<Fr, _>(&mut header)?;
+    let num_wires = header.read_u32::<LittleEndian>()?;
+    let num_public_outputs = header.read_u32::<LittleEndian>()?;
+    let num_public_inputs = header.read_u32::<LittleEndian>()?;
+    let num_private_inputs = header.read_u32::<LittleEndian>()?;
+    let _num_labels = header.read_u64::<LittleEndian>()?;
+    let num_constraints = header.read_u32::<LittleEndian>()?;
+
+    let mut body = section(&sections, R1CS_CONSTRAINTS)?;
+    let mut read_combination = || -> io::Result<WireCombination<Fr>> {
+        let num_terms = body.read_u32::<LittleEndian>()?;
+        (0..num_terms)
+            .map(|_| {
+                let wire = body.read_u32::<LittleEndian>()?;
+                if wire >= num_wires {
+                    return Err(invalid_data("constraint refers to a missing wire"));
+                }
+                Ok((wire, read_field(&mut body)?))
+            })
+            .collect()
+    };
+    let constraints = (0..num_constraints)
+        .map(|_| -> io::Result<_> {
+            Ok((
+                read_combination()?,
+                read_combination()?,
+                read_combination()?,
+            ))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(R1cs {
+        num_wires,
+        num_public_outputs,
+        num_public_inputs,
+        num_private_inputs,
+        constraints,
+    })
+}
+
+/// Read a witness written in the `.wtns` format over `Fr`, indexed by wire.
+pub fn read_wtns<Fr: PrimeField, R: Read>(mut reader: R) -> io::Result<Vec<Fr>> {
+    let sections = read_sections(&mut reader, WTNS_MAGIC, WTNS_VERSION)?;
+
+    let mut header = section(&sections, WTNS_HEADER)?;
+    read_field_header::<Fr, _>(&mut header)?;
+    let num_values = header.read_u32::<LittleEndian>()?;
+
+    let mut values = section(&sections, WTNS_VALUES)?;
+    (0..num_values).map(|_| read_field(&mut values)).collect()
+}
+
+fn field_size<Fr: PrimeField>() -> u32 {
+    (Fr::char().as_ref().len() * 8) as u32
+}
+
+fn write_section<W: Write>(writer: &mut W, section_type: u32, contents: &[u8]) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(section_type)?;
+    writer.write_u64::<LittleEndian>(contents.len() as u64)?;
+    writer.write_all(contents)
+}
+
+fn read_sections<R: Read>(
+    reader: &mut R,
+    magic: &[u8; 4],
+    version: u32,
+) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let mut found = [0u8; 4];
+    reader.read_exact(&mut found)?;
+    if &found != magic || reader.read_u32::<LittleEndian>()? != version {
+        return Err(invalid_data("unexpected file type or version"));
+    }
+
+    let num_sections = reader.read_u32::<LittleEndian>()?;
+    (0..num_sections)
+        .map(|_| {
+            let section_type = reader.read_u32::<LittleEndian>()?;
+            let len = reader.read_u64::<LittleEndian>()?;
+            let mut contents = Vec::new();
+            reader.by_ref().take(len).read_to_end(&mut contents)?;
+            if contents.len() as u64 != len {
+                return Err(invalid_data("truncated section"));
+            }
+            Ok((section_type, contents))
+        })
+        .collect()
+}
+
+fn section(sections: &[(u32, Vec<u8>)], section_type: u32) -> io::Result<&[u8]> {
+    sections
+        .iter()
+        .find(|(t, _)| *t == section_type)
+        .map(|(_, contents)| contents.as_slice())
+        .ok_or_else(|| invalid_data("missing section"))
+}
+
+/// Read the field size and modulus, checking that they are those of `Fr`.
+fn read_field_header<Fr: PrimeField, R: Read>(reader: &mut R) -> io::Result<()> {
+    let n8 = reader.read_u32::<LittleEndian>()?;
+    if n8 != field_size::<Fr>() {
+        return Err(invalid_data("unexpected field size"));
+    }
+
+    let mut modulus = Fr::Repr::default();
+    modulus.read_le(reader)?;
+    if modulus != Fr::char() {
+        return Err(invalid_data("unexpected field modulus"));
+    }
+
+    Ok(())
+}
+
+fn read_field<Fr: PrimeField, R: Read>(reader: &mut R) -> io::Result<Fr> {
+    let mut repr = Fr::Repr::default();
+    repr.read_le(reader)?;
+
+    Fr::from_repr(repr).map_err(|_| invalid_data("field element is not reduced"))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Poseidon;
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let preimage = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let mut r1cs_bytes = Vec::new();
+        let mut wtns_bytes = Vec::new();
+        export_poseidon(&preimage, &constants, &mut r1cs_bytes, &mut wtns_bytes).unwrap();
+
+        let r1cs = read_r1cs::<Fr, _>(r1cs_bytes.as_slice()).unwrap();
+        let witness = read_wtns::<Fr, _>(wtns_bytes.as_slice()).unwrap();
+
+        assert_eq!(
+            crate::circuit::poseidon_constraints(&constants),
+            // The digest is inputized with one more constraint.
+            r1cs.constraints.len() - 1
+        );
+        assert_eq!(1, r1cs.num_public_inputs);
+        assert_eq!(r1cs.num_wires as usize, witness.len());
+        assert!(r1cs.is_satisfied(&witness));
+
+        // The digest is the only public input, so it immediately follows the one wire.
+        let digest = Poseidon::new_with_preimage(&preimage, &constants).hash();
+        assert_eq!(digest, witness[1]);
+
+        let mut corrupted = witness.clone();
+        corrupted[2].add_assign(&Fr::one());
+        assert!(!r1cs.is_satisfied(&corrupted));
+
+        // Files for another field are rejected rather than misread.
+        let mut wrong_modulus = wtns_bytes.clone();
+        wrong_modulus[4 + 4 + 4 + 4 + 8 + 4] ^= 1;
+        assert!(read_wtns::<Fr, _>(wrong_modulus.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use ff::{Field, PrimeField, ScalarEngine};
+
+/// Sponge over the Poseidon permutation, for hashing data whose length is not the arity.
+///
+/// The first element of the state (the capacity) holds the `HashType::Sponge` domain tag, and the remaining `arity`
+/// elements are the rate. Elements are absorbed into the rate, permuting whenever it is full. The first squeeze pads
+/// the absorbed data with a single one and permutes; squeezing then reads the rate, permuting whenever it is
+/// exhausted. Absorbing after squeezing permutes first. `circuit::SpongeCircuit` computes the same outputs in a
+/// circuit.
+pub struct Sponge<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    poseidon: Poseidon<'a, E, A>,
+    pos: usize,
+    squeezing: bool,
+}
+
+impl<'a, E, A> Sponge<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        let mut poseidon = Poseidon::new(constants);
+        poseidon.elements[0] = HashType::Sponge.domain_tag::<E::Fr, A>();
+
+        Sponge {
+            poseidon,
+            pos: 0,
+            squeezing: false,
+        }
+    }
+
+    /// Absorb `element` into the rate, permuting first if the rate is full or the sponge has been squeezed.
+    pub fn absorb(&mut self, element: E::Fr) {
+        if self.squeezing {
+            self.permute();
+            self.squeezing = false;
+        }
+        if self.pos == A::to_usize() {
+            self.permute();
+        }
+
+        self.poseidon.elements[1 + self.pos].add_assign(&element);
+        self.pos += 1;
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_hashes(0, 1);
+    }
+
+    /// Squeeze the next output element, padding and permuting on the first squeeze and whenever the rate is
+    /// exhausted.
+    pub fn squeeze(&mut self) -> E::Fr {
+        if !self.squeezing {
+            if self.pos == A::to_usize() {
+                self.permute();
+            }
+
+            // Padding with a one ensures that absorbing [a, b] and [a, b, 0] squeeze different outputs.
+            self.poseidon.elements[1 + self.pos].add_assign(&E::Fr::one());
+            self.permute();
+            self.squeezing = true;
+        }
+        if self.pos == A::to_usize() {
+            self.permute();
+        }
+
+        let output = self.poseidon.elements[1 + self.pos];
+        self.pos += 1;
+
+        output
+    }
+
+    fn permute(&mut self) {
+        self.poseidon.permute();
+        self.pos = 0;
+    }
+}
+
+/// Hash `bits`. A single one bit is appended, then the bits are packed little-endian into elements of at most
+/// `Fr::CAPACITY` bits, which are absorbed into a `Sponge` before squeezing the digest. The padding bit keeps bit
+/// strings which differ only in trailing zeros apart. `circuit::poseidon_hash_bits` computes the same digest in a
+/// circuit.
+pub fn hash_bits<E, A>(constants: &PoseidonConstants<E, A>, bits: &[bool]) -> E::Fr
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut padded = bits.to_vec();
+    padded.push(true);
+
+    let mut sponge = Sponge::new(constants);
+    for chunk in padded.chunks(E::Fr::CAPACITY as usize) {
+        // Most significant bit first, doubling as we go.
+        let packed = chunk.iter().rev().fold(E::Fr::zero(), |mut acc, bit| {
+            acc.double();
+            if *bit {
+                acc.add_assign(&E::Fr::one());
+            }
+            acc
+        });
+        sponge.absorb(packed);
+    }
+
+    sponge.squeeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scalar_from_u64, scalar_from_u64s};
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+
+    #[test]
+    fn test_sponge_values() {
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let mut sponge = Sponge::new(&constants);
+
+        for i in 1..4 {
+            sponge.absorb(scalar_from_u64(i));
+        }
+
+        // Three squeezes at arity 2 cross a permutation boundary.
+        let expected = [
+            scalar_from_u64s([
+                0xf34818f31ef04f6a,
+                0x18a7c09824990c99,
+                0x7085d789c64f768d,
+                0x3ababc2c8888e076,
+            ]),
+            scalar_from_u64s([
+                0xea8fa648c1a5c7b6,
+                0x9e671193902b958e,
+                0x2080abe8d5f0b3ed,
+                0x5686d38a6f41dcc3,
+            ]),
+            scalar_from_u64s([
+                0xb2f2a40398c46c86,
+                0x6d15d4fdc39a8e86,
+                0x70e261c83d3503cf,
+                0x556f1250f156ed72,
+            ]),
+        ];
+
+        for expected in expected.iter() {
+            assert_eq!(*expected, sponge.squeeze());
+        }
+    }
+
+    #[test]
+    fn test_sponge_padding() {
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let squeeze = |preimage: &[Fr]| {
+            let mut sponge = Sponge::new(&constants);
+            for x in preimage {
+                sponge.absorb(*x);
+            }
+            sponge.squeeze()
+        };
+
+        let a = scalar_from_u64::<Fr>(1);
+        let b = scalar_from_u64::<Fr>(2);
+
+        assert_ne!(squeeze(&[a, b]), squeeze(&[a, b, Fr::zero()]));
+        assert_ne!(squeeze(&[]), squeeze(&[Fr::zero()]));
+    }
+
+    #[test]
+    fn test_sponge_is_not_tree_hash() {
+        let constants = PoseidonConstants::<Bls12, typenum::U2>::new();
+        let preimage = [scalar_from_u64::<Fr>(1), scalar_from_u64::<Fr>(2)];
+
+        let mut sponge = Sponge::new(&constants);
+        for x in preimage.iter() {
+            sponge.absorb(*x);
+        }
+
+        let mut poseidon = Poseidon::new_with_preimage(&preimage, &constants);
+
+        assert_ne!(poseidon.hash(), sponge.squeeze());
+    }
+}
@@ -0,0 +1,841 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use ff::{Field, PrimeField, ScalarEngine};
+
+use crate::scalar_from_u64;
+
+enum NamedObject {
+    Constraint(usize),
+    Var(Variable),
+    Namespace(Path),
+}
+
+/// Cost of the part of a circuit under one namespace prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub constraints: usize,
+    /// Auxiliary variables allocated.
+    pub aux: usize,
+    /// Terms in the constraints' A, B and C linear combinations, together.
+    pub terms: usize,
+}
+
+/// A constraint recorded by `TestConstraintSystem`: its full namespace path and linear combinations.
+pub struct ConstraintRef<'a, E: ScalarEngine> {
+    pub name: String,
+    pub a: &'a LinearCombination<E>,
+    pub b: &'a LinearCombination<E>,
+    pub c: &'a LinearCombination<E>,
+}
+
+/// Constraint system for testing purposes. Every constraint and allocation is recorded under its full
+/// namespace path, so tests can check satisfaction and count exactly what a gadget costs.
+///
+/// `to_json` exports the system for external tooling as a single object, in this stable format:
+///
+/// ```text
+/// {
+///   "inputs": [{"name": "ONE", "value": "0x…"}, …],
+///   "aux": [{"name": "a/b", "value": "0x…"}, …],
+///   "constraints": [
+///     {
+///       "name": "a/c",
+///       "a": [{"variable": "a/b", "coefficient": "0x…"}, …],
+///       "b": […],
+///       "c": […]
+///     },
+///     …
+///   ]
+/// }
+/// ```
+///
+/// Inputs, auxiliary variables and constraints are listed in allocation order, each under its full namespace path.
+/// Terms name the variable they refer to, and appear as the gadget built its linear combination, so a variable may
+/// appear more than once. Values and coefficients are 0x-prefixed big-endian hex.
+///
+/// Paths are stored as a tree of interned namespace components, each object recording only its node, and are only
+/// walked and joined into strings when displayed or looked up, so recording stays cheap for circuits with hundreds of
+/// thousands of constraints.
+pub struct TestConstraintSystem<E: ScalarEngine> {
+    /// Every object by the path of its namespace and its own interned component, so that names stay unique.
+    named_objects: HashMap<(Option<Path>, u32), NamedObject>,
+    nodes: Vec<Node>,
+    /// Distinct path components, indexed by their interned ID.
+    segments: Vec<String>,
+    segment_ids: HashMap<String, u32>,
+    current_namespace: Option<Path>,
+    constraints: Vec<(
+        LinearCombination<E>,
+        LinearCombination<E>,
+        LinearCombination<E>,
+        Path,
+    )>,
+    inputs: Vec<(E::Fr, Path)>,
+    aux: Vec<(E::Fr, Path)>,
+}
+
+/// A namespace path as the index of its last component in `TestConstraintSystem::nodes`.
+type Path = u32;
+
+/// The last component of a path: its interned ID, under the path of its namespace, if it has one.
+struct Node {
+    parent: Option<Path>,
+    segment: u32,
+}
+
+fn eval_lc<E: ScalarEngine>(
+    terms: &LinearCombination<E>,
+    inputs: &[E::Fr],
+    aux: &[E::Fr],
+) -> E::Fr {
+    let mut acc = E::Fr::zero();
+
+    for (var, coeff) in terms.iter() {
+        let mut tmp = match var.get_unchecked() {
+            Index::Input(index) => inputs[index],
+            Index::Aux(index) => aux[index],
+        };
+
+        tmp.mul_assign(coeff);
+        acc.add_assign(&tmp);
+    }
+
+    acc
+}
+
+/// `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+
+    escaped
+}
+
+fn prefix_metrics<'a>(
+    metrics: &'a mut Vec<(Option<Path>, Metrics)>,
+    positions: &mut HashMap<Option<Path>, usize>,
+    prefix: Option<Path>,
+) -> &'a mut Metrics {
+    let position = *positions.entry(prefix).or_insert_with(|| {
+        metrics.push((prefix, Metrics::default()));
+        metrics.len() - 1
+    });
+
+    &mut metrics[position].1
+}
+
+/// SplitMix64, to draw perturbations reproducibly without a dependency on `rand`.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A field element from four draws, taken as the digits of a number in base 2^64 and reduced.
+    fn next_field<F: PrimeField>(&mut self) -> F {
+        let mut base = scalar_from_u64::<F>(1 << 32);
+        base.square();
+
+        let mut element = F::zero();
+        for _ in 0..4 {
+            element.mul_assign(&base);
+            element.add_assign(&scalar_from_u64(self.next_u64()));
+        }
+        element
+    }
+}
+
+impl<E: ScalarEngine> Default for TestConstraintSystem<E> {
+    fn default() -> Self {
+        let mut cs = TestConstraintSystem {
+            named_objects: HashMap::new(),
+            nodes: vec![],
+            segments: vec![],
+            segment_ids: HashMap::new(),
+            current_namespace: None,
+            constraints: vec![],
+            inputs: vec![],
+            aux: vec![],
+        };
+
+        let path = cs.compute_path("ONE".into());
+        cs.inputs.push((E::Fr::one(), path));
+        cs.set_named_obj(path, NamedObject::Var(Self::one()));
+
+        cs
+    }
+}
+
+impl<E: ScalarEngine> TestConstraintSystem<E> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_satisfied(&self) -> bool {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(value, _)| *value)
+            .collect::<Vec<_>>();
+        let aux = self.aux.iter().map(|(value, _)| *value).collect::<Vec<_>>();
+
+        self.is_satisfied_by(&inputs, &aux)
+    }
+
+    /// Every constraint, in order, under its full namespace path, with its A, B and C linear combinations written in
+    /// terms of the variables' allocation paths. Coefficients are printed in hex.
+    pub fn pretty_print(&self) -> String {
+        let mut s = String::new();
+
+        for (a, b, c, path) in self.constraints.iter() {
+            writeln!(s, "{}", self.path_string(*path)).unwrap();
+            for (label, lc) in [("A", a), ("B", b), ("C", c)].iter() {
+                write!(s, "  {}:", label).unwrap();
+                for (i, (var, coeff)) in lc.iter().enumerate() {
+                    let name = self.variable_name(var);
+                    let separator = if i == 0 { "" } else { " +" };
+                    write!(s, "{} {} * {}", separator, coeff.into_repr(), name).unwrap();
+                }
+                writeln!(s).unwrap();
+            }
+        }
+
+        s
+    }
+
+    /// Export the constraint system as JSON, in the format documented on the type.
+    pub fn to_json(&self) -> String {
+        let variables = |variables: &[(E::Fr, Path)]| {
+            variables
+                .iter()
+                .map(|(value, path)| {
+                    format!(
+                        "{{\"name\": {}, \"value\": \"{}\"}}",
+                        json_string(&self.path_string(*path)),
+                        value.into_repr()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let terms = |lc: &LinearCombination<E>| {
+            lc.iter()
+                .map(|(var, coeff)| {
+                    let name = self.variable_name(var);
+                    format!(
+                        "{{\"variable\": {}, \"coefficient\": \"{}\"}}",
+                        json_string(&name),
+                        coeff.into_repr()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut s = String::new();
+        writeln!(s, "{{").unwrap();
+        writeln!(s, "  \"inputs\": [{}],", variables(&self.inputs)).unwrap();
+        writeln!(s, "  \"aux\": [{}],", variables(&self.aux)).unwrap();
+        writeln!(s, "  \"constraints\": [").unwrap();
+        for (i, (a, b, c, path)) in self.constraints.iter().enumerate() {
+            let separator = if i + 1 == self.constraints.len() {
+                ""
+            } else {
+                ","
+            };
+            writeln!(
+                s,
+                "    {{\"name\": {}, \"a\": [{}], \"b\": [{}], \"c\": [{}]}}{}",
+                json_string(&self.path_string(*path)),
+                terms(a),
+                terms(b),
+                terms(c),
+                separator
+            )
+            .unwrap();
+        }
+        writeln!(s, "  ]").unwrap();
+        writeln!(s, "}}").unwrap();
+
+        s
+    }
+
+    /// The full namespace path of the first unsatisfied constraint, with the values its A, B and C linear combinations
+    /// evaluate to, or `None` if every constraint is satisfied.
+    pub fn which_is_unsatisfied(&self) -> Option<String> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(value, _)| *value)
+            .collect::<Vec<_>>();
+        let aux = self.aux.iter().map(|(value, _)| *value).collect::<Vec<_>>();
+
+        self.constraints.iter().find_map(|(a, b, c, path)| {
+            let a = eval_lc::<E>(a, &inputs, &aux);
+            let b = eval_lc::<E>(b, &inputs, &aux);
+            let c = eval_lc::<E>(c, &inputs, &aux);
+
+            let mut ab = a;
+            ab.mul_assign(&b);
+            if ab == c {
+                None
+            } else {
+                Some(format!(
+                    "{}: A = {}, B = {}, C = {}",
+                    self.path_string(*path),
+                    a,
+                    b,
+                    c
+                ))
+            }
+        })
+    }
+
+    /// Whether the recorded constraints are satisfied by another assignment, such as one computed by
+    /// `witness_cs::WitnessCS`. The assignment must have exactly as many inputs and auxiliary values as were allocated.
+    pub fn is_satisfied_by(&self, inputs: &[E::Fr], aux: &[E::Fr]) -> bool {
+        inputs.len() == self.inputs.len()
+            && aux.len() == self.aux.len()
+            && self.constraints.iter().all(|(a, b, c, _)| {
+                let mut a = eval_lc::<E>(a, inputs, aux);
+                let b = eval_lc::<E>(b, inputs, aux);
+                let c = eval_lc::<E>(c, inputs, aux);
+
+                a.mul_assign(&b);
+                a == c
+            })
+    }
+
+    /// Assert that every auxiliary variable is constrained: perturbing any one of them by a random nonzero delta, with
+    /// the rest of the assignment unchanged, must leave the constraints unsatisfied. Panics if the system is not
+    /// satisfied to begin with, or lists the paths of every variable whose perturbation went undetected. Such variables
+    /// are witnesses a prover could choose freely.
+    ///
+    /// The deltas are drawn from a fixed seed, so failures reproduce.
+    pub fn assert_all_aux_constrained(&self) {
+        if let Some(unsatisfied) = self.which_is_unsatisfied() {
+            panic!("constraints are not satisfied: {}", unsatisfied);
+        }
+
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(value, _)| *value)
+            .collect::<Vec<_>>();
+        let mut aux = self.aux.iter().map(|(value, _)| *value).collect::<Vec<_>>();
+        let mut rng = SplitMix64(0);
+
+        let mut unconstrained = Vec::new();
+        for i in 0..aux.len() {
+            let mut delta = rng.next_field::<E::Fr>();
+            while delta.is_zero() {
+                delta = rng.next_field::<E::Fr>();
+            }
+
+            let value = aux[i];
+            aux[i].add_assign(&delta);
+            if self.is_satisfied_by(&inputs, &aux) {
+                unconstrained.push(self.path_string(self.aux[i].1));
+            }
+            aux[i] = value;
+        }
+
+        if !unconstrained.is_empty() {
+            panic!(
+                "unconstrained auxiliary variables: {}",
+                unconstrained.join(", ")
+            );
+        }
+    }
+
+    pub fn num_constraints(&self) -> usize {
+        self.constraints.len()
+    }
+
+    /// Number of public inputs, including the constant one input.
+    pub fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Whether the constraints are satisfied when `public_inputs` are substituted for the inputs after the constant one
+    /// input, as a verifier would check them. The auxiliary assignment is the one recorded during synthesis. False if
+    /// the number of public inputs is wrong.
+    pub fn verify(&self, public_inputs: &[E::Fr]) -> bool {
+        if public_inputs.len() + 1 != self.inputs.len() {
+            return false;
+        }
+
+        let mut inputs = Vec::with_capacity(self.inputs.len());
+        inputs.push(self.inputs[0].0);
+        inputs.extend_from_slice(public_inputs);
+        let aux = self.aux.iter().map(|(value, _)| *value).collect::<Vec<_>>();
+
+        self.is_satisfied_by(&inputs, &aux)
+    }
+
+    /// Number of auxiliary (private witness) variables.
+    pub fn num_aux(&self) -> usize {
+        self.aux.len()
+    }
+
+    /// Hex BLAKE2s digest of the constraint structure: the numbers of inputs, auxiliary variables and constraints, then,
+    /// for every constraint in order, the terms of its A, B and C linear combinations as variable indices and
+    /// coefficients. Terms are merged by variable and zero terms dropped, so the digest reflects the constraints
+    /// themselves rather than how their linear combinations were built. Names and assignments are not included.
+    ///
+    /// Pin this in tests to catch layout changes which leave the constraint count alone.
+    pub fn hash(&self) -> String {
+        let mut state = blake2s_simd::State::new();
+        for n in [self.inputs.len(), self.aux.len(), self.constraints.len()].iter() {
+            state.update(&(*n as u64).to_le_bytes());
+        }
+
+        for (a, b, c, _) in self.constraints.iter() {
+            for lc in [a, b, c].iter() {
+                let mut terms = BTreeMap::<(u8, usize), E::Fr>::new();
+                for (var, coeff) in lc.iter() {
+                    let key = match var.get_unchecked() {
+                        Index::Input(index) => (0, index),
+                        Index::Aux(index) => (1, index),
+                    };
+                    terms
+                        .entry(key)
+                        .or_insert_with(E::Fr::zero)
+                        .add_assign(coeff);
+                }
+                let terms = terms
+                    .into_iter()
+                    .filter(|(_, coeff)| !coeff.is_zero())
+                    .collect::<Vec<_>>();
+
+                state.update(&(terms.len() as u64).to_le_bytes());
+                for ((kind, index), coeff) in terms {
+                    state.update(&[kind]);
+                    state.update(&(index as u64).to_le_bytes());
+                    for limb in coeff.into_repr().as_ref() {
+                        state.update(&limb.to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        state.finalize().to_hex().to_string()
+    }
+
+    /// The constraints whose path is `prefix` or lies under the namespace `prefix`, in order. Prefixes match whole
+    /// path components, so "a/1" does not match "a/10".
+    pub fn constraints_under(&self, prefix: &str) -> Vec<ConstraintRef<E>> {
+        let prefix = match self.lookup_path(prefix) {
+            Some(prefix) => prefix,
+            None => return vec![],
+        };
+
+        self.constraints
+            .iter()
+            .filter(|(_, _, _, path)| self.is_under(*path, prefix))
+            .map(|(a, b, c, path)| ConstraintRef {
+                name: self.path_string(*path),
+                a,
+                b,
+                c,
+            })
+            .collect()
+    }
+
+    /// Metrics for each distinct prefix of `depth` namespace components. Prefixes are listed in the order they are
+    /// first seen among the allocations, then among the constraints, which follows synthesis order for gadgets that
+    /// allocate in each namespace they constrain. Paths with fewer components count under the whole path.
+    pub fn metrics_by_prefix(&self, depth: usize) -> Vec<(String, Metrics)> {
+        let mut metrics = Vec::new();
+        let mut positions = HashMap::new();
+
+        for (_, path) in self.aux.iter() {
+            prefix_metrics(&mut metrics, &mut positions, self.prefix(*path, depth)).aux += 1;
+        }
+        for (a, b, c, path) in self.constraints.iter() {
+            let entry = prefix_metrics(&mut metrics, &mut positions, self.prefix(*path, depth));
+            entry.constraints += 1;
+            entry.terms += a.iter().count() + b.iter().count() + c.iter().count();
+        }
+
+        metrics
+            .into_iter()
+            .map(|(prefix, metrics)| {
+                let name = prefix.map(|prefix| self.path_string(prefix));
+                (name.unwrap_or_default(), metrics)
+            })
+            .collect()
+    }
+
+    /// The value assigned to the variable allocated at `path`. Panics if there is no variable there.
+    pub fn get(&self, path: &str) -> E::Fr {
+        match self.variable_at(path).get_unchecked() {
+            Index::Input(index) => self.inputs[index].0,
+            Index::Aux(index) => self.aux[index].0,
+        }
+    }
+
+    /// Reassign the variable allocated at `path`, so that tests can tamper with a witness after synthesis and check
+    /// that the constraints catch it. Panics if there is no variable there.
+    pub fn set(&mut self, path: &str, value: E::Fr) {
+        match self.variable_at(path).get_unchecked() {
+            Index::Input(index) => self.inputs[index].0 = value,
+            Index::Aux(index) => self.aux[index].0 = value,
+        }
+    }
+
+    fn variable_at(&self, path: &str) -> Variable {
+        match self.lookup(path) {
+            Some(NamedObject::Var(var)) => *var,
+            _ => panic!("no variable exists at path: {}", path),
+        }
+    }
+
+    fn variable_name(&self, var: &Variable) -> String {
+        match var.get_unchecked() {
+            Index::Input(index) => self.path_string(self.inputs[index].1),
+            Index::Aux(index) => self.path_string(self.aux[index].1),
+        }
+    }
+
+    /// The components of `path`, from its outermost namespace down.
+    fn components(&self, path: Path) -> Vec<&Node> {
+        let mut components = vec![&self.nodes[path as usize]];
+        while let Some(parent) = components[components.len() - 1].parent {
+            components.push(&self.nodes[parent as usize]);
+        }
+        components.reverse();
+
+        components
+    }
+
+    /// The display form of `path`, its components joined by '/'.
+    fn path_string(&self, path: Path) -> String {
+        self.components(path)
+            .iter()
+            .map(|node| self.segments[node.segment as usize].as_str())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// The first `depth` components of `path`, or the whole path if it has fewer.
+    fn prefix(&self, path: Path, depth: usize) -> Option<Path> {
+        let components = self.components(path).len();
+        let mut prefix = Some(path);
+        for _ in depth.min(components)..components {
+            prefix = prefix.and_then(|prefix| self.nodes[prefix as usize].parent);
+        }
+
+        prefix
+    }
+
+    /// Whether `path` is `prefix` or lies under it.
+    fn is_under(&self, path: Path, prefix: Path) -> bool {
+        let mut node = Some(path);
+        while let Some(current) = node {
+            if current == prefix {
+                return true;
+            }
+            node = self.nodes[current as usize].parent;
+        }
+
+        false
+    }
+
+    /// The object at a '/'-separated path, or `None` if nothing was created there.
+    fn lookup(&self, path: &str) -> Option<&NamedObject> {
+        let mut object = None;
+        for segment in path.split('/') {
+            let parent = object.map(|object| self.object_path(object));
+            let id = self.segment_ids.get(segment)?;
+            object = Some(self.named_objects.get(&(parent, *id))?);
+        }
+
+        object
+    }
+
+    /// The path of the object at `path`, or `None` if nothing was created there.
+    fn lookup_path(&self, path: &str) -> Option<Path> {
+        self.lookup(path).map(|object| self.object_path(object))
+    }
+
+    fn object_path(&self, object: &NamedObject) -> Path {
+        match object {
+            NamedObject::Constraint(index) => self.constraints[*index].3,
+            NamedObject::Var(var) => match var.get_unchecked() {
+                Index::Input(index) => self.inputs[index].1,
+                Index::Aux(index) => self.aux[index].1,
+            },
+            NamedObject::Namespace(path) => *path,
+        }
+    }
+
+    fn intern(&mut self, segment: String) -> u32 {
+        if segment.chars().any(|a| a == '/') {
+            panic!("'/' is not allowed in names");
+        }
+
+        if let Some(id) = self.segment_ids.get(&segment) {
+            return *id;
+        }
+        let id = self.segments.len() as u32;
+        self.segments.push(segment.clone());
+        self.segment_ids.insert(segment, id);
+
+        id
+    }
+
+    fn compute_path(&mut self, this: String) -> Path {
+        let segment = self.intern(this);
+        self.nodes.push(Node {
+            parent: self.current_namespace,
+            segment,
+        });
+
+        (self.nodes.len() - 1) as Path
+    }
+
+    fn set_named_obj(&mut self, path: Path, to: NamedObject) {
+        let node = &self.nodes[path as usize];
+        let key = (node.parent, node.segment);
+        if self.named_objects.contains_key(&key) {
+            panic!(
+                "tried to create object at existing path: {}",
+                self.path_string(path)
+            );
+        }
+
+        self.named_objects.insert(key, to);
+    }
+}
+
+impl<E: ScalarEngine> ConstraintSystem<E> for TestConstraintSystem<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        let index = self.aux.len();
+        let path = self.compute_path(annotation().into());
+        self.aux.push((f()?, path));
+        let var = Variable::new_unchecked(Index::Aux(index));
+        self.set_named_obj(path, NamedObject::Var(var));
+
+        Ok(var)
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        let index = self.inputs.len();
+        let path = self.compute_path(annotation().into());
+        self.inputs.push((f()?, path));
+        let var = Variable::new_unchecked(Index::Input(index));
+        self.set_named_obj(path, NamedObject::Var(var));
+
+        Ok(var)
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, annotation: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        let path = self.compute_path(annotation().into());
+        let index = self.constraints.len();
+        self.set_named_obj(path, NamedObject::Constraint(index));
+
+        let a = a(LinearCombination::zero());
+        let b = b(LinearCombination::zero());
+        let c = c(LinearCombination::zero());
+
+        self.constraints.push((a, b, c, path));
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        let path = self.compute_path(name_fn().into());
+        self.set_named_obj(path, NamedObject::Namespace(path));
+        self.current_namespace = Some(path);
+    }
+
+    fn pop_namespace(&mut self) {
+        let path = self
+            .current_namespace
+            .expect("pop_namespace without a namespace");
+        self.current_namespace = self.nodes[path as usize].parent;
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bellperson::gadgets::num::AllocatedNum;
+    use paired::bls12_381::{Bls12, Fr};
+
+    #[test]
+    fn test_cs() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        assert!(cs.is_satisfied());
+        assert_eq!(None, cs.which_is_unsatisfied());
+        assert_eq!(0, cs.num_constraints());
+        assert_eq!(1, cs.num_inputs());
+        assert_eq!(0, cs.num_aux());
+
+        let a =
+            AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(crate::scalar_from_u64(3))).unwrap();
+        let b = a.square(cs.namespace(|| "square")).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(1, cs.num_constraints());
+        assert_eq!(2, cs.num_aux());
+
+        cs.enforce(
+            || "wrong",
+            |lc| lc + a.get_variable(),
+            |lc| lc + TestConstraintSystem::<Bls12>::one(),
+            |lc| lc + b.get_variable(),
+        );
+        assert!(!cs.is_satisfied());
+        assert_eq!(2, cs.num_constraints());
+        assert!(cs
+            .which_is_unsatisfied()
+            .unwrap()
+            .starts_with("wrong: A = "));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a = AllocatedNum::alloc(cs.namespace(|| "a \"quoted\""), || {
+            Ok(crate::scalar_from_u64(3))
+        })
+        .unwrap();
+        a.square(cs.namespace(|| "square")).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&cs.to_json()).unwrap();
+        assert_eq!(cs.num_inputs(), json["inputs"].as_array().unwrap().len());
+        assert_eq!(cs.num_aux(), json["aux"].as_array().unwrap().len());
+        assert_eq!(
+            cs.num_constraints(),
+            json["constraints"].as_array().unwrap().len()
+        );
+
+        assert_eq!("a \"quoted\"", json["aux"][0]["name"]);
+        assert_eq!(
+            "0x0000000000000000000000000000000000000000000000000000000000000003",
+            json["aux"][0]["value"]
+        );
+        let constraint = &json["constraints"][0];
+        assert_eq!("square/squaring constraint", constraint["name"]);
+        assert_eq!("a \"quoted\"", constraint["a"][0]["variable"]);
+        assert_eq!("square/squared num", constraint["c"][0]["variable"]);
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a =
+            AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(crate::scalar_from_u64(3))).unwrap();
+        a.square(cs.namespace(|| "square")).unwrap();
+        assert_eq!(Fr::one(), cs.get("ONE"));
+        assert_eq!(
+            crate::scalar_from_u64::<Fr>(9),
+            cs.get("square/squared num")
+        );
+
+        cs.set("a", crate::scalar_from_u64(4));
+        assert!(!cs.is_satisfied());
+        cs.set("square/squared num", crate::scalar_from_u64(16));
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    #[should_panic(expected = "no variable exists at path: square")]
+    fn test_get_namespace() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(Fr::one())).unwrap();
+        a.square(cs.namespace(|| "square")).unwrap();
+        cs.get("square");
+    }
+
+    #[test]
+    fn test_shared_path_components() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        for (outer, inner, value) in [("a", "b", 1), ("b", "a", 2)].iter() {
+            let mut ns = cs.namespace(|| *outer);
+            ns.alloc(|| *inner, || Ok(crate::scalar_from_u64(*value)))
+                .unwrap();
+        }
+
+        assert_eq!(crate::scalar_from_u64::<Fr>(1), cs.get("a/b"));
+        assert_eq!(crate::scalar_from_u64::<Fr>(2), cs.get("b/a"));
+        let names = cs
+            .metrics_by_prefix(2)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["a/b", "b/a"], names);
+        assert!(cs.constraints_under("c").is_empty());
+    }
+
+    #[test]
+    fn test_all_aux_constrained() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a =
+            AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(crate::scalar_from_u64(3))).unwrap();
+        a.square(cs.namespace(|| "square")).unwrap();
+        cs.assert_all_aux_constrained();
+    }
+
+    #[test]
+    #[should_panic(expected = "unconstrained auxiliary variables: free")]
+    fn test_unconstrained_aux() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let a =
+            AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(crate::scalar_from_u64(3))).unwrap();
+        a.square(cs.namespace(|| "square")).unwrap();
+        cs.alloc(|| "free", || Ok(Fr::one())).unwrap();
+        cs.assert_all_aux_constrained();
+    }
+
+    #[test]
+    #[should_panic(expected = "tried to create object at existing path: a/b")]
+    fn test_duplicate_path() {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let mut ns = cs.namespace(|| "a");
+        ns.alloc(|| "b", || Ok(Fr::one())).unwrap();
+        ns.alloc(|| "b", || Ok(Fr::one())).unwrap();
+    }
+}
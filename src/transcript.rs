@@ -0,0 +1,115 @@
+use crate::poseidon::{Arity, PoseidonConstants};
+use crate::scalar_from_u64;
+use crate::sponge::Sponge;
+use ff::{Field, PrimeField, ScalarEngine};
+
+/// Operation codes bound into each label header, so an absorb can never be read as a challenge.
+pub(crate) const ABSORB: u64 = 1;
+pub(crate) const CHALLENGE: u64 = 2;
+
+/// Bytes of a label packed into each field element. 31 bytes stay below the field's capacity.
+const LABEL_BYTES_PER_ELEMENT: usize = 31;
+
+/// The constant elements binding an operation and its label into the transcript: a header holding the operation code
+/// and label length, then the label bytes packed little-endian. The header fixes how many elements follow, so every
+/// sequence of operations absorbs a distinct sequence of elements.
+pub(crate) fn label_elements<Fr: PrimeField>(op: u64, label: &[u8]) -> Vec<Fr> {
+    let mut elements = Vec::with_capacity(1 + label.len() / LABEL_BYTES_PER_ELEMENT + 1);
+    elements.push(scalar_from_u64::<Fr>(((label.len() as u64) << 8) | op));
+
+    let base = scalar_from_u64::<Fr>(256);
+    for chunk in label.chunks(LABEL_BYTES_PER_ELEMENT) {
+        let packed = chunk.iter().rev().fold(Fr::zero(), |mut acc, byte| {
+            acc.mul_assign(&base);
+            acc.add_assign(&scalar_from_u64::<Fr>(u64::from(*byte)));
+            acc
+        });
+        elements.push(packed);
+    }
+
+    elements
+}
+
+/// Fiat–Shamir transcript over the Poseidon sponge.
+///
+/// Every operation first absorbs its label, as constants from `label_elements`. `absorb` then absorbs the element
+/// itself, and `challenge` squeezes one element. Each challenge therefore depends on every label, element and
+/// challenge before it, in order. `circuit::TranscriptCircuit` computes the same challenges in a circuit.
+pub struct Transcript<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    sponge: Sponge<'a, E, A>,
+}
+
+impl<'a, E, A> Transcript<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        Transcript {
+            sponge: Sponge::new(constants),
+        }
+    }
+
+    /// Absorb `element` under `label`.
+    pub fn absorb(&mut self, label: &[u8], element: E::Fr) {
+        self.absorb_label(ABSORB, label);
+        self.sponge.absorb(element);
+    }
+
+    /// Squeeze a challenge under `label`.
+    pub fn challenge(&mut self, label: &[u8]) -> E::Fr {
+        self.absorb_label(CHALLENGE, label);
+        self.sponge.squeeze()
+    }
+
+    fn absorb_label(&mut self, op: u64, label: &[u8]) {
+        for element in label_elements::<E::Fr>(op, label) {
+            self.sponge.absorb(element);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generic_array::typenum;
+    use paired::bls12_381::{Bls12, Fr};
+
+    #[test]
+    fn test_label_elements() {
+        let elements = label_elements::<Fr>(ABSORB, b"ab");
+        assert_eq!(
+            vec![
+                scalar_from_u64::<Fr>((2 << 8) | ABSORB),
+                scalar_from_u64(0x6261)
+            ],
+            elements
+        );
+
+        assert_eq!(1, label_elements::<Fr>(CHALLENGE, b"").len());
+        assert_eq!(2, label_elements::<Fr>(CHALLENGE, &[7; 31]).len());
+        assert_eq!(3, label_elements::<Fr>(CHALLENGE, &[7; 32]).len());
+    }
+
+    #[test]
+    fn test_labels_affect_challenges() {
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let x = scalar_from_u64::<Fr>(42);
+
+        let challenge = |absorb_label: &[u8], challenge_label: &[u8]| {
+            let mut t = Transcript::new(&constants);
+            t.absorb(absorb_label, x);
+            t.challenge(challenge_label)
+        };
+
+        let c = challenge(b"x", b"c");
+        assert_eq!(c, challenge(b"x", b"c"));
+        assert_ne!(c, challenge(b"y", b"c"));
+        assert_ne!(c, challenge(b"x", b"d"));
+        assert_ne!(c, challenge(b"x", b"cc"));
+    }
+}
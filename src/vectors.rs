@@ -0,0 +1,278 @@
+use crate::poseidon::{Arity, HashMode, Poseidon, PoseidonConstants};
+use crate::{scalar_from_u64, Strength};
+use ff::{Field, PrimeField};
+use generic_array::typenum::{U11, U16, U2, U24, U36, U4, U8};
+use paired::bls12_381::{Bls12, Fr};
+use std::{error, fmt};
+
+/// The preimage a known vector hashes, of `arity` elements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preimage {
+    /// The elements 0, 1, ..., `arity - 1`.
+    Incrementing,
+    /// `arity` zeros.
+    Zero,
+}
+
+/// A preimage and the digest `parameters/known_vectors.py` generates for it.
+#[derive(Clone, Copy, Debug)]
+pub struct KnownVector {
+    pub arity: usize,
+    pub strength: Strength,
+    pub preimage: Preimage,
+    /// The digest as a big-endian hexadecimal integer.
+    pub digest: &'static str,
+}
+
+/// Digests generated by `parameters/known_vectors.py`, a Python model of the permutation written separately from this
+/// crate, with the parameters the crate uses:
+///
+/// - the BLS12-381 scalar field, and the x^5 S-box;
+/// - round constants from the Grain LFSR seeded with field 1 (prime), S-box 1 (x^5) and a 255-bit field size;
+/// - the Cauchy MDS matrix with entries 1 / (i + j + width), indexing rows and columns from zero;
+/// - eight full rounds, and partial rounds from `round_numbers` for the strength;
+/// - the `HashType::MerkleTree` domain tag, 2^arity - 1, in the first element of the state.
+///
+/// The script prints this table. Any change to these parameters
+/// changes every digest, and `verify_known_vectors` reports it.
+pub const KNOWN_VECTORS: &[KnownVector] = &[
+    vector(
+        2,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x396508d75e76a56b739e0fd902efe161a6fba9339d05a69d2e203c369a02e7ff",
+    ),
+    vector(
+        2,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x48fe0b1331196f6cdb33a7c6e5af61b76fd388e1ef1d3d418be5147f0e4613d4",
+    ),
+    vector(
+        2,
+        Strength::Strengthened,
+        Preimage::Incrementing,
+        "0x33d28a753baee41bc48b36ecc4cab7485278ecbf17040ea6793dbaf54552cd69",
+    ),
+    vector(
+        2,
+        Strength::Strengthened,
+        Preimage::Zero,
+        "0x3f2a571236323e5cd21942dc15c5afc355b74dd301cb19767a70faf35006b196",
+    ),
+    vector(
+        4,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x58a54b10a9e5848a00db3c6579229399fb6b4605bf1327ec019814ff6662075d",
+    ),
+    vector(
+        4,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x65cec475d81e7e5f0f13b878b866d3eef99a58932383621b64472e952b9711bc",
+    ),
+    vector(
+        4,
+        Strength::Strengthened,
+        Preimage::Incrementing,
+        "0x09d8207c51ca3f4354013bdaf68ba4c2e5113a254d6f5c7e4650ee190212aa9a",
+    ),
+    vector(
+        4,
+        Strength::Strengthened,
+        Preimage::Zero,
+        "0x654e11e8376752ea8caeb82b9de2b142e577cfc8d093b0658b607faeb568c9a0",
+    ),
+    vector(
+        8,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x2394611da3a5de5512010042116770774b682e9d9cc4aed92a9934f56d38a5e6",
+    ),
+    vector(
+        8,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x3f79e614b63889f904a036088bc029f5337c0be60db389d13482b431caff9b3a",
+    ),
+    vector(
+        8,
+        Strength::Strengthened,
+        Preimage::Incrementing,
+        "0x69e61465981ae17ed69aae8fe1cb63e8e843d4cfba662df19f0c3c93c3fc894e",
+    ),
+    vector(
+        8,
+        Strength::Strengthened,
+        Preimage::Zero,
+        "0x472706cd9aed9dc683bf43b65ba2f3c9ff35e8d7f090d6d7d4c778b7ecdfb884",
+    ),
+    vector(
+        11,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x0c0fc1b2e5227f286ca537e232ebe87a09f3dcd8ccb08fc1cee3bbc32b693163",
+    ),
+    vector(
+        11,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x441f2dea348692cf18215eb61da1681b69d57e70108853c985c8b7d1c14ffe94",
+    ),
+    vector(
+        16,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x2d3ae2663381ae8ac1c2fb5a6f871e635b8dbc6d30680a6f1291c74060266d37",
+    ),
+    vector(
+        16,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x01ec3c4c438301db66f7e418faa4b801117a24b6738d2cb732904cc3ba09bf03",
+    ),
+    vector(
+        24,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x63beb8831f11ae15066f39bf783f3d9fc3e779f6468815b1d7ef3569f585b321",
+    ),
+    vector(
+        24,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x2cd6c40223db11b93abff597db1128d2ae5ebf1eb315f018aff0409a6b9da4b9",
+    ),
+    vector(
+        36,
+        Strength::Standard,
+        Preimage::Incrementing,
+        "0x699303082a6e5d5f540a30e03c10bbaa75cd368df8a8ac3c4473606dfa4e8140",
+    ),
+    vector(
+        36,
+        Strength::Standard,
+        Preimage::Zero,
+        "0x024e89662fc8afea11c845985af75deaf0f29b0c34ba345ef60301621aa2e3a8",
+    ),
+];
+
+const fn vector(
+    arity: usize,
+    strength: Strength,
+    preimage: Preimage,
+    digest: &'static str,
+) -> KnownVector {
+    KnownVector {
+        arity,
+        strength,
+        preimage,
+        digest,
+    }
+}
+
+/// A known vector this crate hashes to a different digest.
+#[derive(Clone, Debug)]
+pub struct VectorMismatch {
+    pub vector: KnownVector,
+    pub mode: HashMode,
+    /// The digest computed, as a big-endian hexadecimal integer.
+    pub actual: String,
+}
+
+impl fmt::Display for VectorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "known vector mismatch at arity {} ({:?} strength, {:?} preimage, {:?} mode): expected {}, got {}",
+            self.vector.arity,
+            self.vector.strength,
+            self.vector.preimage,
+            self.mode,
+            self.vector.digest,
+            self.actual
+        )
+    }
+}
+
+impl error::Error for VectorMismatch {}
+
+/// Recompute every vector in `KNOWN_VECTORS`, in every `HashMode`, returning the first whose digest differs.
+pub fn verify_known_vectors() -> Result<(), VectorMismatch> {
+    KNOWN_VECTORS
+        .iter()
+        .try_for_each(|vector| match vector.arity {
+            2 => verify::<U2>(vector),
+            4 => verify::<U4>(vector),
+            8 => verify::<U8>(vector),
+            11 => verify::<U11>(vector),
+            16 => verify::<U16>(vector),
+            24 => verify::<U24>(vector),
+            36 => verify::<U36>(vector),
+            arity => unreachable!("no known vectors at arity {}", arity),
+        })
+}
+
+fn verify<A: Arity<Fr>>(vector: &KnownVector) -> Result<(), VectorMismatch> {
+    let constants = PoseidonConstants::<Bls12, A>::new_with_strength(vector.strength);
+    let preimage = (0..A::to_usize())
+        .map(|i| match vector.preimage {
+            Preimage::Incrementing => scalar_from_u64(i as u64),
+            Preimage::Zero => Fr::zero(),
+        })
+        .collect::<Vec<_>>();
+    let p = Poseidon::new_with_preimage(&preimage, &constants);
+
+    for mode in &[
+        HashMode::Correct,
+        HashMode::OptimizedDynamic,
+        HashMode::OptimizedStatic,
+    ] {
+        let actual = to_hex(&p.hash_copy_in_mode(*mode));
+        if actual != vector.digest {
+            return Err(VectorMismatch {
+                vector: *vector,
+                mode: *mode,
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// `digest` as a big-endian hexadecimal integer, as `KnownVector` records it.
+fn to_hex(digest: &Fr) -> String {
+    let limbs = digest.into_repr();
+    let hex = limbs
+        .as_ref()
+        .iter()
+        .rev()
+        .map(|limb| format!("{:016x}", limb))
+        .collect::<String>();
+
+    format!("0x{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_vectors() {
+        if let Err(mismatch) = verify_known_vectors() {
+            panic!("{}", mismatch);
+        }
+    }
+
+    #[test]
+    fn test_mismatch_is_reported() {
+        let mut vector = KNOWN_VECTORS[0];
+        vector.preimage = Preimage::Zero;
+
+        let mismatch = verify::<U2>(&vector).unwrap_err();
+        assert_eq!(HashMode::Correct, mismatch.mode);
+        assert_eq!(KNOWN_VECTORS[1].digest, mismatch.actual);
+    }
+}
@@ -0,0 +1,90 @@
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use ff::{Field, ScalarEngine};
+
+/// Constraint system which only records the assignment. Constraints are dropped without evaluating their linear
+/// combinations, and annotations and namespaces are never built, so synthesizing into it costs little more than
+/// computing the witness. Use it at proving time, when the constraints are already known from parameter generation.
+///
+/// Every allocation must be assigned: a missing value is an error.
+pub struct WitnessCS<E: ScalarEngine> {
+    input_assignment: Vec<E::Fr>,
+    aux_assignment: Vec<E::Fr>,
+}
+
+impl<E: ScalarEngine> Default for WitnessCS<E> {
+    fn default() -> Self {
+        WitnessCS {
+            input_assignment: vec![E::Fr::one()],
+            aux_assignment: vec![],
+        }
+    }
+}
+
+impl<E: ScalarEngine> WitnessCS<E> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Public input values in allocation order, starting with the constant one input.
+    pub fn input_assignment(&self) -> &[E::Fr] {
+        &self.input_assignment
+    }
+
+    /// Auxiliary (private witness) values in allocation order.
+    pub fn aux_assignment(&self) -> &[E::Fr] {
+        &self.aux_assignment
+    }
+}
+
+impl<E: ScalarEngine> ConstraintSystem<E> for WitnessCS<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux_assignment.push(f()?);
+
+        Ok(Variable::new_unchecked(Index::Aux(
+            self.aux_assignment.len() - 1,
+        )))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.input_assignment.push(f()?);
+
+        Ok(Variable::new_unchecked(Index::Input(
+            self.input_assignment.len() - 1,
+        )))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _annotation: A, _a: LA, _b: LB, _c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+    }
+
+    fn push_namespace<NR, N>(&mut self, _name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
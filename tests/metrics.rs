@@ -0,0 +1,85 @@
+//! The counters are process-wide, so this is the only test in its binary: no other hashing can land in the counts.
+#![cfg(feature = "metrics")]
+
+use generic_array::typenum::U4;
+use neptune::metrics::{self, Snapshot};
+use neptune::poseidon::{PoseidonConstants, POSEIDON_CONSTANTS_4};
+use neptune::sponge::Sponge;
+use neptune::{scalar_from_u64, Poseidon};
+use paired::bls12_381::{Bls12, Fr};
+use std::thread;
+
+#[test]
+fn test_metrics() {
+    metrics::reset();
+    assert_eq!(Snapshot::default(), metrics::snapshot());
+
+    let threads = (0..8)
+        .map(|t| {
+            thread::spawn(move || {
+                for i in 0..500 {
+                    let preimage = (0..4)
+                        .map(|j| scalar_from_u64::<Fr>(t * 10_000 + i * 4 + j))
+                        .collect::<Vec<_>>();
+                    Poseidon::new_with_preimage(&preimage, &*POSEIDON_CONSTANTS_4).hash();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in threads {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(
+        Snapshot {
+            hashes: 4000,
+            elements: 16000,
+            batches: 0,
+            batched_hashes: 0,
+        },
+        metrics::snapshot()
+    );
+
+    // A short preimage counts only the elements input.
+    let constants = PoseidonConstants::<Bls12, U4>::new();
+    let mut p = Poseidon::new(&constants);
+    p.input(scalar_from_u64(1)).unwrap();
+    p.hash();
+    assert_eq!(4001, metrics::snapshot().hashes);
+    assert_eq!(16001, metrics::snapshot().elements);
+
+    // Every entry point counts, not only `hash`: the reference mode, reading a slice, each permutation of a squeeze,
+    // and a sponge's permutations and absorbed elements.
+    let preimage = (0..4).map(scalar_from_u64::<Fr>).collect::<Vec<_>>();
+    Poseidon::new_with_preimage(&preimage, &constants).hash_correct();
+    Poseidon::new(&constants).hash_slice(&preimage);
+    Poseidon::new_with_preimage(&preimage, &constants).hash_squeeze(8);
+    assert_eq!(4005, metrics::snapshot().hashes);
+    assert_eq!(16013, metrics::snapshot().elements);
+
+    let mut sponge = Sponge::new(&constants);
+    for i in 0..5 {
+        sponge.absorb(scalar_from_u64(i));
+    }
+    sponge.squeeze();
+    assert_eq!(4007, metrics::snapshot().hashes);
+    assert_eq!(16018, metrics::snapshot().elements);
+
+    #[cfg(feature = "parallel")]
+    {
+        use generic_array::GenericArray;
+
+        let preimages = (0..1000)
+            .map(|i| GenericArray::<Fr, U4>::clone_from_slice(&[scalar_from_u64(i); 4]))
+            .collect::<Vec<_>>();
+        neptune::parallel::hash_many(&constants, &preimages);
+
+        let snapshot = metrics::snapshot();
+        assert_eq!(5007, snapshot.hashes);
+        assert_eq!(1, snapshot.batches);
+        assert_eq!(1000, snapshot.batched_hashes);
+    }
+
+    metrics::reset();
+    assert_eq!(Snapshot::default(), metrics::snapshot());
+}